@@ -0,0 +1,163 @@
+use crate::logging::{log_to_file, Level};
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// API キーの保存先
+pub fn api_key_path(app_root: &Path) -> PathBuf {
+    app_root.join("data").join("api_key")
+}
+
+/// API キーを指定する環境変数（設定されていれば data/api_key より優先）
+pub const API_KEY_ENV: &str = "DENCHO_API_KEY";
+
+/// DENCHO_API_KEY の値（未設定・空なら None）
+pub fn api_key_from_env() -> Option<String> {
+    std::env::var(API_KEY_ENV)
+        .ok()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+}
+
+/// DENCHO_API_KEY > 保存済みの API キー（無ければ生成して保存）
+pub fn load_or_create_api_key(app_root: &Path) -> Result<String, String> {
+    match api_key_from_env() {
+        Some(key) => Ok(key),
+        None => load_or_create_stored_key(&api_key_path(app_root)),
+    }
+}
+
+/// path に保存済みの API キー（無ければ生成して保存）
+fn load_or_create_stored_key(path: &Path) -> Result<String, String> {
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        let key = existing.trim();
+        if !key.is_empty() {
+            return Ok(key.to_string());
+        }
+    }
+
+    let key = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("API キー保存先の作成失敗: {}", e))?;
+    }
+    std::fs::write(path, &key)
+        .map_err(|e| format!("API キー保存失敗: {} ({})", path.display(), e))?;
+    log_to_file(
+        Level::Info,
+        &format!("API キーを生成しました: {}", path.display()),
+    );
+    Ok(key)
+}
+
+/// X-Api-Key または Authorization: Bearer から提示されたキーを取り出す
+fn presented_key(request: &Request) -> Option<&str> {
+    let headers = request.headers();
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// タイミング差で推測されないよう全バイトを比較する
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// /api/* に API キーを要求するミドルウェア
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = presented_key(&request)
+        .is_some_and(|key| constant_time_eq(key.as_bytes(), state.api_key.as_bytes()));
+
+    if !authorized {
+        log_to_file(
+            Level::Warn,
+            &format!("認証エラー: {} {}", request.method(), request.uri().path()),
+        );
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "API キーが必要です (X-Api-Key または Authorization: Bearer)",
+            })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = Request::builder().uri("/api/status");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn stored_key_is_generated_once_and_reused() {
+        let dir = TempDir::new();
+        let path = api_key_path(dir.path());
+        let key = load_or_create_stored_key(&path).unwrap();
+        assert_eq!(key.len(), 64);
+        assert_eq!(load_or_create_stored_key(&path).unwrap(), key);
+
+        std::fs::write(&path, "  edited-key\n").unwrap();
+        assert_eq!(load_or_create_stored_key(&path).unwrap(), "edited-key");
+
+        // 空のファイルは作り直す
+        std::fs::write(&path, "\n").unwrap();
+        assert_ne!(load_or_create_stored_key(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn key_is_read_from_either_header() {
+        assert_eq!(
+            presented_key(&request(&[("X-Api-Key", " k1 ")])),
+            Some("k1")
+        );
+        assert_eq!(
+            presented_key(&request(&[("Authorization", "Bearer k2")])),
+            Some("k2")
+        );
+        assert_eq!(
+            presented_key(&request(&[("Authorization", "Basic azI6")])),
+            None
+        );
+        assert_eq!(presented_key(&request(&[])), None);
+    }
+
+    #[test]
+    fn keys_are_compared_exactly() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret-longer"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// 時刻の取得と待機
+///
+/// 時間に依存する処理（ジョブ保持期間、再送バックオフ、環境エラーのキャッシュ、
+/// タイムアウト）はすべてこれを経由し、実時間に直接触れない。
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// deadline まで待つ（過ぎていれば即座に戻る）
+    fn sleep_until(&self, deadline: DateTime<Utc>)
+        -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+impl dyn Clock {
+    pub fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        self.sleep_until(self.now() + duration)
+    }
+
+    /// period ごとに発火するタイマー（最初の tick は即座に戻る）
+    pub fn interval(&self, period: Duration) -> Interval<'_> {
+        Interval {
+            clock: self,
+            period,
+            next: self.now(),
+        }
+    }
+}
+
+/// Clock 上の周期タイマー
+pub struct Interval<'a> {
+    clock: &'a dyn Clock,
+    period: Duration,
+    next: DateTime<Utc>,
+}
+
+impl Interval<'_> {
+    pub async fn tick(&mut self) {
+        self.clock.sleep_until(self.next).await;
+        // 遅れた場合は取りこぼした tick をまとめず、今から period 後にする
+        let now = self.clock.now();
+        self.next += self.period;
+        if self.next <= now {
+            self.next = now + self.period;
+        }
+    }
+}
+
+/// 実時間の Clock（tokio のタイマーで待つ）
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep_until(
+        &self,
+        deadline: DateTime<Utc>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let remaining = (deadline - Utc::now()).to_std().unwrap_or_default();
+        Box::pin(tokio::time::sleep(remaining))
+    }
+}
+
+/// 手動で進める Clock（テスト用）
+///
+/// sleep_until は advance で deadline を過ぎるまで戻らないため、実時間を待たずに
+/// スケジューラーやバックオフの待ち時間を確かめられる。
+#[cfg(test)]
+pub struct TestClock {
+    now: tokio::sync::watch::Sender<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl TestClock {
+    pub fn new(start: DateTime<Utc>) -> std::sync::Arc<Self> {
+        std::sync::Arc::new(Self {
+            now: tokio::sync::watch::Sender::new(start),
+        })
+    }
+
+    /// 時刻を進め、deadline を過ぎた sleep_until を起こす
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    fn sleep_until(
+        &self,
+        deadline: DateTime<Utc>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let mut now = self.now.subscribe();
+        Box::pin(async move {
+            let _ = now.wait_for(|now| *now >= deadline).await;
+        })
+    }
+}
+
+/// 1 回だけ poll して、まだ終わっていないか（TestClock で待ちを確かめる用）
+#[cfg(test)]
+pub fn is_pending<F: Future + ?Sized>(future: Pin<&mut F>) -> bool {
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    future.poll(&mut context).is_pending()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn start() -> DateTime<Utc> {
+        "2024-01-31T00:00:00Z".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sleep_returns_only_after_advance() {
+        let test_clock = TestClock::new(start());
+        let clock: Arc<dyn Clock> = test_clock.clone();
+        let mut sleep = clock.sleep(Duration::from_secs(60));
+
+        test_clock.advance(Duration::from_secs(59));
+        assert!(is_pending(sleep.as_mut()));
+
+        test_clock.advance(Duration::from_secs(1));
+        sleep.await;
+        assert_eq!(clock.now(), start() + Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn interval_does_not_replay_missed_ticks() {
+        let test_clock = TestClock::new(start());
+        let clock: Arc<dyn Clock> = test_clock.clone();
+        let mut interval = clock.interval(Duration::from_secs(30));
+        interval.tick().await;
+
+        // 3 周期分遅れても、すぐに戻る tick は 1 回だけで、次はそこから 30 秒後
+        test_clock.advance(Duration::from_secs(100));
+        interval.tick().await;
+        {
+            let mut tick = std::pin::pin!(interval.tick());
+            assert!(is_pending(tick.as_mut()));
+            test_clock.advance(Duration::from_secs(30));
+            tick.await;
+        }
+    }
+}
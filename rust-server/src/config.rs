@@ -0,0 +1,865 @@
+use crate::logging::Level;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// 設定ファイル名（アプリケーションルート直下）
+pub const CONFIG_FILE_NAME: &str = "dencho.toml";
+
+/// 上書き用環境変数の接頭辞
+const ENV_OVERRIDE_PREFIX: &str = "DENCHO_";
+
+/// 上書き用環境変数のセクション・キーの区切り
+const ENV_OVERRIDE_SEPARATOR: &str = "__";
+
+/// dencho.toml の内容
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub output: OutputConfig,
+    pub log: LogConfig,
+    pub outbox: OutboxConfig,
+    pub security: SecurityConfig,
+    pub cors: CorsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub invoices: InvoicesConfig,
+    pub download: DownloadConfig,
+    pub history: HistoryConfig,
+    /// 追加のダウンロードスクリプト（名前 → アプリケーションルートからの相対パス、または表示名付きのテーブル）
+    pub providers: HashMap<String, ProviderConfig>,
+    /// 定期実行するダウンロード（[[schedule]]）
+    pub schedule: Vec<ScheduleEntry>,
+    /// ダウンロード終了の通知先（[[webhooks]]）
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+/// [providers] の 1 項目
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ProviderConfig {
+    /// `freee = "dist/download-freee-invoice.js"`
+    Script(PathBuf),
+    /// `freee = { script = "...", display_name = "freee" }`
+    Table(ProviderTable),
+}
+
+/// [providers] のテーブル形式
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProviderTable {
+    pub script: PathBuf,
+    /// 完了メッセージ等に使う名前（省略時はプロバイダー名）
+    pub display_name: Option<String>,
+}
+
+// untagged で導出すると内容がバッファされ、テーブル内の未知のキーを警告できないため手で実装する
+impl<'de> Deserialize<'de> for ProviderConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = ProviderConfig;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("スクリプトのパス、または { script = \"...\", display_name = \"...\" }")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(ProviderConfig::Script(PathBuf::from(value)))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                ProviderTable::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(ProviderConfig::Table)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl ProviderConfig {
+    pub fn script(&self) -> &Path {
+        match self {
+            Self::Script(script) => script,
+            Self::Table(table) => &table.script,
+        }
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        match self {
+            Self::Script(_) => None,
+            Self::Table(table) => table.display_name.as_deref(),
+        }
+    }
+}
+
+/// [server] セクション
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    /// true の場合、tls_cert / tls_key が無ければ certs/ の自己署名証明書で HTTPS にする
+    pub tls: bool,
+    /// HTTPS 用の証明書（PEM、DENCHO_TLS_CERT 環境変数が優先）
+    pub tls_cert: Option<PathBuf>,
+    /// HTTPS 用の秘密鍵（PEM、DENCHO_TLS_KEY 環境変数が優先）
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            port: 3939,
+            tls: false,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// bind と port を連結した待ち受けアドレス文字列
+    pub fn listen_addr(&self) -> String {
+        // IPv6 アドレスは角括弧で囲む
+        if self.bind.contains(':') && !self.bind.starts_with('[') {
+            format!("[{}]:{}", self.bind, self.port)
+        } else {
+            format!("{}:{}", self.bind, self.port)
+        }
+    }
+}
+
+/// [log] セクション
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// server.log をローテーションするサイズ（MB、0 で無効）
+    pub max_size_mb: u64,
+    /// 保持するローテーション済みファイル数（server.log.1 〜 server.log.N）
+    pub max_files: u32,
+    /// 出力する最小ログレベル（DENCHO_LOG_LEVEL 環境変数が優先）
+    pub level: Level,
+    /// 1 リクエスト 1 行のアクセスログ（logs/access.log）を出力するか
+    pub access: bool,
+    /// アクセスログに出さないパス（完全一致、例: "/health"）
+    pub access_exclude: Vec<String>,
+    /// GET /api/logs で読み込む・返す大きさの上限（バイト）
+    pub api_max_bytes: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 10,
+            max_files: 5,
+            level: Level::Info,
+            access: true,
+            access_exclude: Vec::new(),
+            api_max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// [outbox] セクション: 配信失敗時の再送
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutboxConfig {
+    /// 初回再送までの秒数（以降は倍々）
+    pub base_delay_secs: u64,
+    /// 再送間隔の上限（秒）
+    pub max_delay_secs: u64,
+    /// この時間を過ぎたら自動再送を諦める（時間）
+    pub max_age_hours: u64,
+    /// 再送を諦めたエントリを、登録からこの時間を過ぎたら破棄する（時間）
+    pub discard_after_hours: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            base_delay_secs: 60,
+            max_delay_secs: 3600,
+            max_age_hours: 72,
+            discard_after_hours: 7 * 24,
+        }
+    }
+}
+
+/// [security] セクション
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// true の場合、安全でない設定があれば起動を拒否する
+    pub strict: bool,
+}
+
+/// [cors] セクション
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// 許可するオリジン（例: "http://localhost:5173", "app://dencho"）
+    pub allowed_origins: Vec<String>,
+    /// true の場合、すべてのオリジンを許可する（allowed_origins は無視）
+    pub allow_any: bool,
+}
+
+/// [download] セクション
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// true の場合、実行中と異なるパラメータの POST /api/download は 409 にせず順番待ちにする
+    pub queue: bool,
+    /// スクリプトの実行時間の上限（秒、DENCHO_DOWNLOAD_TIMEOUT 環境変数が優先）
+    pub timeout_secs: u64,
+    /// ダウンロードスクリプト（アプリケーションルートからの相対パス、DENCHO_SCRIPT_PATH 環境変数が優先）
+    pub script: PathBuf,
+    /// true の場合、GitHub 認証情報を標準入力ではなく環境変数で渡す（旧スクリプト用の移行措置）
+    pub legacy_env: bool,
+    /// スクリプトが使う Playwright ブラウザ（DENCHO_PLAYWRIGHT_BROWSER 環境変数が優先）
+    pub browser: PlaywrightBrowser,
+    /// 一時的なエラー（タイムアウト・接続リセット等）で失敗したときの再試行回数
+    /// （0 で再試行しない、DENCHO_DOWNLOAD_RETRIES 環境変数が優先）
+    pub retries: u32,
+    /// 1 回目の再試行までの秒数（以降は倍々、DENCHO_DOWNLOAD_RETRY_BASE_SECS 環境変数が優先）
+    pub retry_base_secs: u64,
+    /// 必要な Node.js のメジャーバージョン（環境セットアップで確認する）
+    pub min_node_version: u32,
+    /// POST /api/download の outputDir で指定できるフォルダの基準
+    /// （アプリケーションルートからの相対パス、絶対パスも可）
+    pub output_base: PathBuf,
+    /// 保存先の空き容量がこれ未満（MB）ならダウンロードを始めない（0 で確認しない）
+    pub min_free_mb: u64,
+    /// 最後の成功からこの日数を超えたプロバイダーがあれば /health・GET /api/status の freshness を warn にする
+    /// （0 で判定しない）
+    pub stale_after_days: u32,
+    /// 環境セットアップの npm install で使うレジストリ（DENCHO_NPM_REGISTRY 環境変数が優先）
+    pub npm_registry: Option<String>,
+    /// Playwright ブラウザのダウンロード元（PLAYWRIGHT_DOWNLOAD_HOST 環境変数が優先）
+    pub playwright_download_host: Option<String>,
+    /// npm・npx・スクリプトが使う HTTP / HTTPS プロキシ
+    /// （DENCHO_PROXY 環境変数が優先、どちらも無ければ HTTPS_PROXY / HTTP_PROXY）
+    pub proxy: Option<String>,
+    /// true の場合、環境セットアップで npm install・Playwright ブラウザのダウンロードを行わず、
+    /// 事前に配置されているか確認するだけにする（DENCHO_OFFLINE 環境変数が優先）
+    pub offline: bool,
+}
+
+/// Playwright のブラウザ（インストールとスクリプトの起動に使う）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaywrightBrowser {
+    #[default]
+    Chromium,
+    Firefox,
+    Webkit,
+}
+
+impl PlaywrightBrowser {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaywrightBrowser::Chromium => "chromium",
+            PlaywrightBrowser::Firefox => "firefox",
+            PlaywrightBrowser::Webkit => "webkit",
+        }
+    }
+}
+
+impl std::str::FromStr for PlaywrightBrowser {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "chromium" => Ok(PlaywrightBrowser::Chromium),
+            "firefox" => Ok(PlaywrightBrowser::Firefox),
+            "webkit" => Ok(PlaywrightBrowser::Webkit),
+            _ => Err(format!(
+                "未対応のブラウザです: {} (chromium / firefox / webkit)",
+                value
+            )),
+        }
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            queue: false,
+            timeout_secs: 10 * 60,
+            script: PathBuf::from("dist").join("download-supabase-invoice.js"),
+            legacy_env: false,
+            browser: PlaywrightBrowser::default(),
+            retries: 2,
+            retry_base_secs: 5,
+            min_node_version: 18,
+            output_base: PathBuf::from("downloads"),
+            min_free_mb: 500,
+            stale_after_days: 35,
+            npm_registry: None,
+            playwright_download_host: None,
+            proxy: None,
+            offline: false,
+        }
+    }
+}
+
+/// [history] セクション: ジョブ履歴（data/history.db）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// true の場合だけ履歴データベースを開いて記録する（変更は再起動後に反映）
+    pub enabled: bool,
+    /// 履歴に書き込むリクエストの任意項目（含めない項目は保存せず、API では "[not stored]" になる）
+    ///
+    /// 変更は以降に登録するジョブにだけ反映する（保存済みの履歴は書き換えない）。
+    pub persist_fields: Vec<HistoryField>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            persist_fields: vec![
+                HistoryField::ClientId,
+                HistoryField::Note,
+                HistoryField::RequesterIp,
+                HistoryField::CredentialSet,
+            ],
+        }
+    }
+}
+
+/// history.persist_fields に指定できる項目
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryField {
+    /// POST /api/download の clientId
+    ClientId,
+    /// POST /api/download の note
+    Note,
+    /// リクエストの送信元 IP アドレス（スケジュール実行では schedule:<名前>）
+    RequesterIp,
+    /// 使った認証情報（request / 保存済みの認証情報のターゲット名）
+    CredentialSet,
+}
+
+impl HistoryField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryField::ClientId => "clientId",
+            HistoryField::Note => "note",
+            HistoryField::RequesterIp => "requesterIp",
+            HistoryField::CredentialSet => "credentialSet",
+        }
+    }
+}
+
+/// [invoices] セクション: GET /api/invoices で一覧するフォルダと保持ルール
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InvoicesConfig {
+    /// アプリケーションルートからの相対パス（絶対パスも可）
+    pub dir: PathBuf,
+    /// 更新からこの日数を過ぎたファイルを削除する
+    pub keep_days: Option<u32>,
+    /// 新しい順にこの件数を超えたファイルを削除する
+    pub keep_count: Option<u32>,
+}
+
+impl Default for InvoicesConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("downloads").join("invoice"),
+            keep_days: None,
+            keep_count: None,
+        }
+    }
+}
+
+/// [rate_limit] セクション: プロファイル（GitHub アカウント）ごとの実行回数制限
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// profiles に無いアカウント（認証情報なしを含む）の実行回数（0 で無制限）
+    pub max_downloads: u32,
+    pub window_minutes: u32,
+    /// [rate_limit.profiles."<githubUsername>"] ごとの制限
+    pub profiles: HashMap<String, RateLimitRule>,
+    /// [rate_limit.client] ダウンロード要求の回数制限（送信元ごと）
+    pub client: ClientRateLimit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let rule = RateLimitRule::default();
+        Self {
+            max_downloads: rule.max_downloads,
+            window_minutes: rule.window_minutes,
+            profiles: HashMap::new(),
+            client: ClientRateLimit::default(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// プロファイルに適用する制限
+    pub fn rule(&self, profile: &str) -> RateLimitRule {
+        self.profiles
+            .get(profile)
+            .copied()
+            .unwrap_or(RateLimitRule {
+                max_downloads: self.max_downloads,
+                window_minutes: self.window_minutes,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitRule {
+    /// window_minutes 分間に実行できる回数（0 で無制限）
+    pub max_downloads: u32,
+    pub window_minutes: u32,
+}
+
+impl Default for RateLimitRule {
+    fn default() -> Self {
+        Self {
+            max_downloads: 0,
+            window_minutes: 60,
+        }
+    }
+}
+
+/// [rate_limit.client]: POST /api/download・GET /api/download/stream の要求回数制限
+///
+/// 実行中・一時停止中などで断られた要求も数える（フロントエンドの再試行ループ対策）。
+/// ループバックで待ち受けている場合は全体で 1 つ、それ以外は送信元 IP ごとに数える。
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClientRateLimit {
+    /// window_minutes 分間に受け付ける要求数（0 で無制限）
+    pub max_requests: u32,
+    pub window_minutes: u32,
+}
+
+impl Default for ClientRateLimit {
+    fn default() -> Self {
+        Self {
+            max_requests: 5,
+            window_minutes: 10,
+        }
+    }
+}
+
+/// [[schedule]]: cron 式で定期実行するダウンロード（認証情報は保存済みのもの・環境変数を使う）
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScheduleEntry {
+    /// ログ・履歴に出す名前（省略時は cron 式）
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 「分 時 日 月 曜日」の cron 式（ローカル時刻）
+    pub cron: String,
+    /// 実行するプロバイダー（省略時は supabase）
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+/// [[webhooks]]: ダウンロード終了を POST で通知する先
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// 指定した場合、本文の HMAC-SHA256 を X-Dencho-Signature-256 ヘッダーに付ける
+    pub secret: Option<String>,
+}
+
+/// [output] セクション: ダウンロード成功後のファイル出力先
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub sink: SinkKind,
+    pub s3: Option<S3Config>,
+    pub azure: Option<AzureConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// ローカルに保存したまま（従来動作）
+    #[default]
+    Local,
+    S3,
+    Azure,
+}
+
+/// [output.s3] セクション（認証情報は AWS_ACCESS_KEY_ID / AWS_SECRET_ACCESS_KEY 環境変数）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    #[serde(default)]
+    pub prefix: String,
+    /// S3 互換ストレージのエンドポイント（指定時はパス形式でアクセス）
+    pub endpoint: Option<String>,
+}
+
+/// [output.azure] セクション（SAS トークンは AZURE_STORAGE_SAS_TOKEN 環境変数）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AzureConfig {
+    /// 例: https://account.blob.core.windows.net/invoices
+    pub container_url: String,
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// 設定ファイルのパス
+pub fn config_path(app_root: &Path) -> PathBuf {
+    app_root.join(CONFIG_FILE_NAME)
+}
+
+/// 設定を読み込む（デフォルト値 < dencho.toml < DENCHO_<セクション>__<キー> 環境変数）
+///
+/// 個別の環境変数（DENCHO_LISTEN_ADDR など）と CLI 引数は、呼び出し側でさらに優先して反映する。
+/// 未知のキーはエラーにせず WARN でログに残す。
+pub fn load_config(app_root: &Path) -> Result<Config, String> {
+    let path = config_path(app_root);
+    let content = if path.exists() {
+        Some(
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("設定ファイル読み込み失敗: {} ({})", path.display(), e))?,
+        )
+    } else {
+        None
+    };
+
+    let (config, unknown_keys) = parse_config(&path, content.as_deref(), &env_overrides())?;
+    for key in unknown_keys {
+        crate::logging::log_to_file(
+            Level::Warn,
+            &format!("設定に未知のキーがあるため無視します: {}", key),
+        );
+    }
+    Ok(config)
+}
+
+/// dencho.toml の内容と上書き用環境変数から設定を作る（未知のキーはドット区切りで返す）
+pub fn parse_config(
+    path: &Path,
+    content: Option<&str>,
+    overrides: &[(String, String)],
+) -> Result<(Config, Vec<String>), String> {
+    let mut table = match content {
+        Some(content) => toml::from_str::<toml::Table>(content)
+            .map_err(|e| format!("設定ファイルの形式が不正です: {}\n{}", path.display(), e))?,
+        None => toml::Table::new(),
+    };
+
+    for (name, value) in overrides {
+        apply_env_override(&mut table, name, value)?;
+    }
+
+    let mut unknown_keys = Vec::new();
+    let config = serde_ignored::deserialize(toml::Value::Table(table), |key| {
+        unknown_keys.push(key.to_string())
+    })
+    .map_err(|e: toml::de::Error| {
+        if overrides.is_empty() {
+            format!("設定ファイルの形式が不正です: {}\n{}", path.display(), e)
+        } else {
+            format!(
+                "設定の形式が不正です: {} / 環境変数 {}\n{}",
+                path.display(),
+                overrides
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                e
+            )
+        }
+    })?;
+    Ok((config, unknown_keys))
+}
+
+/// dencho.toml の値を上書きする環境変数（名前と値、名前順）
+///
+/// DENCHO_SERVER__PORT=4000 → [server] port = 4000 のように、`__` でセクションとキーを区切る。
+pub fn env_overrides() -> Vec<(String, String)> {
+    let mut overrides: Vec<_> = std::env::vars()
+        .filter(|(name, _)| {
+            name.strip_prefix(ENV_OVERRIDE_PREFIX)
+                .is_some_and(|rest| rest.contains(ENV_OVERRIDE_SEPARATOR))
+        })
+        .collect();
+    overrides.sort();
+    overrides
+}
+
+fn apply_env_override(table: &mut toml::Table, name: &str, value: &str) -> Result<(), String> {
+    let keys: Vec<String> = name[ENV_OVERRIDE_PREFIX.len()..]
+        .split(ENV_OVERRIDE_SEPARATOR)
+        .map(|key| key.to_ascii_lowercase())
+        .collect();
+    let Some((last, sections)) = keys
+        .split_last()
+        .filter(|_| keys.iter().all(|k| !k.is_empty()))
+    else {
+        return Err(format!("環境変数名が不正です: {}", name));
+    };
+
+    let mut current = table;
+    for section in sections {
+        current = match current
+            .entry(section.as_str())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            toml::Value::Table(table) => table,
+            _ => return Err(format!("{}: {} はセクションではありません", name, section)),
+        };
+    }
+    current.insert(last.clone(), parse_env_value(value));
+    Ok(())
+}
+
+/// 値を TOML として解釈する（4000・true・["a", "b"] など）。解釈できなければ文字列
+fn parse_env_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// 再起動せずに反映できる設定（POST /api/config/reload、`.` で終わるものはセクション全体）
+const HOT_RELOAD_KEYS: &[&str] = &[
+    "log.level",
+    "log.access",
+    "log.access_exclude",
+    "cors.",
+    "rate_limit.",
+    "download.timeout_secs",
+    "download.retries",
+    "download.retry_base_secs",
+    "download.queue",
+    "download.min_free_mb",
+    "download.stale_after_days",
+    "history.persist_fields",
+    "schedule",
+    "invoices.keep_days",
+    "invoices.keep_count",
+];
+
+/// 設定の変更点（キーは server.port のようなドット区切り、値は TOML 表記）
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// key の変更を再起動せずに反映できるか
+pub fn is_hot_reloadable(key: &str) -> bool {
+    HOT_RELOAD_KEYS
+        .iter()
+        .any(|hot| match hot.strip_suffix('.') {
+            Some(section) => key.starts_with(hot) || key == section,
+            None => key == *hot,
+        })
+}
+
+/// 2 つの設定で値が異なるキー（キー順、表示する値は masked と同じく伏せる）
+pub fn diff(old: &Config, new: &Config) -> Vec<ConfigChange> {
+    let (old_values, new_values) = (flatten(old), flatten(new));
+    let (old_shown, new_shown) = (flatten(&masked(old)), flatten(&masked(new)));
+    let keys: std::collections::BTreeSet<&String> =
+        old_values.keys().chain(new_values.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old_values.get(*key) != new_values.get(*key))
+        .map(|key| ConfigChange {
+            key: key.clone(),
+            old: old_shown.get(key).cloned(),
+            new: new_shown.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// ログ・レスポンス用に、Webhook の URL（トークンを含むことが多い）と secret を伏せる
+fn masked(config: &Config) -> Config {
+    let mut config = config.clone();
+    for webhook in &mut config.webhooks {
+        webhook.url = format!("{}/***", crate::webhook::host(&webhook.url));
+        if webhook.secret.is_some() {
+            webhook.secret = Some("***".to_string());
+        }
+    }
+    config
+}
+
+/// 設定をドット区切りのキーと TOML 表記の値に展開する（配列は 1 つの値として扱う）
+fn flatten(config: &Config) -> BTreeMap<String, String> {
+    fn walk(prefix: &str, value: &toml::Value, out: &mut BTreeMap<String, String>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let key = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    };
+                    walk(&key, value, out);
+                }
+            }
+            value => {
+                out.insert(prefix.to_string(), value.to_string());
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    if let Ok(value) = toml::Value::try_from(config) {
+        walk("", &value, &mut out);
+    }
+    out
+}
+
+/// 起動ログ用に、読み込んだ設定を TOML 形式で出力する
+pub fn describe(config: &Config) -> String {
+    toml::to_string(&masked(config)).unwrap_or_else(|e| format!("(設定を表示できません: {})", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+[server]
+port = 4000
+
+[download]
+timeout_secs = 120
+retries = 5
+"#;
+
+    fn parse(content: Option<&str>, overrides: &[(&str, &str)]) -> (Config, Vec<String>) {
+        let overrides: Vec<_> = overrides
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        parse_config(Path::new(CONFIG_FILE_NAME), content, &overrides).unwrap()
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let (config, unknown) = parse(Some(TOML), &[]);
+        assert!(unknown.is_empty());
+        assert_eq!(config.server.port, 4000);
+        assert_eq!(config.download.timeout_secs, 120);
+        assert_eq!(config.download.retries, 5);
+        // ファイルに無いキーはデフォルト値のまま
+        assert_eq!(config.server.bind, ServerConfig::default().bind);
+        assert_eq!(
+            config.download.retry_base_secs,
+            DownloadConfig::default().retry_base_secs
+        );
+
+        let (defaults, _) = parse(None, &[]);
+        assert_eq!(defaults.server.port, ServerConfig::default().port);
+    }
+
+    #[test]
+    fn env_overrides_file_and_defaults() {
+        let (config, unknown) = parse(
+            Some(TOML),
+            &[
+                ("DENCHO_SERVER__PORT", "5000"),
+                ("DENCHO_DOWNLOAD__QUEUE", "true"),
+                ("DENCHO_OUTPUT__S3__BUCKET", "invoices"),
+                ("DENCHO_OUTPUT__S3__REGION", "ap-northeast-1"),
+                ("DENCHO_CORS__ALLOWED_ORIGINS", r#"["app://dencho"]"#),
+            ],
+        );
+        assert!(unknown.is_empty());
+        assert_eq!(config.server.port, 5000);
+        // 上書きしていないファイルの値は残る
+        assert_eq!(config.download.timeout_secs, 120);
+        assert!(config.download.queue);
+        assert_eq!(config.output.s3.unwrap().bucket, "invoices");
+        assert_eq!(config.cors.allowed_origins, ["app://dencho"]);
+    }
+
+    #[test]
+    fn cli_port_overrides_env_and_file() {
+        let (config, _) = parse(Some(TOML), &[("DENCHO_SERVER__PORT", "5000")]);
+        let addr = config.server.listen_addr().parse().unwrap();
+        assert_eq!(crate::apply_cli_port(addr, None).unwrap().port(), 5000);
+        assert_eq!(
+            crate::apply_cli_port(addr, Some("6000")).unwrap().port(),
+            6000
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_not_rejected() {
+        let content = "[server]\nprot = 4000\n\n[typo]\nvalue = 1\n";
+        let (config, unknown) = parse(Some(content), &[("DENCHO_DOWNLOAD__TIMEOUT", "30")]);
+        assert_eq!(config.server.port, ServerConfig::default().port);
+        assert_eq!(
+            unknown,
+            ["download.timeout", "server.prot", "typo"].map(String::from)
+        );
+    }
+
+    #[test]
+    fn unknown_provider_table_keys_are_ignored() {
+        let content = "[providers]\nother = \"dist/other.js\"\n\n\
+                       [providers.freee]\nscript = \"dist/freee.js\"\ndisplay = \"freee\"\n";
+        let (config, unknown) = parse(Some(content), &[]);
+        assert_eq!(
+            config.providers["other"].script(),
+            Path::new("dist/other.js")
+        );
+        assert_eq!(
+            config.providers["freee"].script(),
+            Path::new("dist/freee.js")
+        );
+        assert_eq!(unknown, ["providers.freee.display"]);
+    }
+
+    #[test]
+    fn invalid_values_are_still_errors() {
+        let overrides = [("DENCHO_SERVER__PORT".to_string(), "\"x\"".to_string())];
+        let e = parse_config(Path::new(CONFIG_FILE_NAME), None, &overrides).unwrap_err();
+        assert!(e.contains("DENCHO_SERVER__PORT"), "{}", e);
+        assert!(parse_config(Path::new(CONFIG_FILE_NAME), Some("[server"), &[]).is_err());
+    }
+
+    #[test]
+    fn history_persist_fields_default_to_everything() {
+        let (defaults, _) = parse(None, &[]);
+        assert!(!defaults.history.enabled);
+        assert_eq!(defaults.history.persist_fields.len(), 4);
+
+        let content = "[history]\npersist_fields = [\"clientId\", \"credentialSet\"]\n";
+        let (config, _) = parse(Some(content), &[]);
+        assert_eq!(
+            config.history.persist_fields,
+            [HistoryField::ClientId, HistoryField::CredentialSet]
+        );
+        let (config, _) = parse(Some(content), &[("DENCHO_HISTORY__PERSIST_FIELDS", "[]")]);
+        assert!(config.history.persist_fields.is_empty());
+
+        let e = parse_config(
+            Path::new(CONFIG_FILE_NAME),
+            Some("[history]\npersist_fields = [\"employee\"]\n"),
+            &[],
+        )
+        .unwrap_err();
+        assert!(e.contains("employee"), "{}", e);
+    }
+}
@@ -0,0 +1,233 @@
+/// Windows 資格情報マネージャーのターゲット名
+pub const TARGET_NAME: &str = "dencho-cli";
+
+/// 保存済みの GitHub 認証情報
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// 保存済みの認証情報（無ければ None）
+pub fn load() -> Result<Option<Credentials>, String> {
+    imp::load()
+}
+
+pub fn store(credentials: &Credentials) -> Result<(), String> {
+    imp::store(credentials)
+}
+
+/// 削除した場合は true（元から無ければ false）
+pub fn delete() -> Result<bool, String> {
+    imp::delete()
+}
+
+/// 標準入力からパスワードを 1 行読む
+///
+/// 端末から入力する場合は入力中の文字を表示しない。パイプからの入力はそのまま読む。
+pub fn read_password() -> std::io::Result<String> {
+    let mut password = String::new();
+    {
+        let hidden = echo::disable();
+        std::io::stdin().read_line(&mut password)?;
+        if hidden.is_some() {
+            // 表示しなかった改行の代わり
+            println!();
+        }
+    }
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// 端末のエコーを止め、drop で元の設定に戻す
+#[cfg(unix)]
+mod echo {
+    pub struct Hidden {
+        original: libc::termios,
+    }
+
+    /// 標準入力が端末でなければ None（何もしない）
+    pub fn disable() -> Option<Hidden> {
+        // SAFETY: termios は tcgetattr が書き込んだ値だけを使う
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return None;
+            }
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+            let mut hidden = original;
+            hidden.c_lflag &= !libc::ECHO;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &hidden) != 0 {
+                return None;
+            }
+            Some(Hidden { original })
+        }
+    }
+
+    impl Drop for Hidden {
+        fn drop(&mut self) {
+            // SAFETY: disable で取得した元の設定に戻すだけ
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            }
+        }
+    }
+}
+
+/// コンソールのエコーを止め、drop で元のモードに戻す
+#[cfg(windows)]
+mod echo {
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT,
+        STD_INPUT_HANDLE,
+    };
+
+    pub struct Hidden {
+        handle: HANDLE,
+        original: CONSOLE_MODE,
+    }
+
+    /// 標準入力がコンソールでなければ None（何もしない）
+    pub fn disable() -> Option<Hidden> {
+        // SAFETY: GetConsoleMode はコンソール以外のハンドルでは失敗するだけ
+        unsafe {
+            let handle = GetStdHandle(STD_INPUT_HANDLE);
+            let mut original: CONSOLE_MODE = 0;
+            if GetConsoleMode(handle, &mut original) == 0 {
+                return None;
+            }
+            if SetConsoleMode(handle, original & !ENABLE_ECHO_INPUT) == 0 {
+                return None;
+            }
+            Some(Hidden { handle, original })
+        }
+    }
+
+    impl Drop for Hidden {
+        fn drop(&mut self) {
+            // SAFETY: disable で取得したハンドルとモードを戻すだけ
+            unsafe {
+                SetConsoleMode(self.handle, self.original);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod echo {
+    pub struct Hidden;
+
+    pub fn disable() -> Option<Hidden> {
+        None
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::{Credentials, TARGET_NAME};
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_NOT_FOUND};
+    use windows_sys::Win32::Security::Credentials::{
+        CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE,
+        CRED_TYPE_GENERIC,
+    };
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    pub fn load() -> Result<Option<Credentials>, String> {
+        let target = wide(TARGET_NAME);
+        let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+        // SAFETY: target は NUL 終端済み。成功時の credential は CredFree で解放する
+        unsafe {
+            if CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+                let error = GetLastError();
+                if error == ERROR_NOT_FOUND {
+                    return Ok(None);
+                }
+                return Err(format!(
+                    "認証情報の読み込みに失敗しました (エラー {})",
+                    error
+                ));
+            }
+            let cred = &*credential;
+            let username = if cred.UserName.is_null() {
+                String::new()
+            } else {
+                let len = (0..).take_while(|&i| *cred.UserName.add(i) != 0).count();
+                String::from_utf16_lossy(std::slice::from_raw_parts(cred.UserName, len))
+            };
+            let password = if cred.CredentialBlob.is_null() {
+                String::new()
+            } else {
+                let blob = std::slice::from_raw_parts(
+                    cred.CredentialBlob,
+                    cred.CredentialBlobSize as usize,
+                );
+                String::from_utf8_lossy(blob).into_owned()
+            };
+            CredFree(credential as *const _);
+            Ok(Some(Credentials { username, password }))
+        }
+    }
+
+    pub fn store(credentials: &Credentials) -> Result<(), String> {
+        let mut target = wide(TARGET_NAME);
+        let mut username = wide(&credentials.username);
+        let mut password = credentials.password.as_bytes().to_vec();
+        // SAFETY: 参照するバッファはすべて CredWriteW の呼び出し中は生存している
+        unsafe {
+            let mut credential: CREDENTIALW = std::mem::zeroed();
+            credential.Type = CRED_TYPE_GENERIC;
+            credential.TargetName = target.as_mut_ptr();
+            credential.UserName = username.as_mut_ptr();
+            credential.CredentialBlobSize = password.len() as u32;
+            credential.CredentialBlob = password.as_mut_ptr();
+            credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+            if CredWriteW(&credential, 0) == 0 {
+                return Err(format!(
+                    "認証情報の保存に失敗しました (エラー {})",
+                    GetLastError()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete() -> Result<bool, String> {
+        let target = wide(TARGET_NAME);
+        // SAFETY: target は NUL 終端済み
+        unsafe {
+            if CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) == 0 {
+                let error = GetLastError();
+                if error == ERROR_NOT_FOUND {
+                    return Ok(false);
+                }
+                return Err(format!("認証情報の削除に失敗しました (エラー {})", error));
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(not(windows))]
+mod imp {
+    use super::Credentials;
+
+    const UNSUPPORTED: &str = "認証情報の保存は Windows でのみ利用できます";
+
+    /// 保存先が無いため常に未保存として扱う（リクエストの認証情報だけを使う）
+    pub fn load() -> Result<Option<Credentials>, String> {
+        Ok(None)
+    }
+
+    pub fn store(_credentials: &Credentials) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub fn delete() -> Result<bool, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+
+/// path があるボリュームの空き容量（バイト、このプロセスが使える分）
+///
+/// path がまだ無ければ、存在する最も近い親フォルダで調べる。
+pub fn available_bytes(path: &Path) -> Result<u64, String> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or_else(|| format!("{} の空き容量を確認できません", path.display()))?;
+    imp::available_bytes(existing)
+        .map_err(|e| format!("{} の空き容量を確認できません: {}", existing.display(), e))
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // fsblkcnt_t・c_ulong は macOS などでは u32 のため from で変換する
+    #[allow(clippy::useless_conversion)]
+    pub fn available_bytes(path: &Path) -> std::io::Result<u64> {
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: statvfs はすべて数値のフィールドなので 0 埋めで初期化できる
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: c_path は NUL 終端済み、stat は書き込み先として有効
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    pub fn available_bytes(path: &Path) -> std::io::Result<u64> {
+        let wide: Vec<u16> = path
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut available = 0u64;
+        // SAFETY: wide は NUL 終端済み。使わない出力は null を渡せる
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(available)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::path::Path;
+
+    pub fn available_bytes(_path: &Path) -> std::io::Result<u64> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "この OS には対応していません",
+        ))
+    }
+}
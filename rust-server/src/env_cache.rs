@@ -0,0 +1,197 @@
+use crate::clock::Clock;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 環境エラーをキャッシュする期間
+pub const ENV_FAILURE_TTL: Duration = Duration::from_secs(30);
+
+/// キャッシュされた環境エラー
+#[derive(Debug, Clone)]
+pub struct EnvFailure {
+    pub component: String,
+    pub reason: String,
+    recorded_at: DateTime<Utc>,
+}
+
+/// /health 用のキャッシュ状態
+#[derive(Serialize)]
+pub struct EnvCacheStatus {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub component: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(rename = "retryAfter", skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    pub reprobing: bool,
+}
+
+/// 環境エラーのネガティブキャッシュ
+///
+/// 環境が壊れている間は、ダウンロード要求ごとにプロセス起動を試さず即座に失敗させる。
+pub struct EnvCache {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    failure: Mutex<Option<EnvFailure>>,
+    reprobing: AtomicBool,
+}
+
+impl EnvCache {
+    pub fn new(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            failure: Mutex::new(None),
+            reprobing: AtomicBool::new(false),
+        }
+    }
+
+    /// TTL 内のキャッシュ済みエラーと、再試行までの秒数を返す
+    pub fn cached_failure(&self) -> Option<(EnvFailure, u64)> {
+        let guard = self.failure.lock().unwrap();
+        let failure = guard.as_ref()?;
+        let elapsed = (self.clock.now() - failure.recorded_at)
+            .to_std()
+            .unwrap_or_default();
+        if elapsed >= self.ttl {
+            return None;
+        }
+        let retry_after = (self.ttl - elapsed).as_secs().max(1);
+        Some((failure.clone(), retry_after))
+    }
+
+    pub fn record_failure(&self, component: &str, reason: &str) {
+        *self.failure.lock().unwrap() = Some(EnvFailure {
+            component: component.to_string(),
+            reason: reason.to_string(),
+            recorded_at: self.clock.now(),
+        });
+    }
+
+    pub fn clear(&self) {
+        *self.failure.lock().unwrap() = None;
+    }
+
+    /// 再プローブを開始してよいか（同時に 1 つだけ実行する）
+    pub fn try_begin_reprobe(&self) -> bool {
+        self.reprobing
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub fn end_reprobe(&self) {
+        self.reprobing.store(false, Ordering::Release);
+    }
+
+    pub fn status(&self) -> EnvCacheStatus {
+        let reprobing = self.reprobing.load(Ordering::Acquire);
+        match self.cached_failure() {
+            Some((failure, retry_after)) => EnvCacheStatus {
+                status: "broken",
+                component: Some(failure.component),
+                reason: Some(failure.reason),
+                retry_after: Some(retry_after),
+                reprobing,
+            },
+            None => EnvCacheStatus {
+                status: "ok",
+                component: None,
+                reason: None,
+                retry_after: None,
+                reprobing,
+            },
+        }
+    }
+}
+
+/// ダウンロードに必要な環境を軽量にチェック（component, reason を返す）
+pub fn probe_environment(script_path: &Path) -> Result<(), (&'static str, String)> {
+    match Command::new("node").arg("--version").output() {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            return Err((
+                "node",
+                format!("node --version が失敗しました: {}", output.status),
+            ))
+        }
+        Err(e) => return Err(("node", format!("Node.js が見つかりません: {}", e))),
+    }
+
+    if !script_path.exists() {
+        return Err((
+            "script",
+            format!(
+                "スクリプトファイルが見つかりません: {}",
+                script_path.display()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+
+    fn cache() -> (EnvCache, Arc<TestClock>) {
+        let clock = TestClock::new("2024-03-01T00:00:00Z".parse().unwrap());
+        (EnvCache::new(ENV_FAILURE_TTL, clock.clone()), clock)
+    }
+
+    #[test]
+    fn failure_is_served_from_cache_until_ttl() {
+        let (cache, clock) = cache();
+        assert!(cache.cached_failure().is_none());
+
+        cache.record_failure("node", "Node.js が見つかりません");
+        let (failure, retry_after) = cache.cached_failure().unwrap();
+        assert_eq!(failure.component, "node");
+        assert_eq!(retry_after, 30);
+        assert_eq!(cache.status().status, "broken");
+
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(cache.cached_failure().unwrap().1, 1);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.cached_failure().is_none());
+        assert_eq!(cache.status().status, "ok");
+    }
+
+    #[test]
+    fn recording_again_restarts_the_ttl() {
+        let (cache, clock) = cache();
+        cache.record_failure("node", "old");
+        clock.advance(Duration::from_secs(20));
+        cache.record_failure("script", "new");
+
+        clock.advance(Duration::from_secs(20));
+        let (failure, retry_after) = cache.cached_failure().unwrap();
+        assert_eq!(failure.reason, "new");
+        assert_eq!(retry_after, 10);
+    }
+
+    #[test]
+    fn clear_invalidates_immediately() {
+        let (cache, _clock) = cache();
+        cache.record_failure("node", "Node.js が見つかりません");
+        cache.clear();
+        assert!(cache.cached_failure().is_none());
+    }
+
+    #[test]
+    fn only_one_reprobe_at_a_time() {
+        let (cache, _clock) = cache();
+        assert!(cache.try_begin_reprobe());
+        assert!(!cache.try_begin_reprobe());
+        assert!(cache.status().reprobing);
+        cache.end_reprobe();
+        assert!(cache.try_begin_reprobe());
+    }
+}
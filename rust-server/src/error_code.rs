@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+/// レスポンスの code（機械判定用のエラー種別）
+///
+/// 画面の出し分けに使われるため、値は変更・削除せず追加のみとする（message は文言が変わりうる）。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// 本文・クエリ・パラメーターが不正
+    InvalidRequest,
+    /// 存在しないパス・ファイル
+    NotFound,
+    /// ジョブ履歴に記録されたファイルが削除されている（410）
+    FileDeleted,
+    MethodNotAllowed,
+    /// 指定したジョブが無い（完了後に削除された場合を含む）
+    JobNotFound,
+    /// locale が許可リストに無い
+    InvalidLocale,
+    /// outputDir が不正・許可された場所の外
+    InvalidOutputDir,
+    UnknownProvider,
+    /// POST /api/pause で一時停止中
+    Paused,
+    SetupInProgress,
+    /// オフラインモード（DENCHO_OFFLINE）のためインストールできない
+    Offline,
+    /// 環境セットアップ（Node.js・依存関係・ブラウザ）に失敗した
+    SetupFailed,
+    /// Node.js やスクリプトが見つからない・起動できない（component は message を参照）
+    EnvironmentBroken,
+    /// スクリプトがアプリケーションルートの外にある
+    ScriptOutsideRoot,
+    DownloadInProgress,
+    RateLimited,
+    DownloadTimeout,
+    /// 保存先の空き容量が download.min_free_mb 未満（507）
+    InsufficientStorage,
+    /// GitHub / Supabase へのログインが完了しなかった（認証情報の確認が必要）
+    LoginFailed,
+    /// スクリプトがエラーで終了した（ログイン以外）
+    ScriptFailed,
+    /// 出力先（S3 / Azure）へのアップロードに失敗した（再送キューに登録済み）
+    UploadFailed,
+    /// サーバー内部のエラー（履歴データベースなど）
+    InternalError,
+}
+
+/// スクリプトがログインに失敗したときの終了コード（src/download-supabase-invoice.ts と合わせる）
+pub const LOGIN_FAILED_EXIT_CODE: i32 = 2;
+
+/// スクリプトが一時的なエラー（タイムアウト・ネットワーク）で失敗したときの終了コード
+pub const TRANSIENT_EXIT_CODE: i32 = 3;
+
+/// スクリプトの終了コードからエラー種別を決める
+pub fn from_exit_code(code: Option<i32>) -> ErrorCode {
+    match code {
+        Some(LOGIN_FAILED_EXIT_CODE) => ErrorCode::LoginFailed,
+        _ => ErrorCode::ScriptFailed,
+    }
+}
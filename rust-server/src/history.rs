@@ -0,0 +1,569 @@
+use crate::config::HistoryField;
+use chrono::{DateTime, Local};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// 履歴に残すエラーメッセージの上限（文字数）
+const ERROR_MAX_CHARS: usize = 500;
+
+/// history.persist_fields で保存しなかった項目に API で返す値
+pub const NOT_STORED: &str = "[not stored]";
+
+/// スキーマの変更履歴（PRAGMA user_version = 適用済みの数）
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE jobs (
+        id TEXT PRIMARY KEY,
+        requested_at TEXT NOT NULL,
+        finished_at TEXT,
+        duration_ms INTEGER,
+        provider TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        files TEXT NOT NULL DEFAULT '[]'
+    );
+    CREATE INDEX jobs_requested_at ON jobs (requested_at DESC);",
+    "ALTER TABLE jobs ADD COLUMN request_id TEXT;
+    ALTER TABLE jobs ADD COLUMN requested_by TEXT;",
+    "ALTER TABLE jobs ADD COLUMN attempts INTEGER;",
+    "ALTER TABLE jobs ADD COLUMN client_id TEXT;
+    ALTER TABLE jobs ADD COLUMN note TEXT;
+    ALTER TABLE jobs ADD COLUMN credential_set TEXT;
+    ALTER TABLE jobs ADD COLUMN not_stored TEXT NOT NULL DEFAULT '[]';",
+];
+
+/// 一覧・取得で読む列（read_record の順）
+const RECORD_COLUMNS: &str =
+    "id, requested_at, finished_at, duration_ms, provider, status, error, files, request_id, requested_by, attempts,
+     client_id, note, credential_set, not_stored";
+
+/// ジョブ履歴の 1 件（GET /api/jobs の要素）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRecord {
+    pub id: String,
+    pub requested_at: DateTime<Local>,
+    pub finished_at: Option<DateTime<Local>>,
+    pub duration_ms: Option<i64>,
+    /// 実行したスクリプト
+    pub provider: String,
+    /// pending / success / error / timeout / cancelled
+    pub status: String,
+    pub error: Option<String>,
+    /// 保存されたファイル名
+    pub files: Vec<String>,
+    /// ジョブを作成したリクエストの ID（X-Request-Id、ログと突き合わせる）
+    pub request_id: Option<String>,
+    /// リクエストの送信元 IP アドレス
+    pub requested_by: Option<String>,
+    /// スクリプトを実行した回数（再試行を含む、実行前に終わった場合は null）
+    pub attempts: Option<u32>,
+    /// リクエストの clientId（呼び出し元アプリの識別子）
+    pub client_id: Option<String>,
+    /// リクエストの note（任意のメモ）
+    pub note: Option<String>,
+    /// 使った認証情報（request / 保存済みの認証情報のターゲット名）
+    pub credential_set: Option<String>,
+}
+
+/// 履歴に残すかを history.persist_fields で選べるリクエストの項目
+#[derive(Debug, Clone, Default)]
+pub struct RequestFields {
+    pub client_id: Option<String>,
+    pub note: Option<String>,
+    /// 送信元 IP アドレス（スケジュール実行では schedule:<名前>）
+    pub requested_by: Option<String>,
+    pub credential_set: Option<String>,
+}
+
+/// GET /api/jobs の絞り込み条件
+#[derive(Debug, Clone)]
+pub struct JobQuery {
+    pub limit: u32,
+    pub offset: u32,
+    pub status: Option<String>,
+}
+
+/// ジョブ履歴（data/history.db）
+pub struct HistoryStore {
+    conn: Mutex<Connection>,
+}
+
+impl HistoryStore {
+    /// データベースを開き、未適用のマイグレーションを適用する
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("履歴ディレクトリ作成失敗: {}", e))?;
+        }
+        let conn = Connection::open(path)
+            .map_err(|e| format!("履歴データベースを開けません: {} ({})", path.display(), e))?;
+        migrate(&conn).map_err(|e| format!("履歴データベースの移行に失敗しました: {}", e))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// 開始したジョブを登録する
+    ///
+    /// fields のうち persist に無い項目は書き込まず、保存しなかったことだけを記録する。
+    pub fn insert_started(
+        &self,
+        id: &str,
+        requested_at: DateTime<Local>,
+        provider: &str,
+        request_id: Option<&str>,
+        fields: &RequestFields,
+        persist: &[HistoryField],
+    ) -> Result<(), String> {
+        let keep = |field: HistoryField, value: &Option<String>| {
+            value.clone().filter(|_| persist.contains(&field))
+        };
+        let not_stored: Vec<&str> = [
+            HistoryField::ClientId,
+            HistoryField::Note,
+            HistoryField::RequesterIp,
+            HistoryField::CredentialSet,
+        ]
+        .into_iter()
+        .filter(|field| !persist.contains(field))
+        .map(|field| field.as_str())
+        .collect();
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO jobs (id, requested_at, provider, status, request_id, requested_by,
+                 client_id, note, credential_set, not_stored)
+                 VALUES (?1, ?2, ?3, 'pending', ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    id,
+                    requested_at.to_rfc3339(),
+                    provider,
+                    request_id,
+                    keep(HistoryField::RequesterIp, &fields.requested_by),
+                    keep(HistoryField::ClientId, &fields.client_id),
+                    keep(HistoryField::Note, &fields.note),
+                    keep(HistoryField::CredentialSet, &fields.credential_set),
+                    serde_json::to_string(&not_stored).unwrap_or_else(|_| "[]".to_string()),
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("履歴の登録に失敗しました: {}", e))
+    }
+
+    /// 終了したジョブの結果を記録する（既に終了済みなら何もしない）
+    pub fn finish(
+        &self,
+        id: &str,
+        finished_at: DateTime<Local>,
+        status: &str,
+        error: Option<&str>,
+        files: &[String],
+        attempts: Option<u32>,
+    ) -> Result<(), String> {
+        let error = error.map(|e| e.chars().take(ERROR_MAX_CHARS).collect::<String>());
+        let files = serde_json::to_string(files).unwrap_or_else(|_| "[]".to_string());
+        let conn = self.conn.lock().unwrap();
+        let requested_at: Option<String> = conn
+            .query_row(
+                "SELECT requested_at FROM jobs WHERE id = ?1 AND finished_at IS NULL",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("履歴の取得に失敗しました: {}", e))?;
+        let Some(requested_at) = requested_at else {
+            return Ok(());
+        };
+        let duration_ms = DateTime::parse_from_rfc3339(&requested_at)
+            .ok()
+            .map(|t| (finished_at - t.with_timezone(&Local)).num_milliseconds());
+
+        conn.execute(
+            "UPDATE jobs SET finished_at = ?2, duration_ms = ?3, status = ?4, error = ?5, files = ?6,
+             attempts = ?7 WHERE id = ?1",
+            params![
+                id,
+                finished_at.to_rfc3339(),
+                duration_ms,
+                status,
+                error,
+                files,
+                attempts
+            ],
+        )
+        .map(|_| ())
+        .map_err(|e| format!("履歴の更新に失敗しました: {}", e))
+    }
+
+    /// 新しい順に返す
+    pub fn list(&self, query: &JobQuery) -> Result<Vec<JobRecord>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM jobs WHERE (?1 IS NULL OR status = ?1)
+                 ORDER BY requested_at DESC LIMIT ?2 OFFSET ?3",
+                RECORD_COLUMNS
+            ))
+            .map_err(|e| format!("履歴の取得に失敗しました: {}", e))?;
+        let rows = stmt
+            .query_map(
+                params![query.status, query.limit, query.offset],
+                read_record,
+            )
+            .map_err(|e| format!("履歴の取得に失敗しました: {}", e))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("履歴の取得に失敗しました: {}", e))
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<JobRecord>, String> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                &format!("SELECT {} FROM jobs WHERE id = ?1", RECORD_COLUMNS),
+                params![id],
+                read_record,
+            )
+            .optional()
+            .map_err(|e| format!("履歴の取得に失敗しました: {}", e))
+    }
+
+    /// いずれかのジョブが name のファイルを保存したか（files 列の JSON 配列を調べる）
+    pub fn has_file(&self, name: &str) -> Result<bool, String> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM jobs, json_each(jobs.files) WHERE json_each.value = ?1)",
+                params![name],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("履歴の取得に失敗しました: {}", e))
+    }
+}
+
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(applied) {
+        conn.execute_batch(sql)?;
+        conn.pragma_update(None, "user_version", version + 1)?;
+    }
+    Ok(())
+}
+
+fn read_record(row: &rusqlite::Row) -> rusqlite::Result<JobRecord> {
+    let parse_time = |index: usize, value: String| {
+        DateTime::parse_from_rfc3339(&value)
+            .map(|t| t.with_timezone(&Local))
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+    };
+    let files: String = row.get(7)?;
+    let not_stored: Vec<String> =
+        serde_json::from_str(&row.get::<_, String>(14)?).unwrap_or_default();
+    // 保存しなかった項目は null（値が無かった）と区別できるよう印を返す
+    let field = |name: &str, index: usize| -> rusqlite::Result<Option<String>> {
+        if not_stored.iter().any(|n| n == name) {
+            Ok(Some(NOT_STORED.to_string()))
+        } else {
+            row.get(index)
+        }
+    };
+    Ok(JobRecord {
+        id: row.get(0)?,
+        requested_at: parse_time(1, row.get(1)?)?,
+        finished_at: row
+            .get::<_, Option<String>>(2)?
+            .map(|value| parse_time(2, value))
+            .transpose()?,
+        duration_ms: row.get(3)?,
+        provider: row.get(4)?,
+        status: row.get(5)?,
+        error: row.get(6)?,
+        files: serde_json::from_str(&files).unwrap_or_default(),
+        request_id: row.get(8)?,
+        requested_by: field(HistoryField::RequesterIp.as_str(), 9)?,
+        attempts: row.get(10)?,
+        client_id: field(HistoryField::ClientId.as_str(), 11)?,
+        note: field(HistoryField::Note.as_str(), 12)?,
+        credential_set: field(HistoryField::CredentialSet.as_str(), 13)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use chrono::Duration;
+
+    fn query(limit: u32, offset: u32, status: Option<&str>) -> JobQuery {
+        JobQuery {
+            limit,
+            offset,
+            status: status.map(str::to_string),
+        }
+    }
+
+    /// デフォルトの history.persist_fields（すべて保存する）
+    fn all_fields() -> Vec<HistoryField> {
+        crate::config::HistoryConfig::default().persist_fields
+    }
+
+    fn ids(records: &[JobRecord]) -> Vec<&str> {
+        records.iter().map(|record| record.id.as_str()).collect()
+    }
+
+    /// base から 1 分ずつずらして 3 件登録し、b だけ success で終える
+    fn store_with_three_jobs(dir: &TempDir) -> HistoryStore {
+        let store = HistoryStore::open(&dir.path().join("data").join("history.db")).unwrap();
+        let base = Local::now();
+        for (i, id) in ["a", "b", "c"].into_iter().enumerate() {
+            store
+                .insert_started(
+                    id,
+                    base + Duration::minutes(i as i64),
+                    "supabase",
+                    None,
+                    &RequestFields::default(),
+                    &all_fields(),
+                )
+                .unwrap();
+        }
+        store
+            .finish(
+                "b",
+                base + Duration::minutes(5),
+                "success",
+                None,
+                &[],
+                Some(1),
+            )
+            .unwrap();
+        store
+    }
+
+    #[test]
+    fn open_applies_migrations_to_a_fresh_database() {
+        let dir = TempDir::new();
+        let path = dir.path().join("data").join("history.db");
+        HistoryStore::open(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        // 最後のマイグレーションで追加した列まで読める
+        conn.prepare(&format!("SELECT {} FROM jobs", RECORD_COLUMNS))
+            .unwrap();
+    }
+
+    #[test]
+    fn reopening_keeps_existing_records() {
+        let dir = TempDir::new();
+        let path = dir.path().join("history.db");
+        let store = HistoryStore::open(&path).unwrap();
+        store
+            .insert_started(
+                "job-1",
+                Local::now(),
+                "supabase",
+                Some("req-1"),
+                &RequestFields {
+                    requested_by: Some("127.0.0.1".to_string()),
+                    ..Default::default()
+                },
+                &all_fields(),
+            )
+            .unwrap();
+        drop(store);
+
+        let store = HistoryStore::open(&path).unwrap();
+        let record = store.get("job-1").unwrap().unwrap();
+        assert_eq!(record.status, "pending");
+        assert_eq!(record.request_id.as_deref(), Some("req-1"));
+        assert_eq!(record.requested_by.as_deref(), Some("127.0.0.1"));
+    }
+
+    #[test]
+    fn finish_records_the_result_once() {
+        let dir = TempDir::new();
+        let store = HistoryStore::open(&dir.path().join("history.db")).unwrap();
+        let requested_at = Local::now();
+        store
+            .insert_started(
+                "job-1",
+                requested_at,
+                "supabase",
+                None,
+                &RequestFields::default(),
+                &all_fields(),
+            )
+            .unwrap();
+        let files = vec!["invoice.pdf".to_string()];
+        let long_error = "x".repeat(ERROR_MAX_CHARS + 100);
+        store
+            .finish(
+                "job-1",
+                requested_at + Duration::seconds(3),
+                "error",
+                Some(&long_error),
+                &files,
+                Some(2),
+            )
+            .unwrap();
+
+        let record = store.get("job-1").unwrap().unwrap();
+        assert_eq!(record.status, "error");
+        assert_eq!(record.duration_ms, Some(3000));
+        assert_eq!(record.error.unwrap().chars().count(), ERROR_MAX_CHARS);
+        assert_eq!(record.files, files);
+        assert_eq!(record.attempts, Some(2));
+        assert!(store.has_file("invoice.pdf").unwrap());
+        assert!(!store.has_file("other.pdf").unwrap());
+
+        // 終了済みのジョブは上書きしない
+        store
+            .finish("job-1", Local::now(), "cancelled", None, &[], None)
+            .unwrap();
+        assert_eq!(store.get("job-1").unwrap().unwrap().status, "error");
+        // 未登録の ID は無視する
+        store
+            .finish("missing", Local::now(), "success", None, &[], None)
+            .unwrap();
+        assert!(store.get("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn list_returns_newest_first() {
+        let dir = TempDir::new();
+        let store = store_with_three_jobs(&dir);
+        assert_eq!(
+            ids(&store.list(&query(10, 0, None)).unwrap()),
+            ["c", "b", "a"]
+        );
+    }
+
+    #[test]
+    fn list_applies_limit_offset_and_status() {
+        let dir = TempDir::new();
+        let store = store_with_three_jobs(&dir);
+
+        assert_eq!(ids(&store.list(&query(2, 0, None)).unwrap()), ["c", "b"]);
+        assert_eq!(ids(&store.list(&query(2, 2, None)).unwrap()), ["a"]);
+        assert_eq!(
+            ids(&store.list(&query(10, 0, Some("success"))).unwrap()),
+            ["b"]
+        );
+        assert_eq!(
+            ids(&store.list(&query(10, 0, Some("pending"))).unwrap()),
+            ["c", "a"]
+        );
+        assert_eq!(
+            ids(&store.list(&query(10, 1, Some("pending"))).unwrap()),
+            ["a"]
+        );
+        assert!(store
+            .list(&query(10, 0, Some("timeout")))
+            .unwrap()
+            .is_empty());
+    }
+
+    /// 各項目に別々の目印を入れたリクエスト
+    fn canary_fields() -> RequestFields {
+        RequestFields {
+            client_id: Some("CANARY-client-7f3a".to_string()),
+            note: Some("CANARY-note-51c2".to_string()),
+            requested_by: Some("CANARY-ip-09be".to_string()),
+            credential_set: Some("CANARY-cred-d48e".to_string()),
+        }
+    }
+
+    #[test]
+    fn excluded_fields_never_reach_the_database_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("history.db");
+        let store = HistoryStore::open(&path).unwrap();
+        store
+            .insert_started(
+                "job-1",
+                Local::now(),
+                "supabase",
+                None,
+                &canary_fields(),
+                &[HistoryField::ClientId],
+            )
+            .unwrap();
+        store
+            .finish("job-1", Local::now(), "success", None, &[], Some(1))
+            .unwrap();
+
+        let record = store.get("job-1").unwrap().unwrap();
+        assert_eq!(record.client_id.as_deref(), Some("CANARY-client-7f3a"));
+        assert_eq!(record.note.as_deref(), Some(NOT_STORED));
+        assert_eq!(record.requested_by.as_deref(), Some(NOT_STORED));
+        assert_eq!(record.credential_set.as_deref(), Some(NOT_STORED));
+        drop(store);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let contains = |needle: &str| {
+            bytes
+                .windows(needle.len())
+                .any(|window| window == needle.as_bytes())
+        };
+        // 保存した項目はファイルに入っている（検索方法の確認）
+        assert!(contains("CANARY-client-7f3a"));
+        for excluded in ["CANARY-note-51c2", "CANARY-ip-09be", "CANARY-cred-d48e"] {
+            assert!(!contains(excluded), "{} が保存されています", excluded);
+        }
+    }
+
+    #[test]
+    fn persist_fields_apply_to_new_records_only() {
+        let dir = TempDir::new();
+        let store = HistoryStore::open(&dir.path().join("history.db")).unwrap();
+        let requested_at = Local::now();
+        store
+            .insert_started(
+                "before",
+                requested_at,
+                "supabase",
+                None,
+                &canary_fields(),
+                &all_fields(),
+            )
+            .unwrap();
+        store
+            .insert_started(
+                "after",
+                requested_at + Duration::minutes(1),
+                "supabase",
+                None,
+                &canary_fields(),
+                &[],
+            )
+            .unwrap();
+
+        let before = store.get("before").unwrap().unwrap();
+        assert_eq!(before.note.as_deref(), Some("CANARY-note-51c2"));
+        assert_eq!(before.requested_by.as_deref(), Some("CANARY-ip-09be"));
+        let after = store.get("after").unwrap().unwrap();
+        assert_eq!(after.client_id.as_deref(), Some(NOT_STORED));
+        assert_eq!(after.note.as_deref(), Some(NOT_STORED));
+
+        // 保存する設定でも、値が無い項目は null のまま
+        store
+            .insert_started(
+                "empty",
+                requested_at + Duration::minutes(2),
+                "supabase",
+                None,
+                &RequestFields::default(),
+                &all_fields(),
+            )
+            .unwrap();
+        assert_eq!(store.get("empty").unwrap().unwrap().note, None);
+    }
+}
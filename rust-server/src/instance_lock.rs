@@ -0,0 +1,85 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// 別のプロセスがロックを持っている（同じポートでサーバーが起動中）場合の終了コード
+pub const ALREADY_RUNNING_EXIT_CODE: i32 = 3;
+
+/// ポートごとの起動ロック（ドロップで解放）
+///
+/// OS のファイルロックなので、プロセスが異常終了した場合も自動で解放される。
+pub struct InstanceLock {
+    file: File,
+    path: PathBuf,
+}
+
+pub enum LockError {
+    /// 別のプロセスが起動中（ロックファイルに書かれた PID）
+    AlreadyRunning {
+        pid: Option<u32>,
+        path: PathBuf,
+    },
+    Failed(String),
+}
+
+/// data/server-<port>.lock のロックを取る
+///
+/// 同じアプリケーションルートで別のポートを使うインスタンスは同時に起動できる。
+pub fn acquire(app_root: &Path, port: u16) -> Result<InstanceLock, LockError> {
+    let dir = app_root.join("data");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| LockError::Failed(format!("{} を作成できません: {}", dir.display(), e)))?;
+    let path = dir.join(format!("server-{}.lock", port));
+    // 他のプロセスが書いた PID を消さないよう、ロックを取るまでは切り詰めない
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| LockError::Failed(format!("{} を開けません: {}", path.display(), e)))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut contents = String::new();
+            let _ = file.read_to_string(&mut contents);
+            return Err(LockError::AlreadyRunning {
+                pid: contents.trim().parse().ok(),
+                path,
+            });
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(LockError::Failed(format!(
+                "{} をロックできません: {}",
+                path.display(),
+                e
+            )));
+        }
+    }
+
+    let written = file
+        .set_len(0)
+        .and_then(|()| file.rewind())
+        .and_then(|()| writeln!(file, "{}", std::process::id()));
+    if let Err(e) = written {
+        return Err(LockError::Failed(format!(
+            "{} を書き込めません: {}",
+            path.display(),
+            e
+        )));
+    }
+    Ok(InstanceLock { file, path })
+}
+
+impl InstanceLock {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,189 @@
+use crate::clock::Clock;
+use crate::DownloadResponse;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// 完了したジョブを保持する期間
+pub const JOB_RETENTION: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// ダウンロードジョブ（GET /api/download/{jobId} のレスポンス）
+#[derive(Clone, Serialize)]
+pub struct Job {
+    #[serde(flatten)]
+    pub response: DownloadResponse,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Local>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: Option<DateTime<Local>>,
+    /// 実行中のスクリプトに中止を伝える
+    #[serde(skip)]
+    cancel: Arc<Notify>,
+}
+
+/// DELETE /api/jobs/{id} の結果
+pub enum CancelOutcome {
+    NotFound,
+    /// 既に完了していた（その時点の状態）
+    AlreadyFinished(Job),
+    /// 中止した（cancelled 状態のジョブ）
+    Cancelled(Job),
+}
+
+/// 実行中・完了済みジョブのインメモリ保管
+pub struct JobStore {
+    clock: Arc<dyn Clock>,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl JobStore {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn now(&self) -> DateTime<Local> {
+        self.clock.now().with_timezone(&Local)
+    }
+
+    /// pending 状態のジョブを作成し、ID と中止の通知を返す
+    pub fn create(&self, request_id: Option<String>) -> (String, Arc<Notify>) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut response = DownloadResponse::pending("ダウンロードを実行中です");
+        response.job_id = Some(id.clone());
+        response.request_id = request_id;
+        let cancel = Arc::new(Notify::new());
+
+        self.jobs.lock().unwrap().insert(
+            id.clone(),
+            Job {
+                response,
+                created_at: self.now(),
+                finished_at: None,
+                cancel: cancel.clone(),
+            },
+        );
+        (id, cancel)
+    }
+
+    /// 結果を記録して完了状態にする（中止済みのジョブはそのまま）
+    pub fn finish(&self, id: &str, mut response: DownloadResponse) {
+        if let Some(job) = self
+            .jobs
+            .lock()
+            .unwrap()
+            .get_mut(id)
+            .filter(|job| job.finished_at.is_none())
+        {
+            response.job_id = Some(id.to_string());
+            response.request_id = job.response.request_id.take();
+            job.response = response;
+            job.finished_at = Some(self.now());
+        }
+    }
+
+    /// 実行中のジョブを cancelled にし、スクリプトに中止を伝える
+    pub fn cancel(&self, id: &str) -> CancelOutcome {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return CancelOutcome::NotFound;
+        };
+        if job.finished_at.is_some() {
+            return CancelOutcome::AlreadyFinished(job.clone());
+        }
+
+        let mut response = DownloadResponse::with_status("cancelled", "ダウンロードを中止しました");
+        response.job_id = Some(id.to_string());
+        response.request_id = job.response.request_id.take();
+        job.response = response;
+        job.finished_at = Some(self.now());
+        // 待ち受け前に呼ばれても取りこぼさないよう notify_one を使う
+        job.cancel.notify_one();
+        CancelOutcome::Cancelled(job.clone())
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// 保持期間を過ぎた完了済みジョブを削除し、削除件数を返す
+    pub fn collect_garbage(&self, retention: std::time::Duration) -> usize {
+        let cutoff = self.now() - retention;
+        let mut jobs = self.jobs.lock().unwrap();
+        let before = jobs.len();
+        jobs.retain(|_, job| job.finished_at.is_none_or(|t| t > cutoff));
+        before - jobs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use std::time::Duration;
+
+    fn store() -> (JobStore, Arc<TestClock>) {
+        let clock = TestClock::new("2024-01-31T00:00:00Z".parse().unwrap());
+        (JobStore::new(clock.clone()), clock)
+    }
+
+    #[tokio::test]
+    async fn cancel_notifies_the_running_job_once() {
+        let (jobs, _) = store();
+        let (id, cancel) = jobs.create(Some("req-1".to_string()));
+
+        let CancelOutcome::Cancelled(job) = jobs.cancel(&id) else {
+            panic!("中止できません");
+        };
+        assert_eq!(job.response.status, "cancelled");
+        assert_eq!(job.response.request_id.as_deref(), Some("req-1"));
+        // 待ち受け前の通知も取りこぼさない
+        tokio::time::timeout(Duration::from_secs(1), cancel.notified())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            jobs.cancel(&id),
+            CancelOutcome::AlreadyFinished(_)
+        ));
+        assert!(matches!(jobs.cancel("missing"), CancelOutcome::NotFound));
+    }
+
+    #[test]
+    fn finish_does_not_overwrite_a_cancelled_job() {
+        let (jobs, _) = store();
+        let (id, _) = jobs.create(None);
+        jobs.cancel(&id);
+        jobs.finish(&id, DownloadResponse::success("完了"));
+        assert_eq!(jobs.get(&id).unwrap().response.status, "cancelled");
+
+        let (id, _) = jobs.create(None);
+        jobs.finish(&id, DownloadResponse::success("完了"));
+        let job = jobs.get(&id).unwrap();
+        assert_eq!(job.response.status, "success");
+        assert_eq!(job.response.job_id.as_deref(), Some(id.as_str()));
+        assert!(matches!(
+            jobs.cancel(&id),
+            CancelOutcome::AlreadyFinished(_)
+        ));
+    }
+
+    #[test]
+    fn garbage_collection_keeps_running_and_recent_jobs() {
+        let (jobs, clock) = store();
+        let (running, _) = jobs.create(None);
+        let (finished, _) = jobs.create(None);
+        jobs.finish(&finished, DownloadResponse::success("完了"));
+
+        clock.advance(JOB_RETENTION - Duration::from_secs(1));
+        assert_eq!(jobs.collect_garbage(JOB_RETENTION), 0);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(jobs.collect_garbage(JOB_RETENTION), 1);
+        assert!(jobs.get(&finished).is_none());
+        assert!(jobs.get(&running).is_some());
+    }
+}
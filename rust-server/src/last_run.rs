@@ -0,0 +1,120 @@
+use crate::logging::{log_to_file, Level};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// プロバイダーごとの直近のダウンロード結果（GET /api/last-run）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastRun {
+    pub last_success_at: Option<DateTime<Local>>,
+    pub last_failure_at: Option<DateTime<Local>>,
+    /// 最後に終わったダウンロードの status（success / error / timeout）
+    pub last_status: Option<String>,
+    pub last_message: Option<String>,
+    /// 最後に成功したダウンロードで保存したファイル数
+    pub file_count: usize,
+}
+
+/// 永続化される直近の結果（data/last_run.json、キーはプロバイダー名）
+pub struct LastRunStore {
+    path: PathBuf,
+    runs: Mutex<BTreeMap<String, LastRun>>,
+}
+
+impl LastRunStore {
+    /// ファイルから読み込む（無ければ空）
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let runs = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("last_run 読み込み失敗: {} ({})", path.display(), e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("last_run の形式が不正です: {} ({})", path.display(), e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path,
+            runs: Mutex::new(runs),
+        })
+    }
+
+    /// 一時ファイルに書いてから置き換える
+    fn persist(&self, runs: &BTreeMap<String, LastRun>) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("last_run ディレクトリ作成失敗: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(runs)
+            .map_err(|e| format!("last_run シリアライズ失敗: {}", e))?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json).map_err(|e| format!("last_run 書き込み失敗: {}", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| format!("last_run 書き込み失敗: {}", e))
+    }
+
+    pub fn list(&self) -> BTreeMap<String, LastRun> {
+        self.runs.lock().unwrap().clone()
+    }
+
+    /// ダウンロードの終了を記録する（中止されたものは成功・失敗のどちらにも数えない）
+    pub fn record(
+        &self,
+        provider: &str,
+        finished_at: DateTime<Local>,
+        status: &str,
+        message: &str,
+        file_count: usize,
+    ) {
+        if status == "cancelled" {
+            return;
+        }
+        let mut runs = self.runs.lock().unwrap();
+        let run = runs.entry(provider.to_string()).or_default();
+        if status == "success" {
+            run.last_success_at = Some(finished_at);
+            run.file_count = file_count;
+        } else {
+            run.last_failure_at = Some(finished_at);
+        }
+        run.last_status = Some(status.to_string());
+        run.last_message = Some(message.to_string());
+        if let Err(e) = self.persist(&runs) {
+            log_to_file(Level::Error, &e);
+        }
+    }
+
+    /// 最後の成功から stale_after_days 日を超えたプロバイダーがあれば warn
+    ///
+    /// 一度も実行していないプロバイダーは判定しない。stale_after_days が 0 なら判定しない。
+    pub fn freshness(&self, now: DateTime<Local>, stale_after_days: u32) -> Freshness {
+        let runs = self.runs.lock().unwrap();
+        let cutoff = now - chrono::Duration::days(stale_after_days.into());
+        let stale: Vec<String> = runs
+            .iter()
+            .filter(|(_, run)| {
+                stale_after_days > 0 && run.last_success_at.is_none_or(|at| at < cutoff)
+            })
+            .map(|(provider, _)| provider.clone())
+            .collect();
+        Freshness {
+            status: if stale.is_empty() { "ok" } else { "warn" },
+            stale_after_days,
+            last_success_at: runs.values().filter_map(|run| run.last_success_at).max(),
+            stale_providers: stale,
+        }
+    }
+}
+
+/// /health・GET /api/status の freshness
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Freshness {
+    /// ok / warn
+    pub status: &'static str,
+    pub stale_after_days: u32,
+    /// すべてのプロバイダーで最も新しい成功
+    pub last_success_at: Option<DateTime<Local>>,
+    pub stale_providers: Vec<String>,
+}
@@ -0,0 +1,668 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// ログファイル名（logs/ 配下）
+const LOG_FILE_NAME: &str = "server.log";
+
+/// HTTP アクセスログ（server.log と同じ設定でローテーションする）
+const ACCESS_LOG_FILE_NAME: &str = "access.log";
+
+/// tail_lines で末尾から一度に読む大きさ
+const TAIL_CHUNK_BYTES: u64 = 8 * 1024;
+
+/// ログファイルが一時的に開けない（ウイルス対策ソフト等がロックしている）場合の試行回数と間隔
+const OPEN_ATTEMPTS: u32 = 3;
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// 保持するアーカイブ数のデフォルト（server.log.1 〜 server.log.5）
+const DEFAULT_MAX_ARCHIVES: u32 = 5;
+
+/// ローテーション閾値のデフォルト（10 MB）
+const DEFAULT_MAX_SIZE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// ログレベル（順序比較で最小レベル判定に使う）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+impl serde::Serialize for Level {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "DEBUG" => Ok(Level::Debug),
+            "INFO" => Ok(Level::Info),
+            "WARN" | "WARNING" => Ok(Level::Warn),
+            "ERROR" => Ok(Level::Error),
+            _ => Err(format!(
+                "不正なログレベルです: {} (DEBUG/INFO/WARN/ERROR)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Level {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value.parse()
+    }
+}
+
+/// 起動時に決定するログ設定
+pub struct LogSettings {
+    pub max_size_bytes: u64,
+    pub max_archives: u32,
+    pub min_level: Level,
+    /// インスタンス名（install --name で登録したサービス。各行に付ける）
+    pub instance: Option<String>,
+}
+
+static SETTINGS: OnceLock<LogSettings> = OnceLock::new();
+
+/// 最小レベル（POST /api/config/reload で変更できるよう SETTINGS とは別に持つ）
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// ローテーションと追記を直列化するためのロック
+static LOG_LOCK: Mutex<()> = Mutex::new(());
+
+/// ログ設定を反映（起動時に 1 回だけ有効）
+pub fn init(settings: LogSettings) {
+    let min_level = settings.min_level;
+    if SETTINGS.set(settings).is_ok() {
+        set_min_level(min_level);
+    }
+}
+
+/// 最小レベルを変更する（設定の再読み込み用）
+pub fn set_min_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// DENCHO_LOG_LEVEL 環境変数からの最小レベル（不正値は警告して無視）
+pub fn min_level_from_env() -> Option<Level> {
+    let value = std::env::var("DENCHO_LOG_LEVEL").ok()?;
+    match value.parse() {
+        Ok(level) => Some(level),
+        Err(e) => {
+            eprintln!("⚠ DENCHO_LOG_LEVEL: {}", e);
+            None
+        }
+    }
+}
+
+fn min_level() -> Level {
+    match MIN_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Debug,
+        1 => Level::Info,
+        2 => Level::Warn,
+        _ => Level::Error,
+    }
+}
+
+/// ログディレクトリ（アプリケーションルートの logs/、取得できなければカレント）
+#[cfg(not(test))]
+pub fn log_dir() -> PathBuf {
+    crate::get_application_root()
+        .map(|p| p.join("logs"))
+        .unwrap_or_else(|_| PathBuf::from("."))
+}
+
+/// テストではリポジトリに logs/ を作らない
+#[cfg(test)]
+pub fn log_dir() -> PathBuf {
+    std::env::temp_dir().join("dencho-cli-test-logs")
+}
+
+pub fn log_file_path() -> PathBuf {
+    log_dir().join(LOG_FILE_NAME)
+}
+
+pub fn log_to_file(level: Level, message: &str) {
+    if level < min_level() {
+        return;
+    }
+
+    // 認証情報がどの経路から混ざっても書き出さない
+    let log_line = format_line(level, &crate::redact::redact_known(message));
+
+    // コンソールにも出力
+    print!("{}", log_line);
+
+    append(LOG_FILE_NAME, &log_line);
+}
+
+/// logs/access.log に 1 行追記する（コンソールには出さない）
+pub fn write_access_log(message: &str) {
+    append(
+        ACCESS_LOG_FILE_NAME,
+        &format!("[{}] {}\n", chrono_lite_timestamp(), message),
+    );
+}
+
+/// logs/ のファイルに追記する（必要ならローテーションしてから）
+fn append(file_name: &str, line: &str) {
+    let log_dir = log_dir();
+    let _ = std::fs::create_dir_all(&log_dir);
+    let log_file = log_dir.join(file_name);
+
+    let _guard = LOG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let (max_size, max_archives) = SETTINGS
+        .get()
+        .map_or((DEFAULT_MAX_SIZE_BYTES, DEFAULT_MAX_ARCHIVES), |s| {
+            (s.max_size_bytes, s.max_archives)
+        });
+    let _ = rotate_if_needed(&log_file, max_size, max_archives);
+
+    let _ = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_file)
+        .and_then(|mut f| std::io::Write::write_all(&mut f, line.as_bytes()));
+}
+
+/// server.log・access.log を空にし、ローテーション済みファイルを削除する（削除・切り詰めたファイル数を返す）
+pub fn clear_logs() -> std::io::Result<usize> {
+    let _guard = LOG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut cleared = 0;
+    for file_name in [LOG_FILE_NAME, ACCESS_LOG_FILE_NAME] {
+        let log_file = log_dir().join(file_name);
+        let prefix = format!("{}.", file_name);
+
+        if let Ok(entries) = std::fs::read_dir(log_dir()) {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let Some(name) = name.to_str() else { continue };
+                let is_archive = name
+                    .strip_prefix(&prefix)
+                    .is_some_and(|n| n.parse::<u32>().is_ok());
+                if is_archive {
+                    std::fs::remove_file(entry.path())?;
+                    cleared += 1;
+                }
+            }
+        }
+
+        if log_file.exists() {
+            // 実行中のサーバーが追記しても壊れないよう削除ではなく切り詰める
+            std::fs::OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(&log_file)?;
+            cleared += 1;
+        }
+    }
+
+    Ok(cleared)
+}
+
+/// server.log の末尾 n 行
+///
+/// ファイル全体は読まず、末尾から TAIL_CHUNK_BYTES ずつ n 行分の改行が見つかるまで読む。
+pub fn tail_lines(n: usize) -> std::io::Result<Vec<String>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = match std::fs::File::open(log_file_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut pos = file.metadata()?.len();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut newlines = 0;
+    // 最後の行の改行を含めて n + 1 個あれば、末尾 n 行は途中から読んだ行を含まない
+    while pos > 0 && newlines <= n {
+        let size = TAIL_CHUNK_BYTES.min(pos);
+        pos -= size;
+        file.seek(SeekFrom::Start(pos))?;
+        let mut chunk = vec![0; size as usize];
+        file.read_exact(&mut chunk)?;
+        newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+    }
+
+    let content = String::from_utf8_lossy(&buf);
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.len().saturating_sub(n);
+    Ok(lines[start..].iter().map(|l| l.to_string()).collect())
+}
+
+/// GET /api/logs で返すログ 1 件（ヘッダーの無い続きの行は直前のログにまとめる）
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// 行頭の日時（読み取れない古い形式の行は null）
+    pub timestamp: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub level: Option<Level>,
+    /// ログそのもの（複数行の場合は改行を含む、秘密情報は伏せる）
+    pub line: String,
+}
+
+/// GET /api/logs の絞り込み
+pub struct TailFilter {
+    /// 返す件数
+    pub lines: usize,
+    /// このレベル以上だけを返す
+    pub min_level: Option<Level>,
+    /// この日時以降だけを返す
+    pub since: Option<chrono::DateTime<chrono::Local>>,
+}
+
+/// server.log の末尾 max_bytes（足りなければ server.log.1 の末尾も）から、条件に合う最後の filter.lines 件を返す
+///
+/// 2 つのファイルはローテーションと同じロックの中で開くため、読んでいる途中にローテーションされても
+/// 行が抜けたり重複したりしない。読み込んだ範囲が途中から始まる場合は、最初の欠けた行を捨てる。
+pub fn tail_entries(filter: &TailFilter, max_bytes: u64) -> std::io::Result<Vec<LogEntry>> {
+    let log_file = log_file_path();
+    let (current, archive) = {
+        let _guard = LOG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let current = open_for_tail(&log_file)?;
+        let mut archive_name = log_file.as_os_str().to_owned();
+        archive_name.push(".1");
+        (current, open_for_tail(Path::new(&archive_name))?)
+    };
+
+    let (mut buf, mut complete) = match current {
+        Some(file) => read_last(file, max_bytes)?,
+        None => (Vec::new(), true),
+    };
+    let remaining = max_bytes.saturating_sub(buf.len() as u64);
+    if let (true, Some(file)) = (complete && remaining > 0, archive) {
+        let (mut older, older_complete) = read_last(file, remaining)?;
+        if !older.is_empty() && !older.ends_with(b"\n") {
+            older.push(b'\n');
+        }
+        older.extend_from_slice(&buf);
+        buf = older;
+        complete = older_complete;
+    }
+    if !complete {
+        let start = buf
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(buf.len(), |i| i + 1);
+        buf.drain(..start);
+    }
+
+    let content = String::from_utf8_lossy(&buf);
+    let mut entries: Vec<LogEntry> = Vec::new();
+    for line in content.lines() {
+        match (parse_header(line), entries.last_mut()) {
+            (None, Some(entry)) => {
+                entry.line.push('\n');
+                entry.line.push_str(line);
+            }
+            (header, _) => entries.push(LogEntry {
+                timestamp: header.map(|(timestamp, _)| timestamp),
+                level: header.map(|(_, level)| level),
+                line: line.to_string(),
+            }),
+        }
+    }
+
+    let mut matched: Vec<LogEntry> = entries
+        .into_iter()
+        .rev()
+        .filter(|entry| {
+            filter
+                .min_level
+                .is_none_or(|min| entry.level.is_some_and(|level| level >= min))
+                && filter
+                    .since
+                    .is_none_or(|since| entry.timestamp.is_some_and(|timestamp| timestamp >= since))
+        })
+        .take(filter.lines)
+        .map(|mut entry| {
+            entry.line = crate::redact::redact_known(&entry.line);
+            entry
+        })
+        .collect();
+    matched.reverse();
+    Ok(matched)
+}
+
+/// 読み込み用に開く（無ければ None、一時的に開けなければ少し待って再試行する）
+fn open_for_tail(path: &Path) -> std::io::Result<Option<std::fs::File>> {
+    let mut attempt = 1;
+    loop {
+        match std::fs::File::open(path) {
+            Ok(file) => return Ok(Some(file)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            // 32 は Windows の ERROR_SHARING_VIOLATION
+            Err(e)
+                if attempt < OPEN_ATTEMPTS
+                    && (e.kind() == std::io::ErrorKind::PermissionDenied
+                        || e.raw_os_error() == Some(32)) =>
+            {
+                std::thread::sleep(OPEN_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// ファイルの末尾 max_bytes を読む（ファイルの先頭から読めたかどうかも返す）
+///
+/// 読んでいる間に切り詰められた場合は、読めた分だけを返す。
+fn read_last(mut file: std::fs::File, max_bytes: u64) -> std::io::Result<(Vec<u8>, bool)> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    file.take(len - start).read_to_end(&mut buf)?;
+    Ok((buf, start == 0))
+}
+
+/// 行頭の `[日時] [レベル]` を読み取る（format_line の形式）
+fn parse_header(line: &str) -> Option<(chrono::DateTime<chrono::FixedOffset>, Level)> {
+    let (timestamp, rest) = line.strip_prefix('[')?.split_once("] [")?;
+    let (level, _) = rest.split_once(']')?;
+    Some((
+        chrono::DateTime::parse_from_rfc3339(timestamp).ok()?,
+        level.parse().ok()?,
+    ))
+}
+
+/// 1 行分のログ（例: [2024-01-31T09:15:00.123+09:00] [INFO] メッセージ）
+///
+/// インスタンス名がある場合はレベルの後に付ける（例: [INFO] [staging] メッセージ）。
+fn format_line(level: Level, message: &str) -> String {
+    // インスタンス名・リクエスト ID があればレベルの後に付ける
+    let mut tags = String::new();
+    if let Some(instance) = SETTINGS.get().and_then(|s| s.instance.as_deref()) {
+        tags.push_str(&format!(" [{}]", instance));
+    }
+    if let Some(request_id) = crate::request_id::current() {
+        tags.push_str(&format!(" [req:{}]", request_id));
+    }
+    format!(
+        "[{}] [{}]{} {}\n",
+        chrono_lite_timestamp(),
+        level.as_str(),
+        tags,
+        message
+    )
+}
+
+/// サイズが閾値を超えていれば server.log → server.log.1 → … と繰り下げ、
+/// max_archives を超えた古いものは削除する（0 の場合は退避せずに削除）
+fn rotate_if_needed(log_file: &Path, max_size: u64, max_archives: u32) -> std::io::Result<()> {
+    let size = match std::fs::metadata(log_file) {
+        Ok(meta) => meta.len(),
+        Err(_) => return Ok(()),
+    };
+    if max_size == 0 || size < max_size {
+        return Ok(());
+    }
+
+    let archive = |n: u32| {
+        let mut name = log_file.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+
+    if max_archives == 0 {
+        return std::fs::remove_file(log_file);
+    }
+
+    let _ = std::fs::remove_file(archive(max_archives));
+    for n in (1..max_archives).rev() {
+        let from = archive(n);
+        if from.exists() {
+            std::fs::rename(&from, archive(n + 1))?;
+        }
+    }
+    std::fs::rename(log_file, archive(1))
+}
+
+/// ログ用タイムスタンプ（ISO 8601 ローカル時刻、ミリ秒付き）
+fn chrono_lite_timestamp() -> String {
+    chrono::Local::now()
+        .format("%Y-%m-%dT%H:%M:%S%.3f%:z")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redact;
+    use crate::test_util::TempDir;
+
+    fn unique(label: &str) -> String {
+        format!("{}-{}", label, uuid::Uuid::new_v4().simple())
+    }
+
+    /// server.log のうち marker を含む行
+    fn logged_lines(marker: &str) -> Vec<String> {
+        std::fs::read_to_string(log_file_path())
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains(marker))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn lines_below_the_minimum_level_are_suppressed() {
+        let marker = unique("marker");
+        set_min_level(Level::Warn);
+        log_to_file(Level::Debug, &format!("{} debug", marker));
+        log_to_file(Level::Info, &format!("{} info", marker));
+        log_to_file(Level::Warn, &format!("{} warn", marker));
+        log_to_file(Level::Error, &format!("{} error", marker));
+        set_min_level(Level::Info);
+
+        let lines = logged_lines(&marker);
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert!(lines[0].contains("[WARN]"));
+        assert!(lines[1].contains("[ERROR]"));
+    }
+
+    #[test]
+    fn registered_secrets_are_masked_until_released() {
+        let marker = unique("marker");
+        let secret = unique("password");
+        let guard = redact::register([secret.clone()]);
+        log_to_file(Level::Error, &format!("{} login as {}", marker, secret));
+        drop(guard);
+        log_to_file(Level::Error, &format!("{} after {}", marker, secret));
+
+        let lines = logged_lines(&marker);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("login as ***"));
+        assert!(lines[1].ends_with(&format!("after {}", secret)));
+    }
+
+    #[test]
+    fn a_secret_shared_by_two_jobs_stays_masked_until_both_finish() {
+        let marker = unique("marker");
+        let secret = unique("password");
+        let first = redact::register([secret.clone()]);
+        let second = redact::register([secret.clone()]);
+        drop(first);
+        log_to_file(Level::Error, &format!("{} {}", marker, secret));
+        drop(second);
+
+        let lines = logged_lines(&marker);
+        assert_eq!(lines.len(), 1);
+        assert!(!lines[0].contains(&secret));
+    }
+
+    #[test]
+    fn tail_masks_secrets_registered_after_the_line_was_written() {
+        let marker = unique("marker");
+        let secret = unique("password");
+        log_to_file(Level::Error, &format!("{} {}", marker, secret));
+
+        let _guard = redact::register([secret.clone()]);
+        let filter = TailFilter {
+            lines: usize::MAX,
+            min_level: Some(Level::Error),
+            since: None,
+        };
+        let entries = tail_entries(&filter, 1024 * 1024).unwrap();
+        let entry = entries
+            .iter()
+            .find(|entry| entry.line.contains(&marker))
+            .unwrap();
+        assert!(!entry.line.contains(&secret));
+        assert_eq!(entry.level, Some(Level::Error));
+    }
+
+    /// s が pattern に一致するか（d は数字、± は + / -、それ以外は同じ文字）
+    fn matches_pattern(s: &str, pattern: &str) -> bool {
+        s.chars().count() == pattern.chars().count()
+            && s.chars().zip(pattern.chars()).all(|(c, p)| match p {
+                'd' => c.is_ascii_digit(),
+                '±' => c == '+' || c == '-',
+                _ => c == p,
+            })
+    }
+
+    #[test]
+    fn timestamp_is_iso_8601_local_time_with_milliseconds() {
+        // 桁数が固定なので、文字列の順序が時刻の順序と一致する
+        let timestamp = chrono_lite_timestamp();
+        assert!(
+            matches_pattern(&timestamp, "dddd-dd-ddTdd:dd:dd.ddd±dd:dd"),
+            "{}",
+            timestamp
+        );
+    }
+
+    /// dir/server.log と既存のアーカイブ（内容はファイル名）を作る
+    fn log_with_archives(dir: &TempDir, archives: u32) -> PathBuf {
+        let log_file = dir.path().join(LOG_FILE_NAME);
+        std::fs::write(&log_file, "current-").unwrap();
+        for n in 1..=archives {
+            std::fs::write(
+                dir.path().join(format!("{}.{}", LOG_FILE_NAME, n)),
+                n.to_string(),
+            )
+            .unwrap();
+        }
+        log_file
+    }
+
+    fn read(dir: &TempDir, name: &str) -> Option<String> {
+        std::fs::read_to_string(dir.path().join(name)).ok()
+    }
+
+    #[test]
+    fn rotation_starts_at_the_size_threshold() {
+        let dir = TempDir::new();
+        let log_file = log_with_archives(&dir, 0);
+
+        rotate_if_needed(&log_file, 9, 5).unwrap();
+        assert!(log_file.exists());
+        // 0 はローテーションしない
+        rotate_if_needed(&log_file, 0, 5).unwrap();
+        assert!(log_file.exists());
+
+        rotate_if_needed(&log_file, 8, 5).unwrap();
+        assert!(!log_file.exists());
+        assert_eq!(read(&dir, "server.log.1").as_deref(), Some("current-"));
+        // ファイルが無ければ何もしない
+        rotate_if_needed(&log_file, 8, 5).unwrap();
+    }
+
+    #[test]
+    fn archives_shift_down_and_the_oldest_is_dropped() {
+        let dir = TempDir::new();
+        let log_file = log_with_archives(&dir, 1);
+        rotate_if_needed(&log_file, 1, 3).unwrap();
+        assert_eq!(read(&dir, "server.log.1").as_deref(), Some("current-"));
+        assert_eq!(read(&dir, "server.log.2").as_deref(), Some("1"));
+
+        let dir = TempDir::new();
+        let log_file = log_with_archives(&dir, 3);
+        rotate_if_needed(&log_file, 1, 3).unwrap();
+        assert_eq!(read(&dir, "server.log.1").as_deref(), Some("current-"));
+        assert_eq!(read(&dir, "server.log.2").as_deref(), Some("1"));
+        assert_eq!(read(&dir, "server.log.3").as_deref(), Some("2"));
+        assert_eq!(read(&dir, "server.log.4"), None);
+    }
+
+    #[test]
+    fn zero_archives_deletes_instead_of_rotating() {
+        let dir = TempDir::new();
+        let log_file = log_with_archives(&dir, 0);
+        rotate_if_needed(&log_file, 1, 0).unwrap();
+        assert!(!log_file.exists());
+        assert_eq!(read(&dir, "server.log.1"), None);
+    }
+
+    #[test]
+    fn lines_start_with_an_iso_8601_timestamp_with_milliseconds() {
+        let line = format_line(Level::Info, "メッセージ");
+        let (timestamp, rest) = line.strip_prefix('[').unwrap().split_once("] ").unwrap();
+        assert_eq!(rest, "[INFO] メッセージ\n");
+        let parsed = chrono::DateTime::parse_from_rfc3339(timestamp).unwrap();
+        assert_eq!(
+            parsed.offset().local_minus_utc(),
+            chrono::Local::now().offset().local_minus_utc()
+        );
+        // 2024-01-31T09:15:00.123+09:00
+        let (_, fraction) = timestamp.split_once('.').unwrap();
+        assert!(fraction[..3].bytes().all(|b| b.is_ascii_digit()));
+        assert!(fraction[3..].starts_with(['+', '-', 'Z']));
+    }
+
+    #[tokio::test]
+    async fn parse_header_reads_back_formatted_lines() {
+        for level in [Level::Debug, Level::Info, Level::Warn, Level::Error] {
+            let line = format_line(level, "メッセージ");
+            let (timestamp, parsed_level) = parse_header(&line).unwrap();
+            assert_eq!(parsed_level, level);
+            assert!(
+                (chrono::Local::now() - timestamp.with_timezone(&chrono::Local)).num_seconds() < 5
+            );
+        }
+
+        let line = crate::request_id::scope("abc123".to_string(), async {
+            format_line(Level::Warn, "[タグ] メッセージ")
+        })
+        .await;
+        assert!(line.contains("[WARN] [req:abc123] [タグ] メッセージ"));
+        assert_eq!(parse_header(&line).unwrap().1, Level::Warn);
+
+        // 続きの行や古い形式（エポック秒）はヘッダーとして読まない
+        assert_eq!(parse_header("    at login (script.js:10)"), None);
+        assert_eq!(parse_header("[1699999999] サービス開始"), None);
+    }
+}
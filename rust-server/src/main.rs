@@ -1,18 +1,78 @@
+mod auth;
+mod clock;
+mod config;
+mod credentials;
+mod disk_space;
+mod env_cache;
+mod error_code;
+mod history;
+mod instance_lock;
+mod jobs;
+mod last_run;
+mod logging;
+mod metrics;
+mod outbox;
+mod output_dir;
+mod process;
+mod providers;
+mod provisioning;
+mod proxy;
+mod rate_limit;
+mod readiness;
+mod redact;
+mod rejection;
+mod request_id;
+mod retention;
+mod retry;
+mod schedule;
+mod script_output;
+mod security;
+mod service;
+mod setup;
+mod sink;
+#[cfg(test)]
+mod test_util;
+mod tls;
+mod webhook;
+
 use axum::{
-    extract::Json as ExtractJson,
-    http::{Method, StatusCode},
-    response::Json,
+    body::HttpBody,
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
     routing::{get, post},
     Router,
 };
+use error_code::ErrorCode;
+use logging::{log_to_file, Level};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::process::Command;
-use tower_http::cors::{Any, CorsLayer};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::AsyncBufReadExt;
+use tokio_stream::StreamExt;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// ダウンロードスクリプトに渡せるロケール（先頭がデフォルト）
+const SUPPORTED_LOCALES: &[&str] = &["ja-JP", "en-US", "en-GB"];
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct VersionResponse {
     version: String,
+    /// ビルド元のコミット（git が無い環境でビルドした場合は unknown）
+    git_commit: String,
+    built_at: Option<chrono::DateTime<chrono::Local>>,
+    /// 実行時に検出したバージョン（見つからなければ null）
+    node_version: Option<String>,
+    playwright_version: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,18 +81,293 @@ struct DownloadRequest {
     github_username: Option<String>,
     #[serde(rename = "githubPassword")]
     github_password: Option<String>,
+    /// 明示指定されたロケール（Accept-Language より優先）
+    locale: Option<String>,
+    /// true の場合、起動時チェック済みとみなしてリクエスト時の環境チェックを省略
+    #[serde(rename = "skipSetup", default)]
+    skip_setup: bool,
+    /// このリクエストだけスクリプトの実行時間の上限（秒）を変える
+    #[serde(rename = "timeoutSeconds")]
+    timeout_seconds: Option<u64>,
+    /// 実行するプロバイダー名（GET /api/providers の name、省略時は supabase）
+    provider: Option<String>,
+    /// 保存先フォルダ（download.output_base からの相対パス、省略時は downloads/invoice）
+    #[serde(rename = "outputDir")]
+    output_dir: Option<String>,
+    /// このリクエストだけ一時的なエラーの再試行回数を変える（0 で再試行しない）
+    retries: Option<u32>,
+    /// 呼び出し元アプリの識別子（ジョブ履歴に残す、history.persist_fields で除外できる）
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+    /// 任意のメモ（ジョブ履歴に残す、history.persist_fields で除外できる）
+    note: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct DownloadResponse {
+    /// pending / success / error / timeout / cancelled
+    status: String,
+    message: String,
+    #[serde(rename = "jobId", skip_serializing_if = "Option::is_none")]
+    job_id: Option<String>,
+    /// 機械判定用のエラー種別
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    /// 再試行までの目安（秒）
+    #[serde(rename = "retryAfter", skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    /// スクリプトを実行した回数（一時的なエラーの再試行を含む）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<u32>,
+    /// リクエストの outputDir から決まった保存先（絶対パス）
+    #[serde(rename = "outputDir", skip_serializing_if = "Option::is_none")]
+    output_dir: Option<String>,
+    /// 保存したファイル（成功時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<script_output::DownloadedFile>>,
+    /// files を正しく決められなかった理由（スクリプトの出力が不正など）
+    #[serde(rename = "filesWarning", skip_serializing_if = "Option::is_none")]
+    files_warning: Option<String>,
+    /// 出力先（S3 / Azure）にアップロードしたファイルの URL
+    #[serde(rename = "remoteUrls", skip_serializing_if = "Option::is_none")]
+    remote_urls: Option<Vec<String>>,
+    /// スクリプトの標準出力（末尾 OUTPUT_TAIL_BYTES まで）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout: Option<String>,
+    /// 失敗時のスクリプトの標準エラー出力（末尾 OUTPUT_TAIL_BYTES まで）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<String>,
+    /// ダウンロードを開始したリクエストの ID（ログの [req:...] と同じ）
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl DownloadResponse {
+    fn with_status(status: &str, message: impl Into<String>) -> Self {
+        Self {
+            status: status.to_string(),
+            message: message.into(),
+            job_id: None,
+            code: None,
+            retry_after: None,
+            attempts: None,
+            output_dir: None,
+            files: None,
+            files_warning: None,
+            remote_urls: None,
+            stdout: None,
+            stderr: None,
+            request_id: request_id::current(),
+        }
+    }
+
+    fn pending(message: impl Into<String>) -> Self {
+        Self::with_status("pending", message)
+    }
+
+    fn success(message: impl Into<String>) -> Self {
+        Self::with_status("success", message)
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self::with_status("error", message)
+    }
+
+    fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+}
+
+/// ハンドラ間で共有する状態
+struct AppState {
+    clock: Arc<dyn clock::Clock>,
+    env_cache: env_cache::EnvCache,
+    sink: sink::OutputSink,
+    outbox: Arc<outbox::Outbox>,
+    jobs: jobs::JobStore,
+    /// ジョブ履歴（[history] enabled が false なら None）
+    history: Option<history::HistoryStore>,
+    api_key: String,
+    posture: std::sync::RwLock<security::PostureInput>,
+    /// 起動時の設定（再起動が必要な変更の判定用）
+    startup_config: config::Config,
+    /// 最後に読み込んだ設定（POST /api/config/reload で更新）
+    config: std::sync::RwLock<config::Config>,
+    /// 再起動せずに変更できる設定
+    reloadable: std::sync::RwLock<ReloadableSettings>,
+    /// CORS の許可オリジン（DENCHO_CORS_ORIGINS 反映済み）
+    cors: std::sync::RwLock<config::CorsConfig>,
+    /// 定期実行するダウンロード（[[schedule]]）
+    schedules: std::sync::RwLock<Vec<schedule::Schedule>>,
+    /// schedules の変更をスケジューラーに伝える
+    schedules_changed: tokio::sync::Notify,
+    /// 保存済み請求書の保持ルール（[invoices] keep_days / keep_count）
+    retention: std::sync::RwLock<retention::Policy>,
+    /// 保持ルールによる削除をすぐに実行させる（ダウンロード成功後・設定変更時）
+    invoice_cleanup: tokio::sync::Notify,
+    /// 起動時の環境セットアップ（完了前でも待ち受けを始める）
+    setup: std::sync::Mutex<setup::SetupStatus>,
+    /// true の場合、ダウンロード API はエラーでも 200 を返す（status / code で判定させる）
+    always_200: bool,
+    rate_limiter: rate_limit::RateLimiter,
+    /// 実行中の node プロセス（終了時に止める）
+    children: process::ChildTracker,
+    stats: ServerStats,
+    /// GET /metrics で公開するダウンロードの統計
+    metrics: metrics::Metrics,
+    started_at: chrono::DateTime<chrono::Utc>,
+    port: u16,
+    /// インスタンス名（run --instance、install --name で登録したサービス名）
+    instance: Option<String>,
+    last_download: std::sync::Mutex<Option<LastDownload>>,
+    /// プロバイダーごとの直近の成功・失敗（data/last_run.json）
+    last_runs: last_run::LastRunStore,
+    readiness: readiness::ReadinessCache,
+    /// GET /api/invoices で一覧するフォルダ
+    invoices_dir: PathBuf,
+    /// デフォルトのダウンロードスクリプトの絶対パス（環境チェック用）
+    script_path: PathBuf,
+    /// POST /api/download の provider で選べるスクリプト
+    providers: providers::ProviderRegistry,
+    /// 実行中のダウンロード（出力先ディレクトリを共有するため同時に 1 つまで）
+    download_lock: Arc<tokio::sync::Mutex<()>>,
+    /// 実行中のダウンロードの内容（重複判定と 409 のジョブ ID 用）
+    running_download: std::sync::Mutex<Option<RunningDownload>>,
+    /// true の場合、認証情報を標準入力ではなく環境変数でスクリプトに渡す（旧スクリプト用）
+    legacy_env_credentials: bool,
+    /// スクリプトが使う Playwright ブラウザ
+    browser: config::PlaywrightBrowser,
+    /// 環境セットアップで確認する Node.js のメジャーバージョン
+    min_node_version: u32,
+    /// 環境セットアップで使う npm レジストリ・Playwright のダウンロード元
+    mirrors: setup::Mirrors,
+    /// npm・npx・スクリプトに渡すプロキシ
+    proxy: proxy::Proxy,
+    /// GET /api/logs で読み込む・返す大きさの上限（log.api_max_bytes）
+    log_api_max_bytes: u64,
+    /// true の場合、環境セットアップでインストールせず、事前に配置されているか確認するだけにする
+    offline: bool,
+    /// true の間は新しいダウンロードを受け付けない（POST /api/pause・/api/resume）
+    paused: AtomicBool,
+    /// ダウンロード終了の通知先（[[webhooks]]・DENCHO_WEBHOOK_URL）
+    webhooks: Option<webhook::Webhooks>,
+    /// ループバック以外で待ち受けている場合は、リクエストの送信元をログに残す
+    /// （[rate_limit.client] も送信元 IP ごとに数える）
+    log_remote_addr: bool,
+}
+
+/// POST /api/config/reload で変更できる設定
+#[derive(Clone, Copy)]
+struct ReloadableSettings {
+    /// スクリプトの実行時間の上限
+    download_timeout: std::time::Duration,
+    /// 一時的なエラーで失敗したスクリプトの再試行
+    retry: retry::RetryPolicy,
+    /// 実行中と異なるパラメータのダウンロードを順番待ちにするか
+    queue_downloads: bool,
+}
+
+#[derive(Clone)]
+struct RunningDownload {
+    /// プロファイル・ロケール・skipSetup から作る重複判定キー
+    fingerprint: String,
+    job_id: Option<String>,
+}
+
+impl AppState {
+    fn now_local(&self) -> chrono::DateTime<chrono::Local> {
+        self.clock.now().with_timezone(&chrono::Local)
+    }
+
+    /// ファイルの更新日時と比べる用の現在時刻
+    fn now_system(&self) -> SystemTime {
+        self.clock.now().into()
+    }
+
+    /// started_at からの経過時間（時計が戻った場合は None）
+    fn elapsed_since(&self, started_at: SystemTime) -> Option<std::time::Duration> {
+        self.now_system().duration_since(started_at).ok()
+    }
+
+    fn settings(&self) -> ReloadableSettings {
+        *self.reloadable.read().unwrap()
+    }
+}
+
+/// 直近のダウンロード結果（GET /api/status 用）
+#[derive(Clone, Serialize)]
+struct LastDownload {
+    #[serde(rename = "finishedAt")]
+    finished_at: chrono::DateTime<chrono::Local>,
+    /// success / error
     status: String,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+}
+
+/// 終了時に表示する統計
+#[derive(Default)]
+struct ServerStats {
+    requests: AtomicU64,
+    downloads: AtomicU64,
+}
+
+/// ダウンロード実行中に保持するロック（ドロップで解放）
+struct DownloadGuard {
+    state: Arc<AppState>,
+    _lock: tokio::sync::OwnedMutexGuard<()>,
+}
+
+impl DownloadGuard {
+    fn new(
+        state: &Arc<AppState>,
+        lock: tokio::sync::OwnedMutexGuard<()>,
+        fingerprint: String,
+    ) -> Self {
+        *state.running_download.lock().unwrap() = Some(RunningDownload {
+            fingerprint,
+            job_id: None,
+        });
+        state.metrics.download_started();
+        Self {
+            state: state.clone(),
+            _lock: lock,
+        }
+    }
+
+    /// 順番が来るまで待ってロックを取る
+    async fn acquire(state: &Arc<AppState>, fingerprint: String) -> Self {
+        let lock = state.download_lock.clone().lock_owned().await;
+        Self::new(state, lock, fingerprint)
+    }
+
+    fn set_job_id(&self, job_id: &str) {
+        if let Some(running) = self.state.running_download.lock().unwrap().as_mut() {
+            running.job_id = Some(job_id.to_string());
+        }
+    }
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        *self.state.running_download.lock().unwrap() = None;
+        self.state.metrics.download_stopped();
+    }
+}
+
+/// ダウンロードの実行枠
+enum DownloadSlot {
+    Acquired(DownloadGuard),
+    /// 実行中のダウンロードの終了を待つ（重複判定キーを持つ）
+    Queued(String),
 }
 
 /// アプリケーションルートディレクトリを検出
 fn get_application_root() -> Result<PathBuf, String> {
-    let exe_path = std::env::current_exe()
-        .map_err(|e| format!("実行ファイルパス取得失敗: {}", e))?;
+    let exe_path =
+        std::env::current_exe().map_err(|e| format!("実行ファイルパス取得失敗: {}", e))?;
 
     let exe_dir = exe_path
         .parent()
@@ -49,256 +384,5682 @@ fn get_application_root() -> Result<PathBuf, String> {
     }
 
     // 開発モード: カレントディレクトリにフォールバック
-    let cwd = std::env::current_dir()
-        .map_err(|e| format!("カレントディレクトリ取得失敗: {}", e))?;
+    let cwd =
+        std::env::current_dir().map_err(|e| format!("カレントディレクトリ取得失敗: {}", e))?;
 
     println!("🔧 開発ディレクトリから実行: {}", cwd.display());
     Ok(cwd)
 }
 
-fn log_to_file(message: &str) {
-    let log_dir = get_application_root()
-        .map(|p| p.join("logs"))
-        .unwrap_or_else(|_| PathBuf::from("."));
+/// スクリプト実行タイムアウトを決定（DENCHO_DOWNLOAD_TIMEOUT（秒） > dencho.toml）
+fn resolve_download_timeout(config_secs: u64) -> Result<std::time::Duration, String> {
+    let secs = match std::env::var("DENCHO_DOWNLOAD_TIMEOUT") {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or_else(|| format!("DENCHO_DOWNLOAD_TIMEOUT が不正です: {}", value))?,
+        _ if config_secs > 0 => config_secs,
+        _ => return Err("download.timeout_secs は 1 以上を指定してください".to_string()),
+    };
+    Ok(std::time::Duration::from_secs(secs))
+}
 
-    let _ = std::fs::create_dir_all(&log_dir);
-    let log_file = log_dir.join("server.log");
+/// 再試行の設定を決定（DENCHO_DOWNLOAD_RETRIES / DENCHO_DOWNLOAD_RETRY_BASE_SECS > dencho.toml）
+fn resolve_retry_policy(config: &config::DownloadConfig) -> Result<retry::RetryPolicy, String> {
+    fn env_number<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+        match std::env::var(name) {
+            Ok(value) if !value.trim().is_empty() => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{} が不正です: {}", name, value)),
+            _ => Ok(default),
+        }
+    }
+    Ok(retry::RetryPolicy {
+        retries: env_number("DENCHO_DOWNLOAD_RETRIES", config.retries)?,
+        base_delay: std::time::Duration::from_secs(env_number(
+            "DENCHO_DOWNLOAD_RETRY_BASE_SECS",
+            config.retry_base_secs,
+        )?),
+    })
+}
 
-    let timestamp = chrono_lite_timestamp();
-    let log_line = format!("[{}] {}\n", timestamp, message);
+/// DENCHO_PLAYWRIGHT_BROWSER > download.browser
+fn resolve_playwright_browser(
+    config_browser: config::PlaywrightBrowser,
+) -> Result<config::PlaywrightBrowser, String> {
+    match std::env::var("DENCHO_PLAYWRIGHT_BROWSER") {
+        Ok(value) if !value.trim().is_empty() => value
+            .parse()
+            .map_err(|e| format!("DENCHO_PLAYWRIGHT_BROWSER: {}", e)),
+        _ => Ok(config_browser),
+    }
+}
 
-    // コンソールにも出力
-    print!("{}", log_line);
+/// DENCHO_NPM_REGISTRY > download.npm_registry、PLAYWRIGHT_DOWNLOAD_HOST > download.playwright_download_host
+fn resolve_setup_mirrors(config: &config::DownloadConfig) -> Result<setup::Mirrors, String> {
+    setup::Mirrors::resolve(
+        config.npm_registry.as_deref(),
+        config.playwright_download_host.as_deref(),
+    )
+}
 
-    let _ = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_file)
-        .and_then(|mut f| std::io::Write::write_all(&mut f, log_line.as_bytes()));
+/// DENCHO_SCRIPT_PATH（アプリケーションルートからの相対パス、絶対パスも可）
+fn script_path_from_env() -> Option<PathBuf> {
+    std::env::var("DENCHO_SCRIPT_PATH")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| PathBuf::from(value.trim()))
 }
 
-fn chrono_lite_timestamp() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    format!("{}", now)
+/// オフラインモードか（DENCHO_OFFLINE（1 / true / yes / on） > download.offline）
+fn resolve_offline(config_offline: bool) -> Result<bool, String> {
+    match std::env::var("DENCHO_OFFLINE") {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "" => Ok(config_offline),
+            "0" | "false" | "no" | "off" => Ok(false),
+            "1" | "true" | "yes" | "on" => Ok(true),
+            _ => Err(format!("DENCHO_OFFLINE が不正です: {}", value)),
+        },
+        Err(_) => Ok(config_offline),
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let args: Vec<String> = std::env::args().collect();
+/// DENCHO_ALWAYS_200 が有効か（1 / true / yes / on）
+fn always_200_from_env() -> Result<bool, String> {
+    match std::env::var("DENCHO_ALWAYS_200") {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "" | "0" | "false" | "no" | "off" => Ok(false),
+            "1" | "true" | "yes" | "on" => Ok(true),
+            _ => Err(format!("DENCHO_ALWAYS_200 が不正です: {}", value)),
+        },
+        Err(_) => Ok(false),
+    }
+}
 
-    // "run" 引数があってもなくても同じ動作（互換性のため）
-    if args.len() > 1 && args[1] != "run" {
-        println!("使用方法: dencho-cli.exe [run]");
-        println!("  run  サーバーを起動します（デフォルト）");
-        return;
+/// 待ち受けアドレスを決定（--addr > DENCHO_LISTEN_ADDR > dencho.toml > デフォルト）
+fn resolve_listen_addr(
+    cli_addr: Option<&str>,
+    server: &config::ServerConfig,
+) -> Result<SocketAddr, String> {
+    let raw = match cli_addr {
+        Some(addr) => addr.to_string(),
+        None => std::env::var("DENCHO_LISTEN_ADDR")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| server.listen_addr()),
+    };
+
+    raw.trim()
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("待ち受けアドレスが不正です: {} ({})", raw, e))
+}
+
+/// --port が指定されていれば待ち受けアドレスのポートだけを置き換える
+fn apply_cli_port(mut addr: SocketAddr, cli_port: Option<&str>) -> Result<SocketAddr, String> {
+    if let Some(port) = cli_port {
+        let port = port
+            .trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|port| *port > 0)
+            .ok_or_else(|| format!("ポート番号が不正です: {}", port))?;
+        addr.set_port(port);
     }
+    Ok(addr)
+}
 
-    println!("=== dencho-cli サーバー ===");
+fn print_usage() {
+    println!(
+        "使用方法: dencho-cli.exe [run] [--addr <IP:PORT>] [--port <PORT>] [--instance <NAME>]"
+    );
+    println!("                         [--foreground] [--regenerate-cert]");
+    println!("          dencho-cli.exe api-key");
+    println!("          dencho-cli.exe --version");
+    println!("          dencho-cli.exe logs (--tail <N> | --clear)");
+    println!("          dencho-cli.exe status [--addr <IP:PORT>] [--port <PORT>]");
+    println!("          dencho-cli.exe setup [--force-browsers] [--skip-npm] [--offline]");
+    println!("          dencho-cli.exe cleanup [--dry-run] [--addr <IP:PORT>] [--port <PORT>]");
+    println!(
+        "          dencho-cli.exe install [--name <NAME>] [--display-name <TEXT>] [--port <PORT>] [--no-recovery] [--system] [--apply]"
+    );
+    println!("          dencho-cli.exe uninstall [--name <NAME>] [--system] [--apply]");
+    println!("          dencho-cli.exe credentials (set <USER> | get [--reveal] | delete)");
+    println!("          dencho-cli.exe provisioning encrypt <INPUT> [<OUTPUT>]");
+    println!("  run              サーバーを起動します（デフォルト）");
+    println!("  api-key          API キーを表示します（未生成なら生成）");
+    println!("  --version        バージョンを表示します");
+    println!("  logs --tail <N>  server.log の末尾 N 行を表示します");
+    println!("  logs --clear     server.log を空にし、ローテーション済みログを削除します");
+    println!("  status           起動中のサーバーの稼働状態を表示します（正常なら終了コード 0）");
+    println!("  setup            環境セットアップ（npm install・Playwright ブラウザ）を実行します");
+    println!("  cleanup          保持ルール（[invoices] keep_days / keep_count）を超えた請求書を削除します");
+    println!("    --dry-run         削除せず、削除するファイルを表示します");
+    println!("    --force-browsers  ブラウザがあってもインストールし直します");
+    println!("    --skip-npm        npm install を省略します");
+    println!("    --offline         インストールせず、node_modules とブラウザがあるか確認します");
+    println!("  install          systemd ユニット / launchd plist を生成します（Linux / macOS）");
+    println!("    --name <NAME>       サービス名（デフォルト: dencho-cli、複数インスタンス用）");
+    println!("    --display-name <TEXT> 表示名（systemd の Description）");
+    println!("    --port <PORT>       サービスの待ち受けポート");
+    println!("    --no-recovery       異常終了時に自動で再起動しません");
+    println!("    --system            ユーザー単位ではなくシステム全体に登録します（root で実行）");
+    println!("    --apply             表示する登録・解除コマンドを実行します");
+    println!(
+        "  uninstall        install で生成した定義ファイルを削除します（--name で対象を指定）"
+    );
+    println!(
+        "  credentials      GitHub 認証情報を Windows 資格情報マネージャーに保存・確認・削除します"
+    );
+    println!("  --addr <IP:PORT> 待ち受けアドレス（環境変数 DENCHO_LISTEN_ADDR でも指定可）");
+    println!("  --port <PORT>    待ち受けポート（待ち受けアドレスのポートだけを置き換えます）");
+    println!("  --instance <NAME> インスタンス名（/health・/api/status・ログに表示）");
+    println!("  --regenerate-cert server.tls の自己署名証明書を作り直して起動します");
+    println!(
+        "  --foreground     サービスマネージャー用（常にフォアグラウンドで動作し、SIGTERM で停止）"
+    );
+    println!("  provisioning encrypt");
+    println!(
+        "                   プロビジョニングファイルを {} のパスフレーズで暗号化します",
+        provisioning::PASSPHRASE_ENV
+    );
+    println!("                   （出力先の省略時は <INPUT>.enc）");
+    println!();
+    println!(
+        "設定ファイル: <アプリケーションルート>\\{}",
+        config::CONFIG_FILE_NAME
+    );
+}
 
-    if let Err(e) = check_and_setup_environment() {
-        eprintln!("❌ 環境セットアップエラー: {}", e);
-        std::process::exit(1);
+/// 許可リストに一致するロケールを返す（完全一致 > 言語部分一致）
+fn match_supported_locale(tag: &str) -> Option<&'static str> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag == "*" {
+        return None;
+    }
+
+    if let Some(exact) = SUPPORTED_LOCALES
+        .iter()
+        .find(|l| l.eq_ignore_ascii_case(tag))
+    {
+        return Some(exact);
     }
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(Any);
+    let lang = tag.split('-').next().unwrap_or(tag);
+    SUPPORTED_LOCALES
+        .iter()
+        .find(|l| {
+            l.split('-')
+                .next()
+                .is_some_and(|p| p.eq_ignore_ascii_case(lang))
+        })
+        .copied()
+}
 
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/api/version", get(get_version))
-        .route("/api/download", post(download_invoice))
-        .layer(cors);
+/// リクエストからロケールを決定（locale フィールド > Accept-Language > デフォルト）
+fn resolve_locale(explicit: Option<&str>, headers: &HeaderMap) -> Result<&'static str, String> {
+    if let Some(locale) = explicit.filter(|l| !l.trim().is_empty()) {
+        return match_supported_locale(locale)
+            .ok_or_else(|| format!("サポートされていないロケールです: {}", locale));
+    }
 
-    let addr = "127.0.0.1:3939";
-    println!("✓ サーバー起動完了: http://{}", addr);
-    println!("  ウィンドウを閉じるとサーバーが停止します\n");
+    let accept_language = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // q 値の高い順に評価
+    let mut candidates: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim();
+            let q = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (q > 0.0).then_some((tag, q))
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    Ok(candidates
+        .iter()
+        .find_map(|(tag, _)| match_supported_locale(tag))
+        .unwrap_or(SUPPORTED_LOCALES[0]))
 }
 
-async fn health_check() -> Json<serde_json::Value> {
-    Json(serde_json::json!({ "status": "ok" }))
+/// logs サブコマンド（終了コードを返す）
+fn run_logs_command(args: &[&str]) -> i32 {
+    match args {
+        ["--clear"] => match logging::clear_logs() {
+            Ok(count) => {
+                println!(
+                    "✓ ログをクリアしました: {} ({} ファイル)",
+                    logging::log_dir().display(),
+                    count
+                );
+                0
+            }
+            Err(e) => {
+                eprintln!("❌ ログのクリアに失敗しました: {}", e);
+                1
+            }
+        },
+        ["--tail", n] => {
+            let Ok(n) = n.parse::<usize>() else {
+                print_usage();
+                return 2;
+            };
+            match logging::tail_lines(n) {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("❌ ログの読み込みに失敗しました: {}", e);
+                    1
+                }
+            }
+        }
+        _ => {
+            print_usage();
+            2
+        }
+    }
 }
 
-async fn get_version() -> Json<VersionResponse> {
-    Json(VersionResponse {
-        version: env!("CARGO_PKG_VERSION").to_string(),
-    })
+/// 設定から求めた、このアプリケーションルートのサーバーの接続先（status / cleanup サブコマンド用）
+struct LocalServer {
+    /// 待ち受けポート（起動ロックのファイル名に使われる）
+    port: u16,
+    base_url: String,
+    client: reqwest::Client,
 }
 
-async fn download_invoice(
-    ExtractJson(payload): ExtractJson<DownloadRequest>,
-) -> (StatusCode, Json<DownloadResponse>) {
-    log_to_file("ダウンロードリクエスト受信");
+fn local_server(
+    app_root: &std::path::Path,
+    config: &config::Config,
+    cli_addr: Option<&str>,
+    cli_port: Option<&str>,
+) -> Result<LocalServer, String> {
+    let addr = resolve_listen_addr(cli_addr, &config.server)
+        .and_then(|addr| apply_cli_port(addr, cli_port))?;
+    let tls = tls::resolve(&config.server, app_root)?.is_some();
+    // 0.0.0.0 などで待ち受けている場合はループバックで確認する
+    let connect = if addr.ip().is_unspecified() {
+        SocketAddr::new(std::net::Ipv4Addr::LOCALHOST.into(), addr.port())
+    } else {
+        addr
+    };
+    // 自己署名の証明書でも確認できるよう、証明書は検証しない（接続先は自分のサーバー）
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .danger_accept_invalid_certs(tls)
+        .build()
+        .map_err(|e| format!("HTTP クライアントの作成に失敗しました: {}", e))?;
+    Ok(LocalServer {
+        port: addr.port(),
+        base_url: format!("{}://{}", if tls { "https" } else { "http" }, connect),
+        client,
+    })
+}
 
+/// status サブコマンド（起動中のサーバーの /health と /api/status を表示する）
+///
+/// /health が ok を返した場合だけ終了コード 0（スクリプトからの死活確認用）。
+async fn run_status_command(args: &[&str]) -> i32 {
+    let mut cli_addr = None;
+    let mut cli_port = None;
+    for pair in args.chunks(2) {
+        match pair {
+            ["--addr", addr] => cli_addr = Some(*addr),
+            // install --port で登録したインスタンスの確認用
+            ["--port", port] => cli_port = Some(*port),
+            _ => {
+                print_usage();
+                return 2;
+            }
+        }
+    }
     let app_root = match get_application_root() {
-        Ok(path) => path,
+        Ok(root) => root,
         Err(e) => {
-            log_to_file(&format!("アプリケーションルート取得エラー: {}", e));
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(DownloadResponse {
-                    status: "error".to_string(),
-                    message: format!("環境設定エラー: {}", e),
-                }),
-            );
+            eprintln!("❌ {}", e);
+            return 1;
         }
     };
+    let server = match config::load_config(&app_root)
+        .and_then(|config| local_server(&app_root, &config, cli_addr, cli_port))
+    {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 1;
+        }
+    };
+    let LocalServer {
+        base_url, client, ..
+    } = server;
 
-    let script_path = app_root.join("dist").join("download-supabase-invoice.js");
-
-    if !script_path.exists() {
-        log_to_file(&format!("スクリプトが見つかりません: {}", script_path.display()));
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(DownloadResponse {
-                status: "error".to_string(),
-                message: format!("スクリプトファイルが見つかりません: {}", script_path.display()),
-            }),
+    println!("サーバー: {}", base_url);
+    let health = match client.get(format!("{}/health", base_url)).send().await {
+        Ok(response) => {
+            let code = response.status();
+            let body = response
+                .bytes()
+                .await
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok());
+            Some((code, body))
+        }
+        Err(e) => {
+            println!("  HTTP 応答: なし ({})", e);
+            None
+        }
+    };
+    let Some((code, body)) = health else {
+        return 1;
+    };
+    let body = body.unwrap_or_default();
+    // warn（しばらく成功していないプロバイダーがある）はダウンロードできるため正常とする
+    let healthy = code.is_success() && (body["status"] == "ok" || body["status"] == "warn");
+    println!(
+        "  HTTP 応答: あり ({}, status={})",
+        code.as_u16(),
+        body["status"].as_str().unwrap_or("不明")
+    );
+    if let Some(instance) = body["instance"].as_str() {
+        println!("  インスタンス: {}", instance);
+    }
+    if body["paused"] == true {
+        println!("  ダウンロード: 一時停止中");
+    }
+    if let Some(checks) = body["checks"].as_object() {
+        println!("  環境チェック:");
+        for (name, value) in checks {
+            println!("    {}: {}", name, value);
+        }
+    }
+    if let Some(stale) = body["freshness"]["staleProviders"]
+        .as_array()
+        .filter(|stale| !stale.is_empty())
+    {
+        println!(
+            "  ⚠ {} 日以上成功していないプロバイダー: {}",
+            body["freshness"]["staleAfterDays"],
+            stale
+                .iter()
+                .filter_map(|provider| provider.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
         );
     }
 
-    let mut cmd = Command::new("node");
-    cmd.arg(&script_path).current_dir(&app_root);
+    // /api/status は API キーが必要（保存済みのキーが無ければ省略）
+    let api_key = auth::api_key_from_env()
+        .or_else(|| std::fs::read_to_string(auth::api_key_path(&app_root)).ok());
+    if let Some(api_key) = api_key {
+        let status = client
+            .get(format!("{}/api/status", base_url))
+            .header("X-Api-Key", api_key.trim())
+            .send()
+            .await
+            .ok()
+            .filter(|response| response.status().is_success());
+        if let Some(response) = status {
+            let status = response
+                .bytes()
+                .await
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+                .unwrap_or_default();
+            println!(
+                "  バージョン: {} (mode={}, 稼働 {} 秒)",
+                status["version"].as_str().unwrap_or("不明"),
+                status["mode"].as_str().unwrap_or("不明"),
+                status["uptimeSeconds"]
+            );
+            match status["lastDownload"].as_object() {
+                Some(last) => println!(
+                    "  直近のダウンロード: {} ({}) {}",
+                    last.get("status").and_then(|v| v.as_str()).unwrap_or(""),
+                    last.get("finishedAt")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or(""),
+                    last.get("message").and_then(|v| v.as_str()).unwrap_or("")
+                ),
+                None => println!("  直近のダウンロード: なし"),
+            }
+        }
+    }
 
-    // Playwright ブラウザパスを設定
-    let appdata = std::env::var("APPDATA").unwrap_or_else(|_| {
-        std::env::var("HOME").unwrap_or_else(|_| ".".to_string())
-    });
-    let browsers_path = std::path::Path::new(&appdata)
-        .join("dencho-cli")
-        .join("browsers");
-    cmd.env("PLAYWRIGHT_BROWSERS_PATH", &browsers_path);
+    if healthy {
+        0
+    } else {
+        1
+    }
+}
 
-    if let Some(username) = payload.github_username {
-        if !username.is_empty() {
-            cmd.env("GITHUB_USERNAME", username);
+/// cleanup サブコマンド（保持ルールを超えた請求書を削除する）
+///
+/// サーバーが起動中ならダウンロードと競合しないよう POST /api/invoices/cleanup に任せ、
+/// 起動していなければ起動ロックを取ってから直接削除する。
+async fn run_cleanup_command(args: &[&str]) -> i32 {
+    let mut dry_run = false;
+    let mut cli_addr = None;
+    let mut cli_port = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match (*arg, rest.clone().next()) {
+            ("--dry-run", _) => dry_run = true,
+            ("--addr", Some(addr)) => {
+                cli_addr = Some(*addr);
+                rest.next();
+            }
+            ("--port", Some(port)) => {
+                cli_port = Some(*port);
+                rest.next();
+            }
+            _ => {
+                print_usage();
+                return 2;
+            }
         }
     }
-    if let Some(password) = payload.github_password {
-        if !password.is_empty() {
-            cmd.env("GITHUB_PASSWORD", password);
+    let app_root = match get_application_root() {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 1;
+        }
+    };
+    let prepared = config::load_config(&app_root).and_then(|config| {
+        let server = local_server(&app_root, &config, cli_addr, cli_port)?;
+        Ok((config, server))
+    });
+    let (config, server) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 1;
+        }
+    };
+
+    let result = match instance_lock::acquire(&app_root, server.port) {
+        Ok(_lock) => retention::Policy::from_config(&config.invoices).map(|policy| {
+            let dir = app_root.join(&config.invoices.dir);
+            retention::run(
+                &dir,
+                policy,
+                clock::Clock::now(&clock::TokioClock).into(),
+                dry_run,
+            )
+        }),
+        Err(instance_lock::LockError::AlreadyRunning { .. }) => {
+            println!(
+                "サーバーが起動中のため、{} に削除を依頼します",
+                server.base_url
+            );
+            request_invoice_cleanup(&app_root, &server, dry_run).await
+        }
+        Err(instance_lock::LockError::Failed(e)) => Err(e),
+    };
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 1;
         }
+    };
+
+    if report.keep_days.is_none() && report.keep_count.is_none() {
+        println!("保持ルール ([invoices] keep_days / keep_count) が設定されていません");
+        return 0;
+    }
+    let verb = if report.dry_run {
+        "削除予定"
+    } else {
+        "削除"
+    };
+    for file in &report.files {
+        println!(
+            "  {}: {} ({} バイト, 更新 {})",
+            verb,
+            file.name,
+            file.size_bytes,
+            file.modified_at.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    println!(
+        "{}: {} 件 ({} バイト)",
+        verb,
+        report.files.len(),
+        report.freed_bytes
+    );
+    for error in &report.errors {
+        eprintln!("  ⚠ {}", error);
     }
+    if report.errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
 
-    let output = cmd.output();
+/// 起動中のサーバーに POST /api/invoices/cleanup を送る
+async fn request_invoice_cleanup(
+    app_root: &std::path::Path,
+    server: &LocalServer,
+    dry_run: bool,
+) -> Result<retention::Report, String> {
+    let api_key = auth::api_key_from_env()
+        .or_else(|| std::fs::read_to_string(auth::api_key_path(app_root)).ok())
+        .ok_or("API キーがありません（api-key サブコマンドで確認してください）")?;
+    let response = server
+        .client
+        .post(format!(
+            "{}/api/invoices/cleanup?dryRun={}",
+            server.base_url, dry_run
+        ))
+        .header("X-Api-Key", api_key.trim())
+        .send()
+        .await
+        .map_err(|e| format!("サーバーに接続できません: {}", e))?;
+    let status = response.status();
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("レスポンスを読み取れません: {}", e))?;
+    if !status.is_success() {
+        let message = serde_json::from_slice::<serde_json::Value>(&bytes)
+            .ok()
+            .and_then(|body| body["message"].as_str().map(str::to_string))
+            .unwrap_or_default();
+        return Err(format!("HTTP {}: {}", status.as_u16(), message));
+    }
+    serde_json::from_slice(&bytes).map_err(|e| format!("レスポンスを解釈できません: {}", e))
+}
 
-    match output {
-        Ok(result) => {
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            let stderr = String::from_utf8_lossy(&result.stderr);
+/// install / uninstall サブコマンド（systemd / launchd 用の定義ファイル）
+fn run_service_command(command: &str, args: &[&str]) -> i32 {
+    let mut options = service::ServiceOptions::default();
+    let mut apply = false;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match *arg {
+            "--no-recovery" if command == "install" => {
+                options.recovery = false;
+                continue;
+            }
+            "--system" => {
+                options.system = true;
+                continue;
+            }
+            "--apply" => {
+                apply = true;
+                continue;
+            }
+            _ => {}
+        }
+        match (*arg, rest.next()) {
+            ("--name", Some(name)) => options.name = name.to_string(),
+            ("--display-name", Some(display_name)) if command == "install" => {
+                options.display_name = Some(display_name.to_string())
+            }
+            ("--port", Some(port)) if command == "install" => match port.parse::<u16>() {
+                Ok(port) if port > 0 => options.port = Some(port),
+                _ => {
+                    eprintln!("❌ ポート番号が不正です: {}", port);
+                    return 2;
+                }
+            },
+            _ => {
+                print_usage();
+                return 2;
+            }
+        }
+    }
 
-            if result.status.success() {
-                log_to_file("ダウンロード成功");
-                (
-                    StatusCode::OK,
-                    Json(DownloadResponse {
-                        status: "success".to_string(),
-                        message: "Supabase 請求書のダウンロードが完了しました".to_string(),
-                    }),
-                )
+    let app_root = match get_application_root() {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 1;
+        }
+    };
+    let result = if command == "install" {
+        std::env::current_exe()
+            .map_err(|e| format!("実行ファイルパス取得失敗: {}", e))
+            .and_then(|exe| service::install(&app_root, &exe, &options))
+            .map(Some)
+    } else {
+        service::uninstall(&app_root, &options)
+    };
+    match result {
+        Ok(Some(definition)) => {
+            if command == "install" {
+                println!(
+                    "✓ サービス定義を作成しました: {}",
+                    definition.path.display()
+                );
+                if let Some(recovery) = &definition.recovery {
+                    println!("  異常終了時: {}", recovery);
+                }
             } else {
-                log_to_file(&format!("ダウンロード失敗: {} {}", stdout, stderr));
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(DownloadResponse {
-                        status: "error".to_string(),
-                        message: format!("ダウンロードエラー: {}", stderr.trim()),
-                    }),
-                )
+                println!(
+                    "✓ サービス定義を削除しました: {}",
+                    definition.path.display()
+                );
+            }
+            if apply {
+                return match service::apply(&definition) {
+                    Ok(()) => {
+                        println!(
+                            "✓ サービスを{}しました",
+                            if command == "install" {
+                                "登録・起動"
+                            } else {
+                                "停止・登録解除"
+                            }
+                        );
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("❌ {}", e);
+                        1
+                    }
+                };
+            }
+            if command == "install" {
+                println!("  次のコマンドで登録してください (--apply で実行も行います):");
+            } else {
+                println!("  登録済みの場合は次のコマンドで解除してください:");
             }
+            for line in definition.instructions {
+                println!("    {}", line);
+            }
+            0
+        }
+        Ok(None) => {
+            println!("サービス定義はありません");
+            0
         }
         Err(e) => {
-            log_to_file(&format!("Node.js 実行エラー: {}", e));
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(DownloadResponse {
-                    status: "error".to_string(),
-                    message: format!("Node.js 実行エラー: {}", e),
-                }),
-            )
+            eprintln!("❌ {}", e);
+            1
         }
     }
 }
 
-fn check_and_setup_environment() -> Result<(), String> {
-    println!("🔍 環境チェック中...");
-
-    let app_root = get_application_root()?;
+/// setup サブコマンド（起動時と同じ環境セットアップを出力を見ながら実行する）
+fn run_setup_subcommand(args: &[&str]) -> i32 {
+    let (browser, min_node_version, mirrors, proxy, offline) = match get_application_root()
+        .and_then(|root| config::load_config(&root))
+        .and_then(|config| {
+            let browser = resolve_playwright_browser(config.download.browser)?;
+            let mirrors = resolve_setup_mirrors(&config.download)?;
+            let proxy = proxy::Proxy::resolve(config.download.proxy.as_deref())?;
+            let offline = resolve_offline(config.download.offline)?;
+            Ok((
+                browser,
+                config.download.min_node_version,
+                mirrors,
+                proxy,
+                offline,
+            ))
+        }) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 1;
+        }
+    };
+    let mut options = setup::SetupOptions {
+        live_output: true,
+        browser,
+        min_node_version,
+        mirrors,
+        proxy,
+        offline,
+        ..Default::default()
+    };
+    for arg in args {
+        match *arg {
+            "--force-browsers" => options.force_browsers = true,
+            "--skip-npm" => options.skip_npm = true,
+            "--offline" => options.offline = true,
+            _ => {
+                print_usage();
+                return 2;
+            }
+        }
+    }
+    match setup::run(options) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("❌ 環境セットアップエラー: {}", e);
+            1
+        }
+    }
+}
 
-    // Node.js チェック
-    println!("  [1/3] Node.js チェック...");
-    let node_check = Command::new("node").arg("--version").output();
-    match node_check {
-        Ok(output) if output.status.success() => {
-            let version = String::from_utf8_lossy(&output.stdout);
-            println!("    ✓ Node.js: {}", version.trim());
+/// credentials サブコマンド（Windows 資格情報マネージャーの GitHub 認証情報）
+fn run_credentials_command(args: &[&str]) -> i32 {
+    match args {
+        ["set", username] => {
+            print!("{} のパスワード: ", username);
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+            let password = match credentials::read_password() {
+                Ok(password) => password,
+                Err(e) => {
+                    eprintln!("❌ パスワードの読み込みに失敗しました: {}", e);
+                    return 1;
+                }
+            };
+            if password.is_empty() {
+                eprintln!("❌ パスワードが空です");
+                return 1;
+            }
+            let credentials = credentials::Credentials {
+                username: username.to_string(),
+                password,
+            };
+            match credentials::store(&credentials) {
+                Ok(()) => {
+                    println!("✓ 認証情報を保存しました ({})", credentials::TARGET_NAME);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("❌ {}", e);
+                    1
+                }
+            }
+        }
+        ["get"] | ["get", "--reveal"] => match credentials::load() {
+            Ok(Some(stored)) => {
+                println!("ユーザー名: {}", stored.username);
+                if args.contains(&"--reveal") {
+                    println!("パスワード: {}", stored.password);
+                } else {
+                    println!("パスワード: 保存済み（表示するには --reveal）");
+                }
+                0
+            }
+            Ok(None) => {
+                println!("認証情報は保存されていません");
+                1
+            }
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                1
+            }
+        },
+        ["delete"] => match credentials::delete() {
+            Ok(true) => {
+                println!("✓ 認証情報を削除しました");
+                0
+            }
+            Ok(false) => {
+                println!("認証情報は保存されていません");
+                0
+            }
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                1
+            }
+        },
+        _ => {
+            print_usage();
+            2
         }
-        _ => return Err("Node.js が見つかりません".to_string()),
     }
+}
 
-    // node_modules チェック
-    println!("  [2/3] 依存関係チェック...");
-    let node_modules_path = app_root.join("node_modules");
-    if !node_modules_path.exists() {
-        println!("    ⚙ npm install を実行中...");
-        let npm_cmd = if cfg!(target_os = "windows") {
-            "npm.cmd"
-        } else {
-            "npm"
-        };
-        let status = Command::new(npm_cmd)
-            .arg("install")
-            .current_dir(&app_root)
-            .status();
+/// provisioning サブコマンド（配布用にプロビジョニングファイルを暗号化する）
+fn run_provisioning_command(args: &[&str], passphrase: Option<&str>) -> i32 {
+    let (input, output) = match args {
+        ["encrypt", input] => (*input, format!("{}.enc", input)),
+        ["encrypt", input, output] => (*input, output.to_string()),
+        _ => {
+            print_usage();
+            return 2;
+        }
+    };
+    let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) else {
+        eprintln!(
+            "❌ 環境変数 {} にパスフレーズを設定してください",
+            provisioning::PASSPHRASE_ENV
+        );
+        return 1;
+    };
+    let encrypted = std::fs::read(input)
+        .map_err(|e| format!("{} を読み込めません: {}", input, e))
+        .and_then(|plain| provisioning::encrypt(&plain, passphrase))
+        .and_then(|encrypted| {
+            std::fs::write(&output, encrypted)
+                .map_err(|e| format!("{} を書き込めません: {}", output, e))
+        });
+    match encrypted {
+        Ok(()) => {
+            println!("✓ 暗号化しました: {}", output);
+            println!(
+                "  配布先では {} として実行ファイルと同じ場所に置いてください",
+                provisioning::ENCRYPTED_FILE_NAME
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            1
+        }
+    }
+}
 
-        if status.is_err() || !status.unwrap().success() {
-            return Err("npm install に失敗しました".to_string());
+/// provisioning.toml(.enc) があれば設定・認証情報を取り込む（失敗しても何も変更せず起動を続ける）
+///
+/// 取り込んだ場合は true（dencho.toml を読み直す）。
+fn apply_provisioning(
+    app_root: &std::path::Path,
+    passphrase: Option<&str>,
+    clock: &dyn clock::Clock,
+) -> bool {
+    match provisioning::apply(
+        app_root,
+        passphrase,
+        &provisioning::SystemCredentials,
+        clock.now().with_timezone(&chrono::Local),
+    ) {
+        Ok(outcome) => matches!(outcome, provisioning::Outcome::Applied { .. }),
+        Err(e) => {
+            log_to_file(
+                Level::Error,
+                &format!("プロビジョニングファイルを適用できません: {}", e),
+            );
+            false
         }
-        println!("    ✓ npm install 完了");
-    } else {
-        println!("    ✓ node_modules 存在確認");
     }
+}
 
-    // Playwright ブラウザチェック
-    println!("  [3/3] Playwright ブラウザチェック...");
-    let appdata = std::env::var("APPDATA").unwrap_or_else(|_| ".".to_string());
-    let browsers_path = std::path::Path::new(&appdata)
-        .join("dencho-cli")
-        .join("browsers");
+/// dencho.toml を読み込む（失敗したら終了する）
+fn load_startup_config() -> config::Config {
+    match get_application_root().and_then(|root| config::load_config(&root)) {
+        Ok(config) => config,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    }
+}
 
-    if !browsers_path.exists()
-        || std::fs::read_dir(&browsers_path)
-            .ok()
-            .map_or(true, |mut d| d.next().is_none())
+/// 待ち受けるアドレスを決める（--addr・--port を優先、不正なら終了する）
+fn resolve_startup_addr(
+    config: &config::Config,
+    cli_addr: Option<&str>,
+    cli_port: Option<&str>,
+) -> SocketAddr {
+    match resolve_listen_addr(cli_addr, &config.server)
+        .and_then(|addr| apply_cli_port(addr, cli_port))
     {
-        println!("    ⚙ Playwright ブラウザをダウンロード中...");
-        let npx_cmd = if cfg!(target_os = "windows") {
-            "npx.cmd"
-        } else {
-            "npx"
-        };
-        let status = Command::new(npx_cmd)
-            .args(["playwright", "install", "chromium"])
-            .current_dir(&app_root)
-            .env("PLAYWRIGHT_BROWSERS_PATH", &browsers_path)
-            .status();
+        Ok(addr) => addr,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    }
+}
 
-        if status.is_err() || !status.unwrap().success() {
-            return Err("Playwright ブラウザのインストールに失敗しました".to_string());
+/// ポートごとの起動ロックを取る（同じポートの dencho-cli が起動中なら終了する）
+fn acquire_instance_lock(port: u16) -> instance_lock::InstanceLock {
+    match get_application_root()
+        .map_err(instance_lock::LockError::Failed)
+        .and_then(|root| instance_lock::acquire(&root, port))
+    {
+        Ok(lock) => lock,
+        Err(instance_lock::LockError::AlreadyRunning { pid, path }) => {
+            log_to_file(
+                Level::Error,
+                &format!(
+                    "起動エラー: ポート {} の dencho-cli は既に起動しています{}。サービスとして起動している場合は停止してから実行するか、--port で別のポートを指定してください (ロックファイル: {})",
+                    port,
+                    pid.map(|pid| format!(" (PID {})", pid)).unwrap_or_default(),
+                    path.display()
+                ),
+            );
+            std::process::exit(instance_lock::ALREADY_RUNNING_EXIT_CODE);
+        }
+        Err(instance_lock::LockError::Failed(e)) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
         }
-        println!("    ✓ Playwright ブラウザインストール完了");
-    } else {
-        println!("    ✓ Playwright ブラウザ存在確認");
     }
+}
 
-    println!("✓ 環境チェック完了\n");
-    Ok(())
+fn main() {
+    // 環境変数の変更はランタイムのスレッドを起動する前に行う（パスフレーズはスクリプトに引き継がない）
+    let provisioning_passphrase = std::env::var(provisioning::PASSPHRASE_ENV).ok();
+    std::env::remove_var(provisioning::PASSPHRASE_ENV);
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("❌ 非同期ランタイムを起動できません: {}", e);
+            std::process::exit(1);
+        }
+    };
+    runtime.block_on(async_main(provisioning_passphrase));
+}
+
+async fn async_main(provisioning_passphrase: Option<String>) {
+    let args: Vec<String> = std::env::args().collect();
+
+    // "run" 引数があってもなくても同じ動作（互換性のため）
+    let mut rest = args.iter().skip(1).peekable();
+    match rest.peek().map(|s| s.as_str()) {
+        Some("run") => {
+            rest.next();
+        }
+        Some("logs") => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_logs_command(&args));
+        }
+        Some("status") => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_status_command(&args).await);
+        }
+        Some("cleanup") => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_cleanup_command(&args).await);
+        }
+        Some(command @ ("install" | "uninstall")) => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_service_command(command, &args));
+        }
+        Some("setup") => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_setup_subcommand(&args));
+        }
+        Some("credentials") => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_credentials_command(&args));
+        }
+        Some("provisioning") => {
+            rest.next();
+            let args: Vec<&str> = rest.map(|s| s.as_str()).collect();
+            std::process::exit(run_provisioning_command(
+                &args,
+                provisioning_passphrase.as_deref(),
+            ));
+        }
+        Some("version" | "--version") => {
+            println!("dencho-cli {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Some("api-key") => {
+            match get_application_root().and_then(|root| auth::load_or_create_api_key(&root)) {
+                Ok(key) => println!("{}", key),
+                Err(e) => {
+                    eprintln!("❌ {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let mut cli_addr: Option<String> = None;
+    let mut cli_port: Option<String> = None;
+    let mut instance: Option<String> = None;
+    let mut regenerate_cert = false;
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            // サービスマネージャーから起動する場合の指定（常にフォアグラウンドで動作する）
+            "--foreground" => {}
+            "--regenerate-cert" => regenerate_cert = true,
+            option @ ("--addr" | "--port" | "--instance") => match rest.next() {
+                Some(value) => match option {
+                    "--addr" => cli_addr = Some(value.clone()),
+                    "--port" => cli_port = Some(value.clone()),
+                    _ => instance = Some(value.clone()),
+                },
+                None => {
+                    print_usage();
+                    std::process::exit(2);
+                }
+            },
+            _ => {
+                print_usage();
+                return;
+            }
+        }
+    }
+
+    let mut config = load_startup_config();
+
+    logging::init(logging::LogSettings {
+        max_size_bytes: config.log.max_size_mb * 1024 * 1024,
+        max_archives: config.log.max_files,
+        min_level: logging::min_level_from_env().unwrap_or(config.log.level),
+        instance: instance.clone(),
+    });
+
+    if let Some(Err(e)) = instance.as_deref().map(service::validate_name) {
+        log_to_file(Level::Error, &format!("起動エラー: --instance: {}", e));
+        std::process::exit(1);
+    }
+
+    // 同じポートの 2 つ目のプロセスは、プロビジョニングやバインドの前にここで止める
+    let mut addr = resolve_startup_addr(&config, cli_addr.as_deref(), cli_port.as_deref());
+    let mut instance_lock = acquire_instance_lock(addr.port());
+
+    let clock: Arc<dyn clock::Clock> = Arc::new(clock::TokioClock);
+
+    if let Ok(root) = get_application_root() {
+        if apply_provisioning(&root, provisioning_passphrase.as_deref(), clock.as_ref()) {
+            // 取り込んだ設定で起動する（ログのローテーション設定は次回の起動から反映）
+            config = load_startup_config();
+            logging::set_min_level(logging::min_level_from_env().unwrap_or(config.log.level));
+            let provisioned =
+                resolve_startup_addr(&config, cli_addr.as_deref(), cli_port.as_deref());
+            if provisioned.port() != addr.port() {
+                instance_lock = acquire_instance_lock(provisioned.port());
+            }
+            addr = provisioned;
+        }
+    }
+    log_to_file(
+        Level::Debug,
+        &format!("起動ロック: {}", instance_lock.path().display()),
+    );
+
+    match get_application_root().map(|root| config::config_path(&root)) {
+        Ok(path) if path.exists() => {
+            log_to_file(Level::Info, &format!("設定ファイル: {}", path.display()));
+        }
+        _ => log_to_file(
+            Level::Info,
+            &format!(
+                "設定ファイル {} が無いためデフォルト値を使用します",
+                config::CONFIG_FILE_NAME
+            ),
+        ),
+    }
+    let overrides = config::env_overrides();
+    if !overrides.is_empty() {
+        // 値は秘密情報の可能性があるため名前だけ出す（反映後の値は下の設定に含まれる）
+        log_to_file(
+            Level::Info,
+            &format!(
+                "環境変数による設定の上書き: {}",
+                overrides
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        );
+    }
+    log_to_file(
+        Level::Info,
+        &format!(
+            "有効な設定:\n{}",
+            redact::redact_known(config::describe(&config).trim_end())
+        ),
+    );
+
+    let output_sink = match sink::OutputSink::from_config(&config.output, clock.clone()) {
+        Ok(sink) => sink,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let browser = match resolve_playwright_browser(config.download.browser) {
+        Ok(browser) => browser,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let mirrors = match resolve_setup_mirrors(&config.download) {
+        Ok(mirrors) => mirrors,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+    log_to_file(
+        Level::Info,
+        &format!("環境セットアップ: {}", mirrors.describe()),
+    );
+
+    let proxy = match proxy::Proxy::resolve(config.download.proxy.as_deref()) {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let offline = match resolve_offline(config.download.offline) {
+        Ok(offline) => offline,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+    if offline {
+        log_to_file(
+            Level::Info,
+            "オフラインモード: npm install・Playwright ブラウザのダウンロードを行わず、事前に配置されているか確認します",
+        );
+    }
+    log_to_file(Level::Info, &proxy.describe());
+
+    let download_timeout = match resolve_download_timeout(config.download.timeout_secs) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let retry_policy = match resolve_retry_policy(&config.download) {
+        Ok(policy) => policy,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let schedules = match schedule::parse(&config.schedule) {
+        Ok(schedules) => schedules,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let retention = match retention::Policy::from_config(&config.invoices) {
+        Ok(retention) => retention,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let api_key = match get_application_root().and_then(|root| auth::load_or_create_api_key(&root))
+    {
+        Ok(key) => key,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+    if auth::api_key_from_env().is_some() {
+        log_to_file(
+            Level::Info,
+            &format!("API キー: 環境変数 {} の値を使用します", auth::API_KEY_ENV),
+        );
+    }
+
+    let always_200 = match always_200_from_env() {
+        Ok(always_200) => always_200,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let tls_files = get_application_root()
+        .and_then(|root| tls::resolve(&config.server, &root))
+        .and_then(|files| {
+            if let Some(files) = files.as_ref().filter(|files| files.self_signed) {
+                if tls::ensure_self_signed(files, regenerate_cert)? {
+                    log_to_file(
+                        Level::Info,
+                        &format!("自己署名証明書を生成しました: {}", files.cert.display()),
+                    );
+                }
+            }
+            Ok(files)
+        });
+    let tls_config = match tls_files {
+        Ok(Some(files)) => match tls::load(&files).await {
+            Ok(loaded) => {
+                log_to_file(
+                    Level::Info,
+                    &format!(
+                        "TLS: 有効 (証明書 {}, SHA-256 指紋 {})",
+                        files.cert.display(),
+                        loaded.fingerprint
+                    ),
+                );
+                if files.self_signed {
+                    println!("🔒 自己署名証明書を使用しています。ブラウザ・OS で信頼してください:");
+                    println!("   {}", files.cert.display());
+                    println!("   SHA-256: {}", loaded.fingerprint);
+                }
+                Some(loaded.config)
+            }
+            Err(e) => {
+                log_to_file(Level::Error, &format!("起動エラー: {}", e));
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => {
+            log_to_file(Level::Info, "TLS: 無効 (HTTP で待ち受けます)");
+            None
+        }
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let cors_config = cors_config_from_env(&config.cors);
+    log_to_file(
+        Level::Info,
+        &format!("CORS: {}", describe_cors_policy(&cors_config)),
+    );
+
+    let posture = security::PostureInput {
+        api_key_from_env: auth::api_key_from_env().is_some(),
+        cors_allows_any: cors_config.allow_any,
+        bind_is_loopback: addr.ip().is_loopback(),
+        tls_enabled: tls_config.is_some(),
+        credentials_via_env: config.download.legacy_env,
+    };
+    if !posture.bind_is_loopback {
+        log_to_file(
+            Level::Warn,
+            &format!(
+                "⚠ ループバック以外のアドレス {} で待ち受けます。ネットワーク上の他の PC から API キーを使ってアクセスできます{}",
+                addr,
+                if posture.tls_enabled {
+                    ""
+                } else {
+                    "（TLS なしのため、API キーと GitHub 認証情報が平文で流れます）"
+                }
+            ),
+        );
+    }
+    if config.security.strict {
+        let failures: Vec<_> = security::evaluate(&posture)
+            .into_iter()
+            .filter(|item| item.verdict == security::Verdict::Fail)
+            .collect();
+        for item in &failures {
+            log_to_file(
+                Level::Error,
+                &format!("strict モード違反 [{}]: {}", item.name, item.detail),
+            );
+        }
+        if let Some(first) = failures.first() {
+            std::process::exit(first.exit_code);
+        }
+    }
+
+    println!("=== dencho-cli サーバー ===");
+
+    if let Err(e) = validate_cors_config(&cors_config) {
+        log_to_file(Level::Error, &format!("起動エラー: {}", e));
+        std::process::exit(1);
+    }
+
+    let outbox_policy = outbox::RetryPolicy {
+        base_delay: chrono::Duration::seconds(config.outbox.base_delay_secs as i64),
+        max_delay: chrono::Duration::seconds(config.outbox.max_delay_secs as i64),
+        max_age: chrono::Duration::hours(config.outbox.max_age_hours as i64),
+        discard_after: chrono::Duration::hours(config.outbox.discard_after_hours as i64),
+    };
+    let (invoices_dir, script_path, providers) = match get_application_root() {
+        Ok(root) => {
+            let script_path =
+                root.join(script_path_from_env().unwrap_or(config.download.script.clone()));
+            let providers =
+                providers::ProviderRegistry::new(&root, script_path.clone(), &config.providers);
+            log_providers(&root, &providers);
+            (root.join(&config.invoices.dir), script_path, providers)
+        }
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let history = if config.history.enabled {
+        match get_application_root()
+            .and_then(|root| history::HistoryStore::open(&root.join("data").join("history.db")))
+        {
+            Ok(history) => Some(history),
+            Err(e) => {
+                log_to_file(Level::Error, &format!("起動エラー: {}", e));
+                std::process::exit(1);
+            }
+        }
+    } else {
+        log_to_file(
+            Level::Debug,
+            "ジョブ履歴は無効です ([history] enabled = true で有効にします)",
+        );
+        None
+    };
+
+    let outbox = match get_application_root().and_then(|root| {
+        outbox::Outbox::load(
+            root.join("data").join("outbox.json"),
+            outbox_policy,
+            clock.clone(),
+        )
+    }) {
+        Ok(outbox) => Arc::new(outbox),
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let webhooks = match webhook::Webhooks::new(&config.webhooks, outbox.clone()) {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+    if let Some(webhooks) = &webhooks {
+        log_to_file(
+            Level::Info,
+            &format!(
+                "Webhook 通知: {} にダウンロード結果を送信します",
+                webhooks.hosts().join(", ")
+            ),
+        );
+    }
+
+    let last_runs = match get_application_root()
+        .and_then(|root| last_run::LastRunStore::load(root.join("data").join("last_run.json")))
+    {
+        Ok(last_runs) => last_runs,
+        Err(e) => {
+            log_to_file(Level::Error, &format!("起動エラー: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    let state = Arc::new(AppState {
+        env_cache: env_cache::EnvCache::new(env_cache::ENV_FAILURE_TTL, clock.clone()),
+        sink: output_sink,
+        outbox,
+        jobs: jobs::JobStore::new(clock.clone()),
+        history,
+        rate_limiter: rate_limit::RateLimiter::new(config.rate_limit.clone(), clock.clone()),
+        children: process::ChildTracker::default(),
+        stats: ServerStats::default(),
+        metrics: metrics::Metrics::default(),
+        started_at: clock.now(),
+        port: addr.port(),
+        instance,
+        last_download: std::sync::Mutex::new(None),
+        last_runs,
+        readiness: readiness::ReadinessCache::new(readiness::READINESS_TTL, clock.clone()),
+        invoices_dir,
+        script_path,
+        providers,
+        clock,
+        api_key,
+        posture: std::sync::RwLock::new(posture),
+        startup_config: config.clone(),
+        config: std::sync::RwLock::new(config.clone()),
+        reloadable: std::sync::RwLock::new(ReloadableSettings {
+            download_timeout,
+            retry: retry_policy,
+            queue_downloads: config.download.queue,
+        }),
+        cors: std::sync::RwLock::new(cors_config),
+        schedules: std::sync::RwLock::new(schedules),
+        schedules_changed: tokio::sync::Notify::new(),
+        retention: std::sync::RwLock::new(retention),
+        invoice_cleanup: tokio::sync::Notify::new(),
+        setup: std::sync::Mutex::new(setup::SetupStatus::InProgress),
+        always_200,
+        download_lock: Arc::new(tokio::sync::Mutex::new(())),
+        running_download: std::sync::Mutex::new(None),
+        legacy_env_credentials: config.download.legacy_env,
+        browser,
+        min_node_version: config.download.min_node_version,
+        mirrors,
+        proxy,
+        log_api_max_bytes: config.log.api_max_bytes,
+        offline,
+        paused: AtomicBool::new(false),
+        log_remote_addr: !addr.ip().is_loopback(),
+        webhooks,
+    });
+    spawn_environment_setup(state.clone());
+    spawn_outbox_worker(state.clone());
+    spawn_job_gc(state.clone());
+    spawn_scheduler(state.clone());
+    spawn_invoice_cleanup(state.clone());
+
+    let cors = build_cors_layer(state.clone());
+    let app = build_router(state.clone(), cors);
+
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            log_to_file(
+                Level::Error,
+                &format!(
+                    "ポート {} は他のプログラムが使用中です。dencho.toml の server.port または --port で別のポートを指定してください",
+                    addr.port()
+                ),
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            log_to_file(
+                Level::Error,
+                &format!("ポートのバインドに失敗しました: {} ({})", addr, e),
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    log_to_file(Level::Info, &format!("サーバー起動: {}://{}", scheme, addr));
+    println!("✓ サーバー起動完了: {}://{}", scheme, addr);
+    println!("  Ctrl+C で終了します\n");
+
+    let result = match tls_config {
+        Some(tls_config) => {
+            let listener = match listener.into_std() {
+                Ok(listener) => listener,
+                Err(e) => {
+                    log_to_file(Level::Error, &format!("サーバーエラー: {}", e));
+                    std::process::exit(1);
+                }
+            };
+            // axum::serve の with_graceful_shutdown と同じく、処理中の接続を返し終えてから止める
+            let handle = axum_server::Handle::new();
+            let shutdown_handle = handle.clone();
+            let shutdown_state = state.clone();
+            tokio::spawn(async move {
+                shutdown_signal(shutdown_state).await;
+                shutdown_handle.graceful_shutdown(None);
+            });
+            axum_server::from_tcp_rustls(listener, tls_config)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+        None => {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal(state.clone()))
+            .await
+        }
+    };
+
+    // 通常は shutdown_signal で止めているが、サーバーエラー時の取りこぼしも止める
+    state.children.kill_all();
+    drop(instance_lock);
+    if let Err(e) = result {
+        log_to_file(Level::Error, &format!("サーバーエラー: {}", e));
+        std::process::exit(1);
+    }
+
+    let requests = state.stats.requests.load(Ordering::Relaxed);
+    let downloads = state.stats.downloads.load(Ordering::Relaxed);
+    println!(
+        "✓ サーバー停止: リクエスト {} 件 / ダウンロード {} 件",
+        requests, downloads
+    );
+    log_to_file(
+        Level::Info,
+        &format!(
+            "サーバー停止 (リクエスト {} 件, ダウンロード {} 件)",
+            requests, downloads
+        ),
+    );
+}
+
+/// Ctrl+C 後、実行中のスクリプトの終了を待つ時間
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// 猶予中にスクリプトの終了を確認する間隔
+const SHUTDOWN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Ctrl+C を待ち、実行中のスクリプトを止めてからシャットダウンを開始する
+///
+/// スクリプトには SHUTDOWN_GRACE だけ終了の猶予を与え、残っていれば終了させる
+/// （もう一度 Ctrl+C で即座に終了）。子プロセスを先に止めることで、SSE ストリームや
+/// ジョブも終了し、処理中のレスポンスを返し終えたところでサーバーが止まる。
+async fn shutdown_signal(state: Arc<AppState>) {
+    let signal = wait_for_stop_signal().await;
+
+    println!("\n⏹ 停止しています...");
+    log_to_file(
+        Level::Info,
+        &format!("{} を受信しました。サーバーを停止します", signal),
+    );
+
+    let running = state.children.len();
+    if running > 0 {
+        println!(
+            "  実行中のスクリプトの終了を最大 {} 秒待ちます (もう一度 Ctrl+C で即座に終了)",
+            SHUTDOWN_GRACE.as_secs()
+        );
+        let wait_idle = async {
+            let deadline = state.clock.now() + SHUTDOWN_GRACE;
+            while state.children.len() > 0 && state.clock.now() < deadline {
+                state.clock.sleep(SHUTDOWN_POLL_INTERVAL).await;
+            }
+        };
+        tokio::select! {
+            _ = wait_idle => {}
+            _ = wait_for_stop_signal() => {}
+        }
+    }
+
+    let killed = state.children.kill_all();
+    if killed > 0 {
+        log_to_file(
+            Level::Warn,
+            &format!("実行中のスクリプトを終了しました: {} 件", killed),
+        );
+    }
+}
+
+/// Ctrl+C（Unix では systemd / launchd の SIGTERM も）を待ち、受信したシグナル名を返す
+async fn wait_for_stop_signal() -> &'static str {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log_to_file(Level::Error, &format!("Ctrl+C ハンドラ登録失敗: {}", e));
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = ctrl_c => "Ctrl+C",
+                _ = sigterm.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                log_to_file(Level::Error, &format!("SIGTERM ハンドラ登録失敗: {}", e));
+                ctrl_c.await;
+                "Ctrl+C"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        ctrl_c.await;
+        "Ctrl+C"
+    }
+}
+
+/// DENCHO_ALWAYS_200 有効時、エラーのステータスコードを 200 に置き換える
+///
+/// 2xx 以外をすべて通信エラー扱いする古いクライアント向け。ボディはそのまま返す。
+async fn force_ok_status(
+    State(state): State<Arc<AppState>>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if state.always_200 && !response.status().is_success() {
+        *response.status_mut() = StatusCode::OK;
+    }
+    response
+}
+
+/// ダウンロード要求の回数を送信元ごとに制限する（[rate_limit.client]）
+///
+/// ループバックで待ち受けている場合、送信元はすべて同じマシンなので 1 つにまとめて数える。
+async fn limit_client_requests(
+    State(state): State<Arc<AppState>>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .filter(|_| state.log_remote_addr)
+        .map_or_else(
+            || rate_limit::LOCAL_CLIENT.to_string(),
+            |ConnectInfo(remote)| remote.ip().to_string(),
+        );
+    if let Err(retry_after) = state.rate_limiter.try_acquire_client(&client) {
+        let rule = state.config.read().unwrap().rate_limit.client;
+        log_to_file(
+            Level::Warn,
+            &format!(
+                "ダウンロード要求が多すぎるため拒否しました (client={}, {} 分間に {} 回まで)",
+                client, rule.window_minutes, rule.max_requests
+            ),
+        );
+        state
+            .metrics
+            .record_rejected(StatusCode::TOO_MANY_REQUESTS.as_u16());
+        return rate_limited(
+            format!(
+                "ダウンロード要求が多すぎます（{} 分間に {} 回まで）。{} 秒後に再試行してください",
+                rule.window_minutes, rule.max_requests, retry_after
+            ),
+            retry_after,
+        );
+    }
+    next.run(request).await
+}
+
+/// リクエスト数を数える
+async fn count_requests(
+    State(state): State<Arc<AppState>>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    state.stats.requests.fetch_add(1, Ordering::Relaxed);
+    if state.log_remote_addr {
+        if let Some(ConnectInfo(remote)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            // /health のポーリングで埋もれないよう、/api/* 以外は DEBUG にする
+            let level = if request.uri().path().starts_with("/api/") {
+                Level::Info
+            } else {
+                Level::Debug
+            };
+            log_to_file(
+                level,
+                &format!(
+                    "リクエスト: {} {} {}",
+                    remote.ip(),
+                    request.method(),
+                    request.uri().path()
+                ),
+            );
+        }
+    }
+    next.run(request).await
+}
+
+/// 1 リクエスト 1 行を logs/access.log に書く（log.access、log.access_exclude のパスは除く）
+///
+/// requestId を追加した後のレスポンスを記録するため、request_id::assign の外側に置く。
+async fn write_access_log(
+    State(state): State<Arc<AppState>>,
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let enabled = {
+        let config = state.config.read().unwrap();
+        config.log.access
+            && !config
+                .log
+                .access_exclude
+                .iter()
+                .any(|path| path == request.uri().path())
+    };
+    if !enabled {
+        return next.run(request).await;
+    }
+
+    let started = std::time::Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let remote = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or_else(
+            || "-".to_string(),
+            |ConnectInfo(addr)| addr.ip().to_string(),
+        );
+    let response = next.run(request).await;
+
+    // ストリーミング（SSE）など長さが決まらない本文は "-"
+    let size = response
+        .body()
+        .size_hint()
+        .exact()
+        .map_or_else(|| "-".to_string(), |len| len.to_string());
+    let request_id = response
+        .headers()
+        .get(request_id::HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-");
+    logging::write_access_log(&format!(
+        "{} {} {} {} {}ms {}B req:{}",
+        remote,
+        method,
+        path,
+        response.status().as_u16(),
+        started.elapsed().as_millis(),
+        size,
+        request_id
+    ));
+    response
+}
+
+/// DENCHO_CORS_ORIGINS（カンマ区切り、"*" で全許可）があれば dencho.toml の [cors] より優先
+fn cors_config_from_env(config: &config::CorsConfig) -> config::CorsConfig {
+    match std::env::var("DENCHO_CORS_ORIGINS") {
+        Ok(value) if value.trim() == "*" => config::CorsConfig {
+            allowed_origins: Vec::new(),
+            allow_any: true,
+        },
+        Ok(value) if !value.trim().is_empty() => config::CorsConfig {
+            allowed_origins: value
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+            allow_any: false,
+        },
+        _ => config.clone(),
+    }
+}
+
+/// 起動ログ用の CORS ポリシー説明
+fn describe_cors_policy(config: &config::CorsConfig) -> String {
+    if config.allow_any {
+        "すべてのオリジンを許可".to_string()
+    } else if config.allowed_origins.is_empty() {
+        "クロスオリジンのリクエストを許可しない".to_string()
+    } else {
+        format!("許可オリジン {}", config.allowed_origins.join(", "))
+    }
+}
+
+/// 許可オリジンがヘッダー値として使えるか確認する
+fn validate_cors_config(config: &config::CorsConfig) -> Result<(), String> {
+    for origin in &config.allowed_origins {
+        origin
+            .parse::<header::HeaderValue>()
+            .map_err(|_| format!("CORS の許可オリジンが不正です: {}", origin))?;
+    }
+    Ok(())
+}
+
+/// CORS レイヤーを構築（allow_any を明示した場合のみ全オリジンを許可）
+///
+/// 許可オリジンは設定の再読み込みで変わるため、リクエストごとに state.cors を参照する。
+fn build_cors_layer(state: Arc<AppState>) -> CorsLayer {
+    let origin = AllowOrigin::predicate(move |origin, _| {
+        let cors = state.cors.read().unwrap();
+        cors.allow_any
+            || cors
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin.as_bytes())
+    });
+
+    CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
+        .allow_headers(Any)
+        .expose_headers([header::HeaderName::from_static(request_id::HEADER)])
+}
+
+/// ルーティングを構築
+fn build_router(state: Arc<AppState>, cors: CorsLayer) -> Router {
+    let always_200_layer = middleware::from_fn_with_state(state.clone(), force_ok_status);
+    let client_limit_layer = middleware::from_fn_with_state(state.clone(), limit_client_requests);
+
+    // /api/* は API キー必須（/health・/version・/metrics は公開）
+    let api = Router::new()
+        .route("/api/version", get(get_version))
+        .route("/api/providers", get(list_providers))
+        .route("/api/schedule", get(list_schedules))
+        .route("/api/last-run", get(get_last_run))
+        .route("/api/webhooks/test", post(test_webhooks))
+        .route(
+            "/api/download",
+            post(download_invoice)
+                .layer(client_limit_layer.clone())
+                .layer(always_200_layer.clone()),
+        )
+        .route(
+            "/api/download/stream",
+            get(stream_download)
+                .layer(client_limit_layer)
+                .layer(always_200_layer.clone()),
+        )
+        .route(
+            "/api/download/:job_id",
+            get(get_download_job).layer(always_200_layer.clone()),
+        )
+        .route(
+            "/api/download/:job_id/cancel",
+            post(cancel_download_job).layer(always_200_layer.clone()),
+        )
+        .route("/api/jobs", get(list_job_history))
+        .route("/api/history", get(list_job_history))
+        .route(
+            "/api/jobs/:job_id",
+            get(get_job_history)
+                .delete(cancel_download_job)
+                .layer(always_200_layer),
+        )
+        .route("/api/invoices", get(list_invoices))
+        .route("/api/invoices/cleanup", post(cleanup_invoices))
+        .route("/api/invoices/:name", get(get_invoice))
+        .route("/api/invoices/:name/file", get(get_invoice_file))
+        .route("/api/status", get(get_status))
+        .route("/api/logs", get(get_logs))
+        .route("/api/logs/tail", get(tail_log))
+        .route("/api/pause", post(pause_downloads))
+        .route("/api/resume", post(resume_downloads))
+        .route("/api/config/reload", post(reload_config))
+        .route("/api/setup/reinstall", post(reinstall_browsers))
+        .route("/api/security/posture", get(get_security_posture))
+        .route("/api/outbox", get(list_outbox))
+        .route("/api/outbox/:id/retry", post(retry_outbox_entry))
+        .route("/api/outbox/:id/discard", post(discard_outbox_entry))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ));
+
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/version", get(get_version))
+        .route("/metrics", get(get_metrics))
+        .merge(api)
+        .fallback(rejection::not_found)
+        .method_not_allowed_fallback(rejection::method_not_allowed)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            count_requests,
+        ))
+        .layer(middleware::from_fn(request_id::assign))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            write_access_log,
+        ))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// ダウンロードの統計（Prometheus テキスト形式）
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        state.metrics.render(),
+    )
+        .into_response()
+}
+
+/// 稼働状態（ダウンロードに必要な環境が揃っていなければ 503）
+async fn health_check(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let checks = match state.readiness.get() {
+        Some(checks) => Some(checks),
+        None => {
+            let checks = match get_application_root() {
+                Ok(app_root) => {
+                    let script_path = state.script_path.clone();
+                    let browser = state.browser;
+                    tokio::task::spawn_blocking(move || {
+                        readiness::Readiness::check(&app_root, &script_path, browser)
+                    })
+                    .await
+                    .ok()
+                }
+                Err(_) => None,
+            };
+            if let Some(checks) = &checks {
+                state.readiness.store(checks.clone());
+            }
+            checks
+        }
+    };
+    let setup_in_progress = matches!(*state.setup.lock().unwrap(), setup::SetupStatus::InProgress);
+    let ready = !setup_in_progress && checks.as_ref().is_some_and(|c| c.is_ready());
+    // 成功していないプロバイダーがあってもダウンロードはできるため 200 のまま warn にする
+    let freshness = freshness(&state);
+    let status = match (ready, freshness.status) {
+        (false, _) => "degraded",
+        (true, "warn") => "warn",
+        (true, _) => "ok",
+    };
+
+    (
+        if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(serde_json::json!({
+            "status": status,
+            "instance": state.instance,
+            "paused": state.paused.load(Ordering::Relaxed),
+            "setupInProgress": setup_in_progress,
+            "checks": checks,
+            "environment": state.env_cache.status(),
+            "freshness": freshness,
+        })),
+    )
+}
+
+async fn get_status(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let uptime = (state.clock.now() - state.started_at).num_seconds().max(0);
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "mode": "console",
+        "port": state.port,
+        "instance": state.instance,
+        "paused": state.paused.load(Ordering::Relaxed),
+        "startedAt": state.started_at.with_timezone(&chrono::Local),
+        "uptimeSeconds": uptime,
+        "lastDownload": *state.last_download.lock().unwrap(),
+        "freshness": freshness(&state),
+        "setup": *state.setup.lock().unwrap(),
+    }))
+}
+
+/// 新しいダウンロードの受け付けを止める（実行中のダウンロードはそのまま完了させる）
+async fn pause_downloads(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    if !state.paused.swap(true, Ordering::Relaxed) {
+        log_to_file(Level::Info, "ダウンロードを一時停止しました");
+    }
+    Json(serde_json::json!({ "paused": true }))
+}
+
+/// 一時停止を解除する
+async fn resume_downloads(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    if state.paused.swap(false, Ordering::Relaxed) {
+        log_to_file(Level::Info, "ダウンロードを再開しました");
+    }
+    Json(serde_json::json!({ "paused": false }))
+}
+
+/// 再読み込みで反映する値（すべて検証済み）
+struct PreparedReload {
+    config: config::Config,
+    settings: ReloadableSettings,
+    schedules: Vec<schedule::Schedule>,
+    retention: retention::Policy,
+    cors: config::CorsConfig,
+    log_level: Level,
+}
+
+/// 新しい設定から反映する値を求める（どれか 1 つでも不正ならエラー）
+///
+/// 起動時と同じく、個別の環境変数（DENCHO_DOWNLOAD_TIMEOUT 等）は設定ファイルより優先する。
+fn prepare_reload(state: &AppState, config: config::Config) -> Result<PreparedReload, String> {
+    let settings = ReloadableSettings {
+        download_timeout: resolve_download_timeout(config.download.timeout_secs)?,
+        retry: resolve_retry_policy(&config.download)?,
+        queue_downloads: config.download.queue,
+    };
+    let cors = cors_config_from_env(&config.cors);
+    validate_cors_config(&cors)?;
+    if state.startup_config.security.strict && cors.allow_any {
+        return Err("strict モードでは CORS で全オリジンを許可できません".to_string());
+    }
+    let log_level = logging::min_level_from_env().unwrap_or(config.log.level);
+    let schedules = schedule::parse(&config.schedule)?;
+    let retention = retention::Policy::from_config(&config.invoices)?;
+    Ok(PreparedReload {
+        config,
+        settings,
+        schedules,
+        retention,
+        cors,
+        log_level,
+    })
+}
+
+/// dencho.toml を読み直し、再起動せずに反映できる設定を適用する
+///
+/// 新しい設定が不正な場合は何も変更せず 400 を返す（現在の設定のまま動作を続ける）。
+async fn reload_config(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let prepared = get_application_root()
+        .and_then(|root| config::load_config(&root))
+        .and_then(|config| prepare_reload(&state, config));
+    let reload = match prepared {
+        Ok(reload) => reload,
+        Err(e) => {
+            log_to_file(
+                Level::Warn,
+                &format!(
+                    "設定の再読み込みを中止しました（現在の設定を維持します）: {}",
+                    e
+                ),
+            );
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "status": "error", "message": e })),
+            );
+        }
+    };
+
+    let applied: Vec<_> = config::diff(&state.config.read().unwrap(), &reload.config)
+        .into_iter()
+        .filter(|change| config::is_hot_reloadable(&change.key))
+        .collect();
+    // 再起動までは起動時の値のままなので、起動時の設定と比べる
+    let restart_required: Vec<_> = config::diff(&state.startup_config, &reload.config)
+        .into_iter()
+        .filter(|change| !config::is_hot_reloadable(&change.key))
+        .collect();
+
+    *state.reloadable.write().unwrap() = reload.settings;
+    state.posture.write().unwrap().cors_allows_any = reload.cors.allow_any;
+    if applied.iter().any(|change| change.key.starts_with("cors.")) {
+        log_to_file(
+            Level::Info,
+            &format!("CORS: {}", describe_cors_policy(&reload.cors)),
+        );
+    }
+    *state.cors.write().unwrap() = reload.cors;
+    state
+        .rate_limiter
+        .set_config(reload.config.rate_limit.clone());
+    logging::set_min_level(reload.log_level);
+    if applied.iter().any(|change| change.key == "schedule") {
+        *state.schedules.write().unwrap() = reload.schedules;
+        state.schedules_changed.notify_one();
+    }
+    if applied
+        .iter()
+        .any(|change| change.key.starts_with("invoices."))
+    {
+        *state.retention.write().unwrap() = reload.retention;
+        state.invoice_cleanup.notify_one();
+    }
+    *state.config.write().unwrap() = reload.config;
+
+    let keys = |changes: &[config::ConfigChange]| {
+        changes
+            .iter()
+            .map(|change| change.key.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    log_to_file(
+        Level::Info,
+        &format!(
+            "設定を再読み込みしました: 反映 {} 件 [{}] / 再起動が必要 {} 件 [{}]",
+            applied.len(),
+            keys(&applied),
+            restart_required.len(),
+            keys(&restart_required)
+        ),
+    );
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "status": "success",
+            "applied": applied,
+            "restartRequired": restart_required,
+        })),
+    )
+}
+
+/// 保存済み請求書ファイル（GET /api/invoices の要素）
+#[derive(Serialize)]
+struct InvoiceFile {
+    /// GET /api/invoices/{name} に渡す値（ファイル名なので削除・上書きされない限り変わらない）
+    id: String,
+    name: String,
+    /// ファイル名の先頭（`supabase-invoice-...` の supabase）から推定したプロバイダー
+    provider: Option<String>,
+    #[serde(rename = "sizeBytes")]
+    size_bytes: u64,
+    /// 作成日時を取得できないファイルシステムでは null
+    #[serde(rename = "createdAt")]
+    created_at: Option<chrono::DateTime<chrono::Local>>,
+    #[serde(rename = "modifiedAt")]
+    modified_at: chrono::DateTime<chrono::Local>,
+}
+
+/// GET /api/invoices のクエリ
+#[derive(Deserialize)]
+struct InvoiceQuery {
+    /// このプロバイダーのファイルだけを返す
+    provider: Option<String>,
+    /// この日時以降に更新されたファイルだけを返す（RFC 3339 または YYYY-MM-DD）
+    since: Option<String>,
+    limit: Option<usize>,
+}
+
+/// ダウンロード途中のファイル（Playwright・ブラウザの一時ファイル）は一覧に出さない
+fn is_partial_download(name: &str) -> bool {
+    name.starts_with('.') || is_in_progress_download(name)
+}
+
+/// ブラウザがダウンロード中に使う一時ファイル名か
+fn is_in_progress_download(name: &str) -> bool {
+    [".tmp", ".crdownload", ".part"]
+        .iter()
+        .any(|suffix| name.to_ascii_lowercase().ends_with(suffix))
+}
+
+/// since を日時として解釈する（日付だけならその日の 0 時）
+fn parse_since(since: &str) -> Option<chrono::DateTime<chrono::Local>> {
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Some(datetime.with_timezone(&chrono::Local));
+    }
+    chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(chrono::Local)
+        .earliest()
+}
+
+/// 保存済みの請求書ファイルを新しい順に返す（フォルダが無ければ空配列）
+async fn list_invoices(
+    State(state): State<Arc<AppState>>,
+    rejection::ApiQuery(query): rejection::ApiQuery<InvoiceQuery>,
+) -> Response {
+    let since = match query.since.as_deref().map(parse_since) {
+        Some(None) => {
+            let message = format!(
+                "since は RFC 3339 の日時または YYYY-MM-DD で指定してください: {}",
+                query.since.as_deref().unwrap_or_default()
+            );
+            log_to_file(Level::Warn, &message);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(DownloadResponse::error(message).with_code(ErrorCode::InvalidRequest)),
+            )
+                .into_response();
+        }
+        Some(since) => since,
+        None => None,
+    };
+    // 長い名前を優先して、supabase と supabase-org のような前方一致の重なりを避ける
+    let mut providers: Vec<String> = state
+        .providers
+        .list()
+        .into_iter()
+        .map(|provider| provider.name)
+        .collect();
+    providers.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+    let mut files: Vec<InvoiceFile> = std::fs::read_dir(&state.invoices_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_partial_download(&name) {
+                return None;
+            }
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let provider = providers
+                .iter()
+                .find(|provider| name.starts_with(&format!("{}-", provider)))
+                .cloned();
+            Some(InvoiceFile {
+                id: name.clone(),
+                name,
+                provider,
+                size_bytes: metadata.len(),
+                created_at: metadata.created().ok().map(Into::into),
+                modified_at: metadata.modified().ok()?.into(),
+            })
+        })
+        .filter(|file| {
+            query
+                .provider
+                .as_ref()
+                .is_none_or(|provider| file.provider.as_ref() == Some(provider))
+        })
+        .filter(|file| since.is_none_or(|since| file.modified_at >= since))
+        .collect();
+    files.sort_by_key(|file| std::cmp::Reverse(file.modified_at));
+    if let Some(limit) = query.limit {
+        files.truncate(limit);
+    }
+    Json(files).into_response()
+}
+
+/// 保存済みの請求書ファイルを添付ファイルとして返す
+async fn get_invoice(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    let invalid = || {
+        log_to_file(Level::Warn, &format!("不正なファイル名: {:?}", name));
+        (
+            StatusCode::BAD_REQUEST,
+            Json(
+                DownloadResponse::error("ファイル名が不正です")
+                    .with_code(ErrorCode::InvalidRequest),
+            ),
+        )
+            .into_response()
+    };
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(
+                DownloadResponse::error(format!("ファイルが見つかりません: {}", name))
+                    .with_code(ErrorCode::NotFound),
+            ),
+        )
+            .into_response()
+    };
+
+    // フォルダ直下のファイル名のみ許可（区切り文字・.. ・ヘッダーを壊す文字を拒否）
+    if name.is_empty()
+        || name.contains("..")
+        || name.contains(['/', '\\', '"'])
+        || name.chars().any(char::is_control)
+    {
+        return invalid();
+    }
+
+    let (Ok(root), Ok(path)) = (
+        state.invoices_dir.canonicalize(),
+        state.invoices_dir.join(&name).canonicalize(),
+    ) else {
+        return not_found();
+    };
+    if path.parent() != Some(root.as_path()) {
+        return invalid();
+    }
+
+    invoice_file_response(&path, &name)
+        .await
+        .unwrap_or_else(not_found)
+}
+
+/// GET /api/invoices の id のファイルを返す
+///
+/// id をパスとして連結せず、フォルダ内の一覧から同じ名前のファイルを探す。一覧に無くても
+/// ジョブ履歴に記録されていれば、削除されたものとして 410 を返す。
+async fn get_invoice_file(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    let path = std::fs::read_dir(&state.invoices_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .find(|entry| {
+            entry.file_name().to_str() == Some(id.as_str())
+                && !is_partial_download(&id)
+                && entry.metadata().is_ok_and(|m| m.is_file())
+        })
+        .map(|entry| entry.path());
+    if let Some(path) = path {
+        if let Some(response) = invoice_file_response(&path, &id).await {
+            return response;
+        }
+    }
+
+    let deleted = match state.history.as_ref().map(|history| history.has_file(&id)) {
+        Some(Ok(deleted)) => deleted,
+        Some(Err(e)) => {
+            log_to_file(Level::Error, &e);
+            false
+        }
+        None => false,
+    };
+    let (status, code, message) = if deleted {
+        (
+            StatusCode::GONE,
+            ErrorCode::FileDeleted,
+            format!("ファイルは削除されています: {}", id),
+        )
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            format!("ファイルが見つかりません: {}", id),
+        )
+    };
+    (
+        status,
+        Json(DownloadResponse::error(message).with_code(code)),
+    )
+        .into_response()
+}
+
+/// ファイルを添付ファイルとしてストリーミングで返す（開けなければ None）
+async fn invoice_file_response(path: &std::path::Path, name: &str) -> Option<Response> {
+    let file = tokio::fs::File::open(path).await.ok()?;
+    let length = file.metadata().await.ok()?.len();
+    let content_type = sink::content_type(path);
+    // ヘッダーを壊す文字はファイル名から除く（一覧の名前は任意の文字を含みうる）
+    let filename: String = name
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"' && *c != '\\')
+        .collect();
+
+    Some(
+        (
+            [
+                (header::CONTENT_TYPE, content_type.to_string()),
+                (header::CONTENT_LENGTH, length.to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", filename),
+                ),
+            ],
+            axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+        )
+            .into_response(),
+    )
+}
+
+#[derive(Deserialize)]
+struct InvoiceCleanupQuery {
+    /// true なら削除せず、削除するファイルを返す
+    #[serde(rename = "dryRun", default)]
+    dry_run: bool,
+}
+
+/// 保持ルール（[invoices] keep_days / keep_count）を超えた請求書をすぐに削除する
+async fn cleanup_invoices(
+    State(state): State<Arc<AppState>>,
+    rejection::ApiQuery(query): rejection::ApiQuery<InvoiceCleanupQuery>,
+) -> Response {
+    // 削除はスクリプトが保存中のファイルと競合するので、ダウンロード中は断る（dry run は削除しない）
+    let _lock = if query.dry_run {
+        None
+    } else {
+        match state.download_lock.clone().try_lock_owned() {
+            Ok(lock) => Some(lock),
+            Err(_) => {
+                let message = "ダウンロード実行中のため削除できません。終了後に再実行してください";
+                log_to_file(Level::Warn, message);
+                return (
+                    StatusCode::CONFLICT,
+                    Json(DownloadResponse::error(message).with_code(ErrorCode::DownloadInProgress)),
+                )
+                    .into_response();
+            }
+        }
+    };
+    let policy = *state.retention.read().unwrap();
+    let now = state.clock.now().with_timezone(&chrono::Local);
+    Json(retention::run(
+        &state.invoices_dir,
+        policy,
+        now,
+        query.dry_run,
+    ))
+    .into_response()
+}
+
+/// ダウンロード結果を直近の結果として記録する
+fn record_last_download(
+    state: &AppState,
+    provider: &str,
+    response: &DownloadResponse,
+    files: &[String],
+) {
+    let finished_at = state.now_local();
+    *state.last_download.lock().unwrap() = Some(LastDownload {
+        finished_at,
+        status: response.status.clone(),
+        message: response.message.clone(),
+        code: response.code,
+    });
+    state.last_runs.record(
+        provider,
+        finished_at,
+        &response.status,
+        &response.message,
+        files.len(),
+    );
+}
+
+/// 最後の成功からの経過（/health・GET /api/status 用）
+fn freshness(state: &AppState) -> last_run::Freshness {
+    let stale_after_days = state.config.read().unwrap().download.stale_after_days;
+    state
+        .last_runs
+        .freshness(state.now_local(), stale_after_days)
+}
+
+/// プロバイダーごとの直近の成功・失敗
+async fn get_last_run(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "providers": state.last_runs.list(),
+        "freshness": freshness(&state),
+    }))
+}
+
+async fn get_security_posture(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let items = security::evaluate(&state.posture.read().unwrap());
+    Json(serde_json::json!({
+        "overall": security::overall(&items),
+        "items": items,
+    }))
+}
+
+/// [[schedule]] の時刻になったら、POST /api/download と同じ手順でダウンロードを始める
+///
+/// 設定の再読み込みでスケジュールが変わったら、次回実行時刻を計算し直す。
+fn spawn_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut after = state.now_local();
+        log_schedules(&state, &after);
+        loop {
+            let next = schedule::next_due(&state.schedules.read().unwrap(), &after);
+            let Some((at, due)) = next else {
+                // スケジュールが無ければ再読み込みまで待つ
+                state.schedules_changed.notified().await;
+                after = state.now_local();
+                log_schedules(&state, &after);
+                continue;
+            };
+            tokio::select! {
+                _ = state.clock.sleep_until(at.with_timezone(&chrono::Utc)) => {}
+                _ = state.schedules_changed.notified() => {
+                    after = state.now_local();
+                    log_schedules(&state, &after);
+                    continue;
+                }
+            }
+            after = at;
+            for entry in due {
+                run_scheduled_download(&state, &entry).await;
+            }
+        }
+    });
+}
+
+/// 解釈済みの [[schedule]] と次回実行時刻（cron 式の確認用）
+async fn list_schedules(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let now = state.now_local();
+    let schedules: Vec<_> = state
+        .schedules
+        .read()
+        .unwrap()
+        .iter()
+        .map(|schedule| {
+            serde_json::json!({
+                "name": schedule.name(),
+                "cron": schedule.entry.cron,
+                "provider": schedule
+                    .entry
+                    .provider
+                    .as_deref()
+                    .unwrap_or(providers::DEFAULT_PROVIDER),
+                "locale": schedule.entry.locale,
+                "nextRunAt": schedule.next_after(&now),
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "schedules": schedules }))
+}
+
+/// 各スケジュールの次回実行時刻をログに残す
+fn log_schedules(state: &AppState, after: &chrono::DateTime<chrono::Local>) {
+    for entry in state.schedules.read().unwrap().iter() {
+        log_to_file(
+            Level::Info,
+            &format!(
+                "スケジュール: {} ({}) 次回 {}",
+                entry.name(),
+                entry.entry.cron,
+                entry
+                    .next_after(after)
+                    .map_or_else(|| "なし".to_string(), |at| at.to_rfc3339())
+            ),
+        );
+    }
+}
+
+/// スケジュール実行を 1 回始める（結果はジョブ終了のログと履歴に残る）
+async fn run_scheduled_download(state: &Arc<AppState>, entry: &schedule::Schedule) {
+    let payload = DownloadRequest {
+        github_username: std::env::var("GITHUB_USERNAME").ok(),
+        github_password: std::env::var("GITHUB_PASSWORD").ok(),
+        locale: entry.entry.locale.clone(),
+        skip_setup: false,
+        timeout_seconds: None,
+        provider: entry.entry.provider.clone(),
+        output_dir: None,
+        retries: None,
+        client_id: None,
+        note: None,
+    };
+    let requested_by = format!("schedule:{}", entry.name());
+    // ジョブのログを追えるよう、スケジュール実行ごとにリクエスト ID を振る
+    let request_id = format!(
+        "schedule-{}",
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    request_id::scope(request_id, async {
+        log_to_file(
+            Level::Info,
+            &format!("スケジュール実行: {} ({})", entry.name(), entry.entry.cron),
+        );
+        match start_download_job(state, &HeaderMap::new(), payload, &requested_by) {
+            Ok(started) => log_to_file(
+                Level::Info,
+                &format!(
+                    "スケジュール実行のジョブ{}: {}",
+                    if started.queued {
+                        "登録（順番待ち）"
+                    } else {
+                        "開始"
+                    },
+                    started.job_id
+                ),
+            ),
+            Err(response) => log_to_file(
+                Level::Warn,
+                &format!(
+                    "スケジュール実行を開始できませんでした: {} (HTTP {})",
+                    entry.name(),
+                    response.status().as_u16()
+                ),
+            ),
+        }
+    })
+    .await;
+}
+
+/// 完了済みジョブを定期的に削除する
+fn spawn_job_gc(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = state.clock.interval(std::time::Duration::from_secs(5 * 60));
+        loop {
+            interval.tick().await;
+            let removed = state.jobs.collect_garbage(jobs::JOB_RETENTION);
+            if removed > 0 {
+                log_to_file(
+                    Level::Debug,
+                    &format!("完了済みジョブを削除: {} 件", removed),
+                );
+            }
+        }
+    });
+}
+
+/// 保持ルールによる削除の間隔（起動時にも 1 回実行する）
+const INVOICE_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// 保持ルールを超えた請求書を毎日・ダウンロード成功後に削除する
+fn spawn_invoice_cleanup(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = state.clock.interval(INVOICE_CLEANUP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = state.invoice_cleanup.notified() => {}
+            }
+            let policy = *state.retention.read().unwrap();
+            if !policy.is_enabled() {
+                continue;
+            }
+            // スクリプトが保存中のファイルと競合しないよう、実行中のダウンロードが終わるまで待つ
+            let _lock = state.download_lock.lock().await;
+            let now = state.clock.now().with_timezone(&chrono::Local);
+            let report = retention::run(&state.invoices_dir, policy, now, false);
+            if !report.files.is_empty() {
+                log_to_file(
+                    Level::Info,
+                    &format!(
+                        "保持ルールにより請求書を削除: {} 件 ({} バイト)",
+                        report.files.len(),
+                        report.freed_bytes
+                    ),
+                );
+            }
+        }
+    });
+}
+
+/// 再送キューの間隔
+const OUTBOX_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// 環境セットアップをバックグラウンドで実行する（npm install 等の間も /health に応答する）
+fn spawn_environment_setup(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let options = setup::SetupOptions {
+            browser: state.browser,
+            min_node_version: state.min_node_version,
+            mirrors: state.mirrors.clone(),
+            proxy: state.proxy.clone(),
+            offline: state.offline,
+            ..Default::default()
+        };
+        let result = tokio::task::spawn_blocking(move || setup::run(options))
+            .await
+            .unwrap_or_else(|e| Err(format!("環境セットアップが異常終了しました: {}", e)));
+        let status = match result {
+            Ok(report) => {
+                // セットアップ前に記録した環境エラー・準備状況を引きずらないようにする
+                state.env_cache.clear();
+                state.readiness.clear();
+                setup::SetupStatus::Done(report)
+            }
+            Err(e) => {
+                eprintln!("❌ 環境セットアップエラー: {}", e);
+                log_to_file(Level::Error, &format!("環境セットアップエラー: {}", e));
+                setup::SetupStatus::Failed { error: e }
+            }
+        };
+        *state.setup.lock().unwrap() = status;
+    });
+}
+
+/// Playwright ブラウザを削除してインストールし直す（壊れたブラウザの復旧用）
+///
+/// インストールが終わるまで応答しない。ダウンロード実行中・セットアップ中は 409 を返す。
+async fn reinstall_browsers(State(state): State<Arc<AppState>>) -> Response {
+    let conflict = |message: &str, code: ErrorCode| {
+        log_to_file(Level::Warn, message);
+        (
+            StatusCode::CONFLICT,
+            Json(DownloadResponse::error(message).with_code(code)),
+        )
+            .into_response()
+    };
+    if state.offline {
+        return conflict(
+            "オフラインモード (DENCHO_OFFLINE) のため Playwright ブラウザを再インストールできません",
+            ErrorCode::Offline,
+        );
+    }
+    // ダウンロードがブラウザを使っている間は削除しない（終わるまで新しいダウンロードも始めない）
+    let Ok(_lock) = state.download_lock.clone().try_lock_owned() else {
+        return conflict(
+            "ダウンロード実行中のため再インストールできません。終了後に再実行してください",
+            ErrorCode::DownloadInProgress,
+        );
+    };
+    {
+        let mut setup = state.setup.lock().unwrap();
+        if matches!(*setup, setup::SetupStatus::InProgress) {
+            drop(setup);
+            return conflict(
+                "環境セットアップ中のため再インストールできません",
+                ErrorCode::SetupInProgress,
+            );
+        }
+        *setup = setup::SetupStatus::InProgress;
+    }
+
+    log_to_file(
+        Level::Info,
+        "Playwright ブラウザの再インストールを開始します",
+    );
+    let options = setup::SetupOptions {
+        force_browsers: true,
+        wipe_browsers: true,
+        skip_npm: true,
+        browser: state.browser,
+        min_node_version: state.min_node_version,
+        mirrors: state.mirrors.clone(),
+        proxy: state.proxy.clone(),
+        ..Default::default()
+    };
+    let result = tokio::task::spawn_blocking(move || setup::run(options))
+        .await
+        .unwrap_or_else(|e| Err(format!("環境セットアップが異常終了しました: {}", e)));
+    state.readiness.clear();
+
+    let response = match &result {
+        Ok(report) => {
+            state.env_cache.clear();
+            log_to_file(Level::Info, "Playwright ブラウザを再インストールしました");
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "success",
+                    "message": "Playwright ブラウザを再インストールしました",
+                    "setup": report,
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            log_to_file(
+                Level::Error,
+                &format!("Playwright ブラウザの再インストールに失敗しました: {}", e),
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(DownloadResponse::error(e.clone()).with_code(ErrorCode::SetupFailed)),
+            )
+                .into_response()
+        }
+    };
+    *state.setup.lock().unwrap() = match result {
+        Ok(report) => setup::SetupStatus::Done(report),
+        Err(error) => setup::SetupStatus::Failed { error },
+    };
+    response
+}
+
+/// 再送キューを定期的に処理する
+fn spawn_outbox_worker(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = state.clock.interval(OUTBOX_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match state.outbox.discard_expired() {
+                Ok(expired) => {
+                    for entry in expired {
+                        log_to_file(
+                            Level::Warn,
+                            &format!(
+                                "再送を諦めたエントリを破棄しました: {} ({}、{} 回失敗: {})",
+                                entry.id,
+                                entry.destination,
+                                entry.attempts,
+                                entry.last_error.as_deref().unwrap_or("")
+                            ),
+                        );
+                    }
+                }
+                Err(e) => log_to_file(Level::Error, &e),
+            }
+            for entry in state.outbox.due() {
+                let result = deliver_outbox_entry(&state, &entry).await;
+                let update = match &result {
+                    Ok(()) => {
+                        log_to_file(Level::Info, &format!("再送成功: {}", entry.id));
+                        state.outbox.mark_delivered(&entry.id)
+                    }
+                    Err(e) => match state.outbox.mark_failed(&entry.id, e) {
+                        Ok(true) => {
+                            log_to_file(
+                                Level::Error,
+                                &format!("再送を断念しました: {} ({})", entry.id, e),
+                            );
+                            Ok(())
+                        }
+                        Ok(false) => {
+                            log_to_file(Level::Warn, &format!("再送失敗: {} ({})", entry.id, e));
+                            Ok(())
+                        }
+                        Err(persist_err) => Err(persist_err),
+                    },
+                };
+                if let Err(e) = update {
+                    log_to_file(Level::Error, &e);
+                }
+            }
+        }
+    });
+}
+
+async fn deliver_outbox_entry(state: &AppState, entry: &outbox::OutboxEntry) -> Result<(), String> {
+    match entry.kind {
+        outbox::OutboxKind::Upload => {
+            if entry.destination != state.sink.name() {
+                return Err(format!(
+                    "出力先が変更されています: {} → {}",
+                    entry.destination,
+                    state.sink.name()
+                ));
+            }
+            let files: Vec<PathBuf> = serde_json::from_value(entry.payload["files"].clone())
+                .map_err(|e| format!("再送データが不正です: {}", e))?;
+            state
+                .sink
+                .upload(&files, &entry.idempotency_key)
+                .await
+                .map(|_| ())
+        }
+        outbox::OutboxKind::Webhook => match &state.webhooks {
+            Some(webhooks) => webhooks.redeliver(entry).await,
+            None => Err("Webhook が設定されていません".to_string()),
+        },
+    }
+}
+
+async fn list_outbox(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "entries": state.outbox.list() }))
+}
+
+async fn retry_outbox_entry(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    outbox_action(state.outbox.retry_now(&id), &id, "retry")
+}
+
+async fn discard_outbox_entry(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    outbox_action(state.outbox.discard(&id), &id, "discard")
+}
+
+fn outbox_action(
+    result: Result<bool, String>,
+    id: &str,
+    action: &str,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match result {
+        Ok(true) => {
+            log_to_file(Level::Info, &format!("再送キュー操作 {}: {}", action, id));
+            (StatusCode::OK, Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("エントリが見つかりません: {}", id),
+            })),
+        ),
+        Err(e) => {
+            log_to_file(Level::Error, &e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "status": "error", "message": e })),
+            )
+        }
+    }
+}
+
+/// バックグラウンドで環境を再チェックし、キャッシュを更新する
+fn spawn_env_reprobe(state: Arc<AppState>) {
+    if !state.env_cache.try_begin_reprobe() {
+        return;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        match env_cache::probe_environment(&state.script_path) {
+            Ok(()) => {
+                log_to_file(Level::Info, "環境再チェック: 正常に復旧しました");
+                state.env_cache.clear();
+            }
+            Err((component, reason)) => {
+                log_to_file(
+                    Level::Warn,
+                    &format!("環境再チェック: 異常継続 ({}) {}", component, reason),
+                );
+                state.env_cache.record_failure(component, &reason);
+            }
+        }
+        state.env_cache.end_reprobe();
+    });
+}
+
+/// 実行できるプロバイダーの一覧
+async fn list_providers(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "providers": state.providers.list() }))
+}
+
+/// バージョンとビルド情報（デプロイ済みのビルドを遠隔で確認するため）
+async fn get_version() -> Json<VersionResponse> {
+    let (node_version, playwright_version) = tokio::task::spawn_blocking(|| {
+        let playwright_version = get_application_root()
+            .ok()
+            .and_then(|root| readiness::playwright_version(&root));
+        (readiness::node_version(), playwright_version)
+    })
+    .await
+    .unwrap_or_default();
+
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: env!("DENCHO_GIT_COMMIT").to_string(),
+        built_at: env!("DENCHO_BUILD_EPOCH")
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|t| t.with_timezone(&chrono::Local)),
+        node_version,
+        playwright_version,
+    })
+}
+
+/// 保存先の空き容量が download.min_free_mb 未満なら 507 を返す（確認できなければ続行する）
+#[allow(clippy::result_large_err)]
+fn check_free_space(state: &AppState, dir: &std::path::Path) -> Result<(), Response> {
+    let min_free_mb = state.config.read().unwrap().download.min_free_mb;
+    if min_free_mb == 0 {
+        return Ok(());
+    }
+    let available_mb = match disk_space::available_bytes(dir) {
+        Ok(bytes) => bytes / (1024 * 1024),
+        Err(e) => {
+            log_to_file(Level::Warn, &e);
+            return Ok(());
+        }
+    };
+    if available_mb >= min_free_mb {
+        log_to_file(
+            Level::Debug,
+            &format!(
+                "空き容量: {} MB (必要 {} MB, {})",
+                available_mb,
+                min_free_mb,
+                dir.display()
+            ),
+        );
+        return Ok(());
+    }
+
+    let message = format!(
+        "保存先の空き容量が不足しています: 空き {} MB / 必要 {} MB ({})",
+        available_mb,
+        min_free_mb,
+        dir.display()
+    );
+    log_to_file(Level::Error, &message);
+    Err((
+        StatusCode::INSUFFICIENT_STORAGE,
+        Json(DownloadResponse::error(message).with_code(ErrorCode::InsufficientStorage)),
+    )
+        .into_response())
+}
+
+/// スクリプト実行前の共通処理（ロケール決定・環境チェック・コマンド組み立て）
+#[allow(clippy::result_large_err, clippy::too_many_arguments)]
+fn prepare_download(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    profile: &str,
+    provider: &str,
+    locale: Option<&str>,
+    skip_setup: bool,
+    download_dir: &std::path::Path,
+    allow_queue: bool,
+) -> Result<(Command, DownloadSlot), Response> {
+    if state.paused.load(Ordering::Relaxed) {
+        let message = "ダウンロードは一時停止中です（POST /api/resume で再開します）";
+        log_to_file(Level::Warn, message);
+        let mut response = DownloadResponse::error(message);
+        response.code = Some(ErrorCode::Paused);
+        return Err((StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response());
+    }
+
+    let setup_error = match &*state.setup.lock().unwrap() {
+        setup::SetupStatus::Done(_) => None,
+        setup::SetupStatus::InProgress => Some((
+            "環境セットアップ中です。完了してから再実行してください".to_string(),
+            ErrorCode::SetupInProgress,
+        )),
+        setup::SetupStatus::Failed { error } => Some((
+            format!("環境セットアップに失敗しました: {}", error),
+            ErrorCode::SetupFailed,
+        )),
+    };
+    if let Some((message, code)) = setup_error {
+        log_to_file(Level::Warn, &message);
+        let mut response = DownloadResponse::error(message);
+        response.code = Some(code);
+        return Err((StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response());
+    }
+
+    // 登録済みの名前だけを受け付け、リクエストからパスを指定させない
+    let Some(script_path) = state.providers.script(provider) else {
+        log_to_file(
+            Level::Warn,
+            &format!("未登録のプロバイダーが指定されました: {}", provider),
+        );
+        let mut response =
+            DownloadResponse::error(format!("プロバイダー {} は登録されていません", provider));
+        response.code = Some(ErrorCode::UnknownProvider);
+        return Err((StatusCode::BAD_REQUEST, Json(response)).into_response());
+    };
+
+    let locale = match resolve_locale(locale, headers) {
+        Ok(locale) => locale,
+        Err(e) => {
+            log_to_file(Level::Warn, &format!("ロケールエラー: {}", e));
+            let response = DownloadResponse::error(e).with_code(ErrorCode::InvalidLocale);
+            return Err((StatusCode::BAD_REQUEST, Json(response)).into_response());
+        }
+    };
+
+    let app_root = match get_application_root() {
+        Ok(path) => path,
+        Err(e) => {
+            log_to_file(
+                Level::Error,
+                &format!("アプリケーションルート取得エラー: {}", e),
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(
+                    DownloadResponse::error(format!("環境設定エラー: {}", e))
+                        .with_code(ErrorCode::InternalError),
+                ),
+            )
+                .into_response());
+        }
+    };
+
+    if skip_setup {
+        log_to_file(Level::Debug, "skipSetup 指定: 環境チェックを省略します");
+    } else if let Some((failure, retry_after)) = state.env_cache.cached_failure() {
+        // 直近で環境エラーが出ている場合はプロセスを起動せずに即座に失敗させる
+        spawn_env_reprobe(state.clone());
+        return Err(
+            environment_broken(&failure.component, &failure.reason, retry_after).into_response(),
+        );
+    }
+
+    // skipSetup でも確認する（見つからないスクリプトをルート外として報告しないように）
+    if !script_path.exists() {
+        let reason = format!(
+            "スクリプトファイルが見つかりません: {}",
+            script_path.display()
+        );
+        log_to_file(Level::Error, &reason);
+        state.env_cache.record_failure("script", &reason);
+        return Err(
+            environment_broken("script", &reason, env_cache::ENV_FAILURE_TTL.as_secs())
+                .into_response(),
+        );
+    }
+
+    if let Err(reason) = ensure_within_root(&app_root, script_path) {
+        log_to_file(Level::Error, &reason);
+        let mut response = DownloadResponse::error(reason);
+        response.code = Some(ErrorCode::ScriptOutsideRoot);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response());
+    }
+
+    // 保存先が違えば別のダウンロードとして扱う
+    let fingerprint = format!(
+        "{}|{}|{}|{}|{}",
+        profile,
+        provider,
+        locale,
+        skip_setup,
+        download_dir.display()
+    );
+    let slot = match state.download_lock.clone().try_lock_owned() {
+        Ok(lock) => DownloadSlot::Acquired(DownloadGuard::new(state, lock, fingerprint)),
+        Err(_) => {
+            let running = state.running_download.lock().unwrap().clone();
+            let duplicate = running
+                .as_ref()
+                .is_some_and(|running| running.fingerprint == fingerprint);
+            if !duplicate && allow_queue && state.settings().queue_downloads {
+                log_to_file(Level::Info, "ダウンロード実行中のため順番待ちにします");
+                DownloadSlot::Queued(fingerprint)
+            } else {
+                log_to_file(
+                    Level::Warn,
+                    "ダウンロード実行中のため新しいリクエストを拒否しました",
+                );
+                let mut response = DownloadResponse::error(if duplicate {
+                    "同じ内容のダウンロードが既に実行中です"
+                } else {
+                    "ダウンロードは既に実行中です"
+                });
+                response.code = Some(ErrorCode::DownloadInProgress);
+                response.job_id = running.and_then(|running| running.job_id);
+                return Err((StatusCode::CONFLICT, Json(response)).into_response());
+            }
+        }
+    };
+
+    if let Err(retry_after) = state.rate_limiter.try_acquire(profile) {
+        log_to_file(
+            Level::Warn,
+            &format!("実行回数の上限に達しました (profile={})", profile),
+        );
+        return Err(rate_limited(
+            format!("{} の実行回数の上限に達しました", profile),
+            retry_after,
+        ));
+    }
+
+    let mut cmd = Command::new("node");
+    // サーバーのコンソール入力は渡さない（認証情報を渡す場合は run_download でパイプにする）
+    cmd.arg(script_path)
+        .current_dir(&app_root)
+        .stdin(Stdio::null());
+
+    // Playwright ブラウザパスを設定（セットアップでインストールした場所と同じ）
+    cmd.env(
+        "PLAYWRIGHT_BROWSERS_PATH",
+        readiness::playwright_browsers_path(),
+    );
+    cmd.env("PLAYWRIGHT_BROWSER", state.browser.as_str());
+    cmd.env("DENCHO_LOCALE", locale);
+    cmd.env("DENCHO_OUTPUT_DIR", download_dir);
+    state.proxy.apply(&mut cmd);
+
+    log_to_file(
+        Level::Debug,
+        &format!(
+            "スクリプト実行: node {} (locale={})",
+            script_path.display(),
+            locale
+        ),
+    );
+
+    Ok((cmd, slot))
+}
+
+/// スクリプトがアプリケーションルート配下にあることを確認する（設定ミスで任意の JS を実行しない）
+///
+/// パスを解決できない場合（存在しない・リンク切れ等）は、ルート配下と確認できないため拒否する。
+fn ensure_within_root(
+    app_root: &std::path::Path,
+    script_path: &std::path::Path,
+) -> Result<(), String> {
+    let root = app_root.canonicalize().map_err(|e| {
+        format!(
+            "アプリケーションルートを確認できないためスクリプトを実行しません: {} ({})",
+            app_root.display(),
+            e
+        )
+    })?;
+    let script = script_path.canonicalize().map_err(|e| {
+        format!(
+            "スクリプトのパスを確認できないため実行しません: {} ({})",
+            script_path.display(),
+            e
+        )
+    })?;
+    if script.starts_with(&root) {
+        Ok(())
+    } else {
+        Err(format!(
+            "スクリプトがアプリケーションルートの外にあるため実行しません: {} (ルート: {})",
+            script.display(),
+            root.display()
+        ))
+    }
+}
+
+/// 登録されたプロバイダーをログに残す（実行できないものは起動時に警告する）
+fn log_providers(app_root: &std::path::Path, providers: &providers::ProviderRegistry) {
+    for provider in providers.list() {
+        log_to_file(
+            Level::Info,
+            &format!("プロバイダー: {} ({})", provider.name, provider.script),
+        );
+        let Some(script) = providers.script(&provider.name) else {
+            continue;
+        };
+        if !provider.script_exists {
+            log_to_file(
+                Level::Warn,
+                &format!(
+                    "プロバイダー {} のスクリプトが見つかりません: {}",
+                    provider.name, provider.script
+                ),
+            );
+        } else if let Err(e) = ensure_within_root(app_root, script) {
+            log_to_file(
+                Level::Warn,
+                &format!("プロバイダー {}: {}", provider.name, e),
+            );
+        }
+    }
+}
+
+/// リクエストで省略された認証情報を資格情報マネージャーの保存内容で補う
+///
+/// リクエストの値が優先。保存済みのパスワードは、ユーザー名が保存済みのものと一致する場合だけ使う。
+/// 3 つ目は履歴に残す認証情報の出どころ（request / 資格情報マネージャーのターゲット名、無ければ None）。
+fn resolve_github_credentials(
+    username: Option<String>,
+    password: Option<String>,
+) -> (Option<String>, Option<String>, Option<&'static str>) {
+    let from_request =
+        (username.is_some() || password.is_some()).then_some(CREDENTIALS_FROM_REQUEST);
+    if username.is_some() && password.is_some() {
+        return (username, password, from_request);
+    }
+    let stored = match credentials::load() {
+        Ok(stored) => stored,
+        Err(e) => {
+            log_to_file(Level::Warn, &e);
+            None
+        }
+    };
+    let Some(stored) = stored else {
+        return (username, password, from_request);
+    };
+    let username = username.unwrap_or_else(|| stored.username.clone());
+    let password = password.or_else(|| (username == stored.username).then_some(stored.password));
+    (Some(username), password, Some(credentials::TARGET_NAME))
+}
+
+/// 認証情報をリクエスト（スケジュール実行では環境変数）で受け取った場合の credentialSet
+const CREDENTIALS_FROM_REQUEST: &str = "request";
+
+async fn download_invoice(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    rejection::ApiJson(payload): rejection::ApiJson<DownloadRequest>,
+) -> Response {
+    log_to_file(Level::Debug, "ダウンロードリクエスト受信");
+
+    let started = match start_download_job(&state, &headers, payload, &remote.ip().to_string()) {
+        Ok(started) => started,
+        Err(response) => return response,
+    };
+    let mut response = DownloadResponse::pending(if started.queued {
+        "実行中のダウンロードの終了後に開始します"
+    } else {
+        "ダウンロードを開始しました"
+    });
+    response.job_id = Some(started.job_id);
+    response.output_dir = started.output_dir;
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
+
+/// 登録したダウンロードジョブ
+struct StartedJob {
+    job_id: String,
+    /// 実行中のダウンロードの終了を待っている
+    queued: bool,
+    /// outputDir を指定した場合の保存先（絶対パス）
+    output_dir: Option<String>,
+}
+
+/// 認証情報をスクリプトに渡す（標準入力に書き込む 1 行を返す、legacy_env なら環境変数で渡して None）
+fn pass_credentials(
+    state: &AppState,
+    cmd: &mut Command,
+    username: Option<String>,
+    password: Option<String>,
+) -> Option<String> {
+    if state.legacy_env_credentials {
+        if let Some(username) = &username {
+            cmd.env("GITHUB_USERNAME", username);
+        }
+        if let Some(password) = password {
+            cmd.env("GITHUB_PASSWORD", password);
+        }
+        return None;
+    }
+    // 環境変数はブラウザの子プロセスにも継承されるため、標準入力の 1 行で渡す
+    // （サーバー自身の環境変数に設定されていても引き継がせない）
+    cmd.env_remove("GITHUB_USERNAME")
+        .env_remove("GITHUB_PASSWORD");
+    let mut input = serde_json::json!({
+        "githubUsername": username,
+        "githubPassword": password,
+    })
+    .to_string();
+    input.push('\n');
+    Some(input)
+}
+
+/// 認証情報の 1 行を標準入力に書き込み、パイプを閉じる
+async fn write_credentials(stdin: Option<tokio::process::ChildStdin>, line: Option<&str>) {
+    let (Some(mut stdin), Some(line)) = (stdin, line) else {
+        return;
+    };
+    use tokio::io::AsyncWriteExt;
+    let written = async {
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.flush().await
+    }
+    .await;
+    if let Err(e) = written {
+        log_to_file(
+            Level::Warn,
+            &format!("スクリプトへの認証情報の書き込みに失敗しました: {}", e),
+        );
+    }
+    // stdin はここでドロップされ、パイプが閉じる
+}
+
+/// ダウンロードジョブを登録し、バックグラウンドで実行する（POST /api/download とスケジュール実行で共通）
+///
+/// 受け付けられない場合はエラーのレスポンスを返す。
+#[allow(clippy::result_large_err)]
+fn start_download_job(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    payload: DownloadRequest,
+    requested_by: &str,
+) -> Result<StartedJob, Response> {
+    let timeout = match payload.timeout_seconds {
+        Some(0) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(
+                    DownloadResponse::error("timeoutSeconds は 1 以上を指定してください")
+                        .with_code(ErrorCode::InvalidRequest),
+                ),
+            )
+                .into_response());
+        }
+        Some(secs) => std::time::Duration::from_secs(secs),
+        None => state.settings().download_timeout,
+    };
+    let retry = match payload.retries {
+        Some(retries) if retries > retry::MAX_REQUEST_RETRIES => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(
+                    DownloadResponse::error(format!(
+                        "retries は {} 以下を指定してください",
+                        retry::MAX_REQUEST_RETRIES
+                    ))
+                    .with_code(ErrorCode::InvalidRequest),
+                ),
+            )
+                .into_response());
+        }
+        Some(retries) => retry::RetryPolicy {
+            retries,
+            ..state.settings().retry
+        },
+        None => state.settings().retry,
+    };
+
+    let output_dir = match payload.output_dir.as_deref().filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(resolve_output_dir(state, dir)?),
+        None => None,
+    };
+
+    let (username, password, credential_set) = resolve_github_credentials(
+        payload
+            .github_username
+            .filter(|username| !username.is_empty()),
+        payload
+            .github_password
+            .filter(|password| !password.is_empty()),
+    );
+    // ジョブが終わるまで、この認証情報をすべてのログ出力でマスクする
+    let secret_guard = redact::register(username.iter().chain(password.iter()).cloned());
+    let profile = username.as_deref().unwrap_or(rate_limit::DEFAULT_PROFILE);
+    let provider = payload
+        .provider
+        .as_deref()
+        .unwrap_or(providers::DEFAULT_PROVIDER);
+    let download_dir = output_dir
+        .clone()
+        .unwrap_or_else(|| state.invoices_dir.clone());
+    let prepared = match check_free_space(state, &download_dir) {
+        Ok(()) => prepare_download(
+            state,
+            headers,
+            profile,
+            provider,
+            payload.locale.as_deref(),
+            payload.skip_setup,
+            &download_dir,
+            true,
+        ),
+        Err(response) => Err(response),
+    };
+    let (mut cmd, slot) = match prepared {
+        Ok(prepared) => prepared,
+        Err(response) => {
+            state.metrics.record_rejected(response.status().as_u16());
+            return Err(response);
+        }
+    };
+    let output_dir = output_dir.map(|dir| dir.to_string_lossy().into_owned());
+
+    // 出力に認証情報が含まれていても、ログやレスポンスには出さない
+    let mut secrets = redact::env_secrets();
+    secrets.extend(username.iter().chain(password.iter()).cloned());
+    let stdin = pass_credentials(state, &mut cmd, username, password);
+    let input = ScriptInput {
+        stdin,
+        secrets,
+        provider: provider.to_string(),
+    };
+
+    let request_id = request_id::current();
+    let (job_id, cancel) = state.jobs.create(request_id.clone());
+    let fields = history::RequestFields {
+        client_id: payload.client_id.filter(|id| !id.is_empty()),
+        note: payload.note.filter(|note| !note.is_empty()),
+        requested_by: Some(requested_by.to_string()),
+        credential_set: credential_set.map(str::to_string),
+    };
+    record_job_started(state, &job_id, provider, request_id.as_deref(), &fields);
+    let queued = matches!(slot, DownloadSlot::Queued(_));
+    if let DownloadSlot::Acquired(guard) = &slot {
+        guard.set_job_id(&job_id);
+    }
+    log_to_file(
+        Level::Info,
+        &format!(
+            "ジョブ{}: {}",
+            if queued {
+                "登録（順番待ち）"
+            } else {
+                "開始"
+            },
+            job_id
+        ),
+    );
+
+    let skip_setup = payload.skip_setup;
+    let task_state = state.clone();
+    let task_job_id = job_id.clone();
+    let task_output_dir = output_dir.clone();
+    let task_provider = provider.to_string();
+    tokio::spawn(request_id::inherit(async move {
+        // パニックやタイムアウトでもタスク終了時にロックを解放する
+        let _secret_guard = secret_guard;
+        let _guard = match slot {
+            DownloadSlot::Acquired(guard) => guard,
+            DownloadSlot::Queued(fingerprint) => {
+                let guard = tokio::select! {
+                    guard = DownloadGuard::acquire(&task_state, fingerprint) => guard,
+                    // 順番待ちの間に中止された（状態は cancel 側で更新済み）
+                    _ = cancel.notified() => {
+                        let response =
+                            DownloadResponse::with_status("cancelled", "ダウンロードを中止しました");
+                        download_finished(
+                            &task_state,
+                            Some(&task_job_id),
+                            &task_provider,
+                            &response,
+                            &[],
+                            None,
+                        );
+                        return;
+                    }
+                };
+                guard.set_job_id(&task_job_id);
+                log_to_file(Level::Info, &format!("ジョブ開始: {}", task_job_id));
+                guard
+            }
+        };
+        let started_at = task_state.now_system();
+        let mut response = run_download(
+            &task_state,
+            cmd,
+            input,
+            &download_dir,
+            skip_setup,
+            RunLimits { timeout, retry },
+            &cancel,
+        )
+        .await;
+        response.output_dir = task_output_dir;
+        let files = downloaded_files(&response, &download_dir, started_at);
+        record_job_history(&task_state, &task_job_id, &response, &files);
+        download_finished(
+            &task_state,
+            Some(&task_job_id),
+            &task_provider,
+            &response,
+            &files,
+            task_state.elapsed_since(started_at),
+        );
+        log_to_file(
+            Level::Info,
+            &format!("ジョブ終了: {} ({})", task_job_id, response.status),
+        );
+        record_last_download(&task_state, &task_provider, &response, &files);
+        task_state.jobs.finish(&task_job_id, response);
+    }));
+
+    Ok(StartedJob {
+        job_id,
+        queued,
+        output_dir,
+    })
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    locale: Option<String>,
+    provider: Option<String>,
+    #[serde(rename = "skipSetup", default)]
+    skip_setup: bool,
+    /// true の場合、クライアントが切断してもスクリプトを最後まで実行する
+    #[serde(default)]
+    detach: bool,
+    /// POST /api/download の clientId・note と同じ（ジョブ履歴に残す）
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+    note: Option<String>,
+}
+
+/// スクリプトを実行し、stdout / stderr を 1 行ずつ SSE で送る
+///
+/// イベント: `stdout` / `stderr`（1 行ずつ）、最後に `exit`（終了コードと結果）。
+/// クライアントが切断した場合は子プロセスを終了する（detach 指定時は最後まで実行する）。
+async fn stream_download(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    rejection::ApiQuery(query): rejection::ApiQuery<StreamQuery>,
+) -> Result<Sse<impl tokio_stream::Stream<Item = Result<Event, std::convert::Infallible>>>, Response>
+{
+    log_to_file(Level::Debug, "ダウンロードリクエスト受信 (stream)");
+
+    let download_dir = state.invoices_dir.clone();
+    check_free_space(&state, &download_dir)
+        .inspect_err(|response| state.metrics.record_rejected(response.status().as_u16()))?;
+    let provider = query
+        .provider
+        .clone()
+        .unwrap_or_else(|| providers::DEFAULT_PROVIDER.to_string());
+    // URL に認証情報を載せないよう、資格情報マネージャーに保存したものだけを使う
+    let (username, password, credential_set) = resolve_github_credentials(None, None);
+    let secret_guard = redact::register(username.iter().chain(password.iter()).cloned());
+    let profile = username.as_deref().unwrap_or(rate_limit::DEFAULT_PROFILE);
+    let (mut cmd, slot) = prepare_download(
+        &state,
+        &headers,
+        profile,
+        &provider,
+        query.locale.as_deref(),
+        query.skip_setup,
+        &download_dir,
+        false,
+    )
+    .inspect_err(|response| state.metrics.record_rejected(response.status().as_u16()))?;
+    let DownloadSlot::Acquired(guard) = slot else {
+        unreachable!("stream では順番待ちにしない");
+    };
+    let mut secrets = redact::env_secrets();
+    secrets.extend(username.iter().chain(password.iter()).cloned());
+    let stdin = pass_credentials(&state, &mut cmd, username, password);
+
+    process::isolate_process_group(&mut cmd);
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+
+    let started_at = state.now_system();
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let reason = format!("Node.js 実行エラー: {}", e);
+            log_to_file(Level::Error, &reason);
+            state.env_cache.record_failure("node", &reason);
+            return Err(
+                environment_broken("node", &reason, env_cache::ENV_FAILURE_TTL.as_secs())
+                    .into_response(),
+            );
+        }
+    };
+    write_credentials(child.stdin.take(), stdin.as_deref()).await;
+
+    let job_id = uuid::Uuid::new_v4().to_string();
+    let request_id = request_id::current();
+    let fields = history::RequestFields {
+        client_id: query.client_id.clone().filter(|id| !id.is_empty()),
+        note: query.note.clone().filter(|note| !note.is_empty()),
+        requested_by: Some(remote.ip().to_string()),
+        credential_set: credential_set.map(str::to_string),
+    };
+    record_job_started(&state, &job_id, &provider, request_id.as_deref(), &fields);
+    guard.set_job_id(&job_id);
+    log_to_file(Level::Info, &format!("ジョブ開始 (stream): {}", job_id));
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Event>(64);
+    let secrets: Arc<[String]> = secrets.into();
+
+    let mut forwarders = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        forwarders.push(tokio::spawn(forward_lines(
+            stdout,
+            "stdout",
+            tx.clone(),
+            secrets.clone(),
+        )));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forwarders.push(tokio::spawn(forward_lines(
+            stderr,
+            "stderr",
+            tx.clone(),
+            secrets,
+        )));
+    }
+
+    tokio::spawn(request_id::inherit(async move {
+        let _guard = guard;
+        let _secret_guard = secret_guard;
+        let detach = query.detach;
+        let download_timeout = state.settings().download_timeout;
+        let pid = child.id();
+        let _tracked = pid.map(|pid| state.children.track(pid));
+        state.stats.downloads.fetch_add(1, Ordering::Relaxed);
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = tx.closed(), if !detach => {
+                log_to_file(Level::Warn, "クライアントが切断したためスクリプトを終了します");
+                if let Some(pid) = pid {
+                    process::kill_process_tree(pid);
+                }
+                let _ = child.kill().await;
+                let response = DownloadResponse::with_status(
+                    "cancelled",
+                    "クライアントが切断したためダウンロードを中止しました",
+                );
+                record_job_history(&state, &job_id, &response, &[]);
+                record_last_download(&state, &provider, &response, &[]);
+                download_finished(&state, Some(&job_id), &provider, &response, &[], state.elapsed_since(started_at));
+                return;
+            }
+            _ = state.clock.sleep(download_timeout) => {
+                let response = download_timed_out(pid, download_timeout);
+                record_job_history(&state, &job_id, &response, &[]);
+                record_last_download(&state, &provider, &response, &[]);
+                download_finished(&state, Some(&job_id), &provider, &response, &[], state.elapsed_since(started_at));
+                let _ = child.kill().await;
+                drain_forwarders(&state, forwarders).await;
+                let data = serde_json::json!({ "exitCode": null, "result": response });
+                let _ = tx
+                    .send(Event::default().event("exit").data(data.to_string()))
+                    .await;
+                return;
+            }
+        };
+
+        let (exit_code, response) = match status {
+            Ok(status) if status.success() => {
+                let mut response =
+                    finish_successful_download(&state, &provider, &download_dir, started_at).await;
+                set_downloaded_files(&mut response, &download_dir, None, started_at);
+                (status.code(), response)
+            }
+            Ok(status) => {
+                log_to_file(
+                    Level::Error,
+                    &format!("ダウンロード失敗 (stream): {}", status),
+                );
+                (
+                    status.code(),
+                    DownloadResponse::error(format!("ダウンロードエラー: {}", status))
+                        .with_code(error_code::from_exit_code(status.code())),
+                )
+            }
+            Err(e) => {
+                log_to_file(Level::Error, &format!("Node.js 実行エラー: {}", e));
+                (
+                    None,
+                    DownloadResponse::error(format!("Node.js 実行エラー: {}", e))
+                        .with_code(ErrorCode::EnvironmentBroken),
+                )
+            }
+        };
+
+        let files = downloaded_files(&response, &download_dir, started_at);
+        record_job_history(&state, &job_id, &response, &files);
+        record_last_download(&state, &provider, &response, &files);
+        download_finished(
+            &state,
+            Some(&job_id),
+            &provider,
+            &response,
+            &files,
+            state.elapsed_since(started_at),
+        );
+        // 出力の最後の行より先に exit が届かないよう、転送が終わってから送る
+        drain_forwarders(&state, forwarders).await;
+        let data = serde_json::json!({ "exitCode": exit_code, "result": response });
+        let _ = tx
+            .send(Event::default().event("exit").data(data.to_string()))
+            .await;
+    }));
+
+    let stream = tokio_stream::wrappers::ReceiverStream::new(rx).map(Ok);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// 出力の転送を待つ上限（終了後も孫プロセスがパイプを開いたままの場合に備える）
+const FORWARD_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// stdout / stderr の転送タスクが残りの行を送り終えるまで待つ
+async fn drain_forwarders(state: &AppState, forwarders: Vec<tokio::task::JoinHandle<()>>) {
+    let drained = async {
+        for forwarder in forwarders {
+            let _ = forwarder.await;
+        }
+    };
+    tokio::select! {
+        _ = drained => {}
+        _ = state.clock.sleep(FORWARD_DRAIN_TIMEOUT) => {
+            log_to_file(
+                Level::Warn,
+                "スクリプトの出力が閉じられないため、出力の転送を待たずに終了を通知します",
+            );
+        }
+    }
+}
+
+/// パイプから 1 行ずつ読み、秘密情報をマスクして SSE イベントとして送る
+async fn forward_lines(
+    reader: impl tokio::io::AsyncRead + Unpin,
+    event: &'static str,
+    tx: tokio::sync::mpsc::Sender<Event>,
+    secrets: Arc<[String]>,
+) {
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    let mut connected = true;
+    while let Ok(Some(line)) = lines.next_line().await {
+        // 切断後も読み続ける（detach 時にパイプが詰まってスクリプトが止まらないように）
+        if connected {
+            connected = tx
+                .send(
+                    Event::default()
+                        .event(event)
+                        .data(redact::redact(&line, &secrets)),
+                )
+                .await
+                .is_ok();
+        }
+    }
+}
+
+/// ジョブの状態を返す（タイムアウトで中断したジョブは 504）
+async fn get_download_job(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Response {
+    match state.jobs.get(&job_id) {
+        Some(job) if job.response.code == Some(ErrorCode::DownloadTimeout) => {
+            (StatusCode::GATEWAY_TIMEOUT, Json(job)).into_response()
+        }
+        Some(job) => Json(job).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(
+                DownloadResponse::error(format!("ジョブが見つかりません: {}", job_id))
+                    .with_code(ErrorCode::JobNotFound),
+            ),
+        )
+            .into_response(),
+    }
+}
+
+/// 成功したダウンロードで保存したファイル名（失敗時は空）
+fn downloaded_files(
+    response: &DownloadResponse,
+    download_dir: &std::path::Path,
+    started_at: SystemTime,
+) -> Vec<String> {
+    if response.status != "success" {
+        return Vec::new();
+    }
+    if let Some(files) = &response.files {
+        return files
+            .iter()
+            .map(|file| {
+                let name = file.path.rsplit('/').next().unwrap_or(&file.path);
+                name.to_string()
+            })
+            .collect();
+    }
+    files_modified_since(download_dir, started_at)
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// 保存したファイルをレスポンスの files に入れる
+///
+/// スクリプトの標準出力に files 行があればそれを、無ければ started_at 以降に更新されたファイルを使う。
+/// 出力を解釈できなくても成功のままにし、files を空にして filesWarning で知らせる。
+fn set_downloaded_files(
+    response: &mut DownloadResponse,
+    download_dir: &std::path::Path,
+    stdout: Option<&str>,
+    started_at: SystemTime,
+) {
+    let app_root = get_application_root().ok();
+    let reported = stdout.map_or(script_output::ReportedFiles::Missing, |stdout| {
+        script_output::reported_files(stdout)
+    });
+    let (files, warning) = match reported {
+        script_output::ReportedFiles::Found(paths) => {
+            let (files, missing) = script_output::describe(app_root.as_deref(), &paths);
+            let warning = (!missing.is_empty()).then(|| {
+                format!(
+                    "スクリプトが報告したファイルが見つかりません: {}",
+                    missing.join(", ")
+                )
+            });
+            (files, warning)
+        }
+        script_output::ReportedFiles::Missing => {
+            let paths = files_modified_since(download_dir, started_at);
+            (script_output::describe(app_root.as_deref(), &paths).0, None)
+        }
+        script_output::ReportedFiles::Invalid(e) => (Vec::new(), Some(e)),
+    };
+    if let Some(warning) = &warning {
+        log_to_file(Level::Warn, warning);
+    }
+    response.files = Some(files);
+    response.files_warning = warning;
+}
+
+/// 登録したジョブを履歴に記録する（履歴が無効なら何もしない）
+fn record_job_started(
+    state: &AppState,
+    job_id: &str,
+    provider: &str,
+    request_id: Option<&str>,
+    fields: &history::RequestFields,
+) {
+    let Some(history) = &state.history else {
+        return;
+    };
+    if let Err(e) = history.insert_started(
+        job_id,
+        state.now_local(),
+        provider,
+        request_id,
+        fields,
+        &state.config.read().unwrap().history.persist_fields,
+    ) {
+        log_to_file(Level::Error, &e);
+    }
+}
+
+/// 終了したジョブを履歴に記録する（履歴が無効なら何もしない）
+fn record_job_history(
+    state: &AppState,
+    job_id: &str,
+    response: &DownloadResponse,
+    files: &[String],
+) {
+    let Some(history) = &state.history else {
+        return;
+    };
+    let error = (response.status != "success").then_some(response.message.as_str());
+    if let Err(e) = history.finish(
+        job_id,
+        state.now_local(),
+        &response.status,
+        error,
+        files,
+        response.attempts,
+    ) {
+        log_to_file(Level::Error, &e);
+    }
+}
+
+/// ダウンロードの終了を統計に記録し、Webhook で通知する（duration が None なら実行前に終わったもの）
+fn download_finished(
+    state: &AppState,
+    job_id: Option<&str>,
+    provider: &str,
+    response: &DownloadResponse,
+    files: &[String],
+    duration: Option<std::time::Duration>,
+) {
+    state.metrics.record_finished(&response.status, duration);
+    if response.status == "success" {
+        state.invoice_cleanup.notify_one();
+    }
+    if let Some(webhooks) = &state.webhooks {
+        webhooks.notify(webhook::Payload {
+            event: "download.finished",
+            status: response.status.clone(),
+            message: response.message.clone(),
+            provider: Some(provider.to_string()),
+            filename: files.first().cloned(),
+            files: files.to_vec(),
+            duration_ms: duration.map(|d| d.as_millis() as u64),
+            job_id: job_id.map(str::to_string),
+            text: format!(
+                "dencho-cli: {} ({}, {})",
+                response.message, provider, response.status
+            ),
+        });
+    }
+}
+
+/// 設定済みのすべての Webhook にテスト用の通知を送る（再送せず、結果をそのまま返す）
+async fn test_webhooks(State(state): State<Arc<AppState>>) -> Response {
+    let Some(webhooks) = &state.webhooks else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(
+                DownloadResponse::error(
+                    "Webhook が設定されていません ([[webhooks]] または DENCHO_WEBHOOK_URL)",
+                )
+                .with_code(ErrorCode::NotFound),
+            ),
+        )
+            .into_response();
+    };
+    let deliveries = webhooks
+        .send_test(webhook::Payload {
+            event: "test",
+            status: "success".to_string(),
+            message: "dencho-cli からのテスト通知です".to_string(),
+            provider: None,
+            filename: None,
+            files: Vec::new(),
+            duration_ms: None,
+            job_id: None,
+            text: "dencho-cli: Webhook のテスト通知です".to_string(),
+        })
+        .await;
+    let delivered = deliveries.iter().all(|delivery| delivery.delivered);
+    (
+        if delivered {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_GATEWAY
+        },
+        Json(serde_json::json!({
+            "delivered": delivered,
+            "webhooks": deliveries,
+        })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct JobHistoryQuery {
+    limit: Option<u32>,
+    offset: Option<u32>,
+    status: Option<String>,
+}
+
+/// 1 回に返す履歴の件数（デフォルトと上限）
+const JOB_HISTORY_DEFAULT_LIMIT: u32 = 20;
+const JOB_HISTORY_MAX_LIMIT: u32 = 100;
+
+/// ジョブ履歴を新しい順に返す
+async fn list_job_history(
+    State(state): State<Arc<AppState>>,
+    rejection::ApiQuery(query): rejection::ApiQuery<JobHistoryQuery>,
+) -> Response {
+    let Some(history) = &state.history else {
+        return history_disabled();
+    };
+    let query = history::JobQuery {
+        limit: query
+            .limit
+            .unwrap_or(JOB_HISTORY_DEFAULT_LIMIT)
+            .min(JOB_HISTORY_MAX_LIMIT),
+        offset: query.offset.unwrap_or(0),
+        status: query.status.filter(|status| !status.is_empty()),
+    };
+    match history.list(&query) {
+        Ok(jobs) => Json(serde_json::json!({
+            "jobs": jobs,
+            "limit": query.limit,
+            "offset": query.offset,
+        }))
+        .into_response(),
+        Err(e) => {
+            log_to_file(Level::Error, &e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(DownloadResponse::error(e).with_code(ErrorCode::InternalError)),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LogTailQuery {
+    lines: Option<usize>,
+    /// text の場合はテキスト（1 行 1 ログ）、それ以外は JSON
+    format: Option<String>,
+}
+
+/// GET /api/logs/tail で返す行数（デフォルトと上限）
+const LOG_TAIL_DEFAULT_LINES: usize = 100;
+const LOG_TAIL_MAX_LINES: usize = 5000;
+
+/// server.log の末尾を返す（ファイルが無ければ空）
+async fn tail_log(rejection::ApiQuery(query): rejection::ApiQuery<LogTailQuery>) -> Response {
+    let count = query
+        .lines
+        .unwrap_or(LOG_TAIL_DEFAULT_LINES)
+        .min(LOG_TAIL_MAX_LINES);
+    let lines = match logging::tail_lines(count) {
+        Ok(lines) => lines,
+        Err(e) => {
+            log_to_file(
+                Level::Error,
+                &format!("ログの読み込みに失敗しました: {}", e),
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(
+                    DownloadResponse::error(format!("ログの読み込みに失敗しました: {}", e))
+                        .with_code(ErrorCode::InternalError),
+                ),
+            )
+                .into_response();
+        }
+    };
+    if query.format.as_deref() == Some("text") {
+        let mut body = lines.join("\n");
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        return ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response();
+    }
+    Json(serde_json::json!({ "lines": lines })).into_response()
+}
+
+/// GET /api/logs のクエリ
+#[derive(Deserialize)]
+struct LogQuery {
+    lines: Option<usize>,
+    /// このレベル以上だけを返す（debug / info / warn / error）
+    level: Option<Level>,
+    /// この日時以降だけを返す（RFC 3339 または YYYY-MM-DD）
+    since: Option<String>,
+}
+
+/// GET /api/logs で返す件数のデフォルト（上限は LOG_TAIL_MAX_LINES）
+const LOGS_DEFAULT_LINES: usize = 200;
+
+/// JSON の {"lines":[],"truncated":false} の分
+const LOGS_JSON_OVERHEAD_BYTES: u64 = 32;
+
+/// server.log の末尾を絞り込んで返す（Accept: text/plain ならテキスト、それ以外は JSON）
+///
+/// 読み込む範囲と返す本文はどちらも log.api_max_bytes までに収め、収まらない古いログは省く。
+async fn get_logs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    rejection::ApiQuery(query): rejection::ApiQuery<LogQuery>,
+) -> Response {
+    let since = match query.since.as_deref().map(parse_since) {
+        Some(None) => {
+            let message = format!(
+                "since は RFC 3339 の日時または YYYY-MM-DD で指定してください: {}",
+                query.since.as_deref().unwrap_or_default()
+            );
+            log_to_file(Level::Warn, &message);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(DownloadResponse::error(message).with_code(ErrorCode::InvalidRequest)),
+            )
+                .into_response();
+        }
+        Some(since) => since,
+        None => None,
+    };
+    let filter = logging::TailFilter {
+        lines: query
+            .lines
+            .unwrap_or(LOGS_DEFAULT_LINES)
+            .min(LOG_TAIL_MAX_LINES),
+        min_level: query.level,
+        since,
+    };
+    let max_bytes = state.log_api_max_bytes;
+    let result = tokio::task::spawn_blocking(move || logging::tail_entries(&filter, max_bytes))
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+    let entries = match result {
+        Ok(entries) => entries,
+        // 再試行してもロックされたままの場合など（時間をおけば読める）
+        Err(e) => {
+            log_to_file(
+                Level::Error,
+                &format!("ログの読み込みに失敗しました: {}", e),
+            );
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(
+                    DownloadResponse::error(format!("ログの読み込みに失敗しました: {}", e))
+                        .with_code(ErrorCode::InternalError),
+                ),
+            )
+                .into_response();
+        }
+    };
+
+    let text = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain"));
+    // 新しいものから上限に収まるだけ残す（JSON は 1 件ごとのエンコード後の大きさと外側の分で数える）
+    let mut size: u64 = if text { 0 } else { LOGS_JSON_OVERHEAD_BYTES };
+    let mut kept = entries.len();
+    for entry in entries.iter().rev() {
+        let entry_size = if text {
+            entry.line.len() + 1
+        } else {
+            serde_json::to_vec(entry).map_or(0, |json| json.len()) + 1
+        } as u64;
+        if size + entry_size > max_bytes {
+            break;
+        }
+        size += entry_size;
+        kept -= 1;
+    }
+    let truncated = kept > 0;
+    let entries = &entries[kept..];
+
+    if text {
+        let mut body = entries
+            .iter()
+            .map(|entry| entry.line.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        return ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response();
+    }
+    Json(serde_json::json!({ "lines": entries, "truncated": truncated })).into_response()
+}
+
+/// [history] enabled が false のときの GET /api/jobs・/api/history の応答
+fn history_disabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(
+            DownloadResponse::error(
+                "ジョブ履歴は無効です ([history] enabled = true で有効にします)",
+            )
+            .with_code(ErrorCode::NotFound),
+        ),
+    )
+        .into_response()
+}
+
+/// ジョブ履歴の 1 件を返す
+async fn get_job_history(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Response {
+    let Some(history) = &state.history else {
+        return history_disabled();
+    };
+    match history.get(&job_id) {
+        Ok(Some(job)) => Json(job).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(
+                DownloadResponse::error(format!("ジョブが見つかりません: {}", job_id))
+                    .with_code(ErrorCode::JobNotFound),
+            ),
+        )
+            .into_response(),
+        Err(e) => {
+            log_to_file(Level::Error, &e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(DownloadResponse::error(e).with_code(ErrorCode::InternalError)),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// 実行中のジョブを中止する（完了済みなら 409 と最終状態）
+async fn cancel_download_job(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Response {
+    match state.jobs.cancel(&job_id) {
+        jobs::CancelOutcome::Cancelled(job) => {
+            log_to_file(Level::Info, &format!("ジョブ中止: {}", job_id));
+            if let Some(history) = &state.history {
+                if let Err(e) = history.finish(
+                    &job_id,
+                    state.now_local(),
+                    &job.response.status,
+                    None,
+                    &[],
+                    None,
+                ) {
+                    log_to_file(Level::Error, &e);
+                }
+            }
+            Json(job).into_response()
+        }
+        jobs::CancelOutcome::AlreadyFinished(job) => {
+            (StatusCode::CONFLICT, Json(job)).into_response()
+        }
+        jobs::CancelOutcome::NotFound => (
+            StatusCode::NOT_FOUND,
+            Json(
+                DownloadResponse::error(format!("ジョブが見つかりません: {}", job_id))
+                    .with_code(ErrorCode::JobNotFound),
+            ),
+        )
+            .into_response(),
+    }
+}
+
+/// スクリプトに渡す入力
+struct ScriptInput {
+    /// 標準入力に書き込んでから閉じる内容（EOF まで読むスクリプトを待たせない）
+    stdin: Option<String>,
+    /// 出力をログやレスポンスに含める前にマスクする値
+    secrets: Vec<String>,
+    /// 実行するプロバイダー名（完了メッセージの表示名に使う）
+    provider: String,
+}
+
+/// スクリプトの実行時間の上限と再試行（リクエストの timeoutSeconds / retries で変えられる）
+struct RunLimits {
+    timeout: std::time::Duration,
+    retry: retry::RetryPolicy,
+}
+
+/// スクリプトを実行し、成功時は出力先へアップロードする
+async fn run_download(
+    state: &AppState,
+    mut cmd: Command,
+    input: ScriptInput,
+    download_dir: &std::path::Path,
+    skip_setup: bool,
+    limits: RunLimits,
+    cancel: &tokio::sync::Notify,
+) -> DownloadResponse {
+    let RunLimits { timeout, retry } = limits;
+    let started_at = state.now_system();
+    process::isolate_process_group(&mut cmd);
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if input.stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+
+    // 一時的なエラーで失敗した場合は待ってから同じコマンドで再実行する
+    let with_attempts = |mut response: DownloadResponse, attempts: u32| {
+        response.attempts = Some(attempts);
+        response
+    };
+    let mut attempt = 0;
+    let output = loop {
+        attempt += 1;
+        if attempt > 1 {
+            log_to_file(
+                Level::Info,
+                &format!(
+                    "ダウンロードを再試行します ({}/{} 回目)",
+                    attempt,
+                    retry.retries + 1
+                ),
+            );
+        }
+        let output = match cmd.spawn() {
+            Ok(mut child) => {
+                let pid = child.id();
+                write_credentials(child.stdin.take(), input.stdin.as_deref()).await;
+                let _tracked = pid.map(|pid| state.children.track(pid));
+                if attempt == 1 {
+                    state.stats.downloads.fetch_add(1, Ordering::Relaxed);
+                }
+                // 中止した場合も、それまでの出力（files 行）を削除の判断に使えるよう自前で読む
+                let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+                let status = {
+                    let (stdout_pipe, stderr_pipe) = (child.stdout.take(), child.stderr.take());
+                    let output = async {
+                        let (status, _, _) = tokio::join!(
+                            child.wait(),
+                            read_pipe(stdout_pipe, &mut stdout),
+                            read_pipe(stderr_pipe, &mut stderr)
+                        );
+                        status
+                    };
+                    tokio::select! {
+                        status = output => Some(status),
+                        _ = state.clock.sleep(timeout) => {
+                            return with_attempts(download_timed_out(pid, timeout), attempt);
+                        }
+                        _ = cancel.notified() => None,
+                    }
+                };
+                let Some(status) = status else {
+                    let stdout = String::from_utf8_lossy(&stdout);
+                    return with_attempts(
+                        download_cancelled(pid, download_dir, started_at, Some(&stdout)),
+                        attempt,
+                    );
+                };
+                status.map(|status| std::process::Output {
+                    status,
+                    stdout,
+                    stderr,
+                })
+            }
+            Err(e) => Err(e),
+        };
+
+        let (exit_code, stderr) = match &output {
+            Ok(result) if !result.status.success() => (
+                result.status.code(),
+                String::from_utf8_lossy(&result.stderr),
+            ),
+            _ => break output,
+        };
+        if attempt > retry.retries || !retry::is_retryable(exit_code, &stderr) {
+            break output;
+        }
+        let delay = retry.delay(attempt);
+        let last_line = stderr.lines().rev().find(|line| !line.trim().is_empty());
+        log_to_file(
+            Level::Warn,
+            &format!(
+                "ダウンロード失敗（一時的なエラー、{}/{} 回目）: {} 秒後に再試行します: {}",
+                attempt,
+                retry.retries + 1,
+                delay.as_secs(),
+                redact::redact(last_line.unwrap_or("").trim(), &input.secrets)
+            ),
+        );
+        tokio::select! {
+            _ = state.clock.sleep(delay) => {}
+            _ = cancel.notified() => {
+                return with_attempts(download_cancelled(None, download_dir, started_at, None), attempt);
+            }
+        }
+    };
+    if attempt > 1 {
+        let succeeded = output.as_ref().is_ok_and(|result| result.status.success());
+        log_to_file(
+            if succeeded { Level::Info } else { Level::Error },
+            &format!(
+                "ダウンロードを {} 回実行し、{}しました",
+                attempt,
+                if succeeded { "成功" } else { "失敗" }
+            ),
+        );
+    }
+
+    let response = match output {
+        Ok(result) => {
+            let stdout = redact::redact(&String::from_utf8_lossy(&result.stdout), &input.secrets);
+            let stderr = redact::redact(&String::from_utf8_lossy(&result.stderr), &input.secrets);
+
+            let mut response = if result.status.success() {
+                let mut response =
+                    finish_successful_download(state, &input.provider, download_dir, started_at)
+                        .await;
+                set_downloaded_files(&mut response, download_dir, Some(&stdout), started_at);
+                response
+            } else {
+                log_to_file(
+                    Level::Error,
+                    &format!("ダウンロード失敗: {} {}", stdout, stderr),
+                );
+                let mut response =
+                    DownloadResponse::error(format!("ダウンロードエラー: {}", stderr.trim()))
+                        .with_code(error_code::from_exit_code(result.status.code()));
+                response.stderr = Some(output_tail(&stderr));
+                response
+            };
+            response.stdout = Some(output_tail(&stdout));
+            response
+        }
+        Err(e) => {
+            let reason = if skip_setup {
+                format!(
+                    "Node.js 実行エラー: {} (skipSetup 指定のため環境チェックを省略しました)",
+                    e
+                )
+            } else {
+                format!("Node.js 実行エラー: {}", e)
+            };
+            log_to_file(Level::Error, &reason);
+            state.env_cache.record_failure("node", &reason);
+            environment_broken_response("node", &reason, env_cache::ENV_FAILURE_TTL.as_secs())
+        }
+    };
+    with_attempts(response, attempt)
+}
+
+/// レスポンスに含めるスクリプト出力の上限（末尾を残す）
+const OUTPUT_TAIL_BYTES: usize = 8 * 1024;
+
+/// 出力の末尾 OUTPUT_TAIL_BYTES を返す（切り詰めた場合は先頭に注記）
+fn output_tail(output: &str) -> String {
+    if output.len() <= OUTPUT_TAIL_BYTES {
+        return output.to_string();
+    }
+    let mut start = output.len() - OUTPUT_TAIL_BYTES;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("...(先頭 {} バイトを省略)\n{}", start, &output[start..])
+}
+
+/// パイプを EOF まで読んで buf に追加する（途中で中止されても読んだ分は buf に残る）
+async fn read_pipe(pipe: Option<impl tokio::io::AsyncRead + Unpin>, buf: &mut Vec<u8>) {
+    use tokio::io::AsyncReadExt;
+    let Some(mut pipe) = pipe else {
+        return;
+    };
+    let mut chunk = [0u8; 8192];
+    while let Ok(n @ 1..) = pipe.read(&mut chunk).await {
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// 中止したダウンロードで消すファイル
+///
+/// スクリプトが files 行で報告したファイル（保存先フォルダ内のものだけ）と、
+/// 実行中に作られたブラウザの一時ファイル（.crdownload 等）に限る。ほかのファイルには触れない。
+fn cancelled_download_files(
+    download_dir: &std::path::Path,
+    started_at: SystemTime,
+    stdout: Option<&str>,
+) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = files_modified_since(download_dir, started_at)
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| is_in_progress_download(&name.to_string_lossy()))
+        })
+        .collect();
+
+    if let Some(script_output::ReportedFiles::Found(paths)) =
+        stdout.map(script_output::reported_files)
+    {
+        let app_root = get_application_root().ok();
+        let dir = download_dir.canonicalize().ok();
+        for path in paths {
+            let absolute = match &app_root {
+                Some(root) if path.is_relative() => root.join(&path),
+                _ => path,
+            };
+            let inside = absolute
+                .canonicalize()
+                .ok()
+                .zip(dir.as_ref())
+                .is_some_and(|(file, dir)| file.starts_with(dir) && file.is_file());
+            if inside && !files.contains(&absolute) {
+                files.push(absolute);
+            }
+        }
+    }
+    files
+}
+
+/// 中止されたスクリプトを終了し、途中まで保存されたファイルを削除する
+fn download_cancelled(
+    pid: Option<u32>,
+    download_dir: &std::path::Path,
+    started_at: SystemTime,
+    stdout: Option<&str>,
+) -> DownloadResponse {
+    if let Some(pid) = pid {
+        process::kill_process_tree(pid);
+    }
+    for file in cancelled_download_files(download_dir, started_at, stdout) {
+        match std::fs::remove_file(&file) {
+            Ok(()) => log_to_file(
+                Level::Info,
+                &format!("中止したダウンロードのファイルを削除: {}", file.display()),
+            ),
+            Err(e) => log_to_file(
+                Level::Warn,
+                &format!("ファイル削除失敗: {} ({})", file.display(), e),
+            ),
+        }
+    }
+    log_to_file(Level::Info, "ダウンロードを中止しました");
+    DownloadResponse::with_status("cancelled", "ダウンロードを中止しました")
+}
+
+/// タイムアウトしたスクリプトをプロセスツリーごと終了する
+fn download_timed_out(pid: Option<u32>, timeout: std::time::Duration) -> DownloadResponse {
+    if let Some(pid) = pid {
+        process::kill_process_tree(pid);
+    }
+    let message = format!(
+        "ダウンロードが {} 秒以内に完了しなかったため中断しました",
+        timeout.as_secs()
+    );
+    log_to_file(Level::Error, &message);
+    let mut response = DownloadResponse::with_status("timeout", message);
+    response.code = Some(ErrorCode::DownloadTimeout);
+    response
+}
+
+/// スクリプト成功後の処理（出力先へのアップロード）
+async fn finish_successful_download(
+    state: &AppState,
+    provider: &str,
+    download_dir: &std::path::Path,
+    started_at: SystemTime,
+) -> DownloadResponse {
+    log_to_file(Level::Info, "ダウンロード成功");
+    let mut response = DownloadResponse::success(format!(
+        "{} 請求書のダウンロードが完了しました",
+        state.providers.display_name(provider)
+    ));
+
+    if !matches!(state.sink, sink::OutputSink::Local) {
+        let files = files_modified_since(download_dir, started_at);
+        // 再送でも同じキーを送り、出力先で重複を判別できるようにする
+        let idempotency_key = uuid::Uuid::new_v4().to_string();
+        match state.sink.upload(&files, &idempotency_key).await {
+            Ok(urls) => {
+                log_to_file(
+                    Level::Info,
+                    &format!(
+                        "アップロード完了 ({}): {} 件",
+                        state.sink.name(),
+                        urls.len()
+                    ),
+                );
+                response.remote_urls = Some(urls);
+            }
+            Err(e) => {
+                log_to_file(Level::Error, &e);
+                let payload = serde_json::json!({ "files": files });
+                let message = match state.outbox.enqueue(
+                    outbox::OutboxKind::Upload,
+                    state.sink.name(),
+                    payload,
+                    &idempotency_key,
+                    &e,
+                ) {
+                    Ok(id) => {
+                        log_to_file(
+                            Level::Info,
+                            &format!("アップロードを再送キューに登録: {}", id),
+                        );
+                        format!("{} (再送キューに登録しました: {})", e, id)
+                    }
+                    Err(queue_err) => {
+                        log_to_file(Level::Error, &queue_err);
+                        e
+                    }
+                };
+                let mut response = DownloadResponse::error(message);
+                response.code = Some(ErrorCode::UploadFailed);
+                return response;
+            }
+        }
+    }
+
+    response
+}
+
+/// リクエストの outputDir を download.output_base の中で解決する（不正なら 400）
+#[allow(clippy::result_large_err)]
+fn resolve_output_dir(state: &AppState, requested: &str) -> Result<PathBuf, Response> {
+    let invalid = |message: String| {
+        log_to_file(Level::Warn, &format!("outputDir エラー: {}", message));
+        let mut response = DownloadResponse::error(message);
+        response.code = Some(ErrorCode::InvalidOutputDir);
+        (StatusCode::BAD_REQUEST, Json(response)).into_response()
+    };
+    let app_root = get_application_root().map_err(invalid)?;
+    let base = app_root.join(&state.startup_config.download.output_base);
+    output_dir::resolve(&base, requested).map_err(invalid)
+}
+
+/// since 以降に更新されたファイルを列挙（名前順）
+fn files_modified_since(dir: &std::path::Path, since: SystemTime) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            entry
+                .metadata()
+                .ok()
+                .filter(|m| m.is_file())
+                .and_then(|m| m.modified().ok())
+                .is_some_and(|modified| modified >= since)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    files.sort();
+    files
+}
+
+/// 回数制限を超えた場合の 429（本文の retryAfter と同じ秒数を Retry-After ヘッダーにも付ける）
+fn rate_limited(message: impl Into<String>, retry_after: u64) -> Response {
+    let mut response = DownloadResponse::error(message);
+    response.code = Some(ErrorCode::RateLimited);
+    response.retry_after = Some(retry_after);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(response),
+    )
+        .into_response()
+}
+
+/// 環境エラー時のレスポンス
+fn environment_broken(
+    component: &str,
+    reason: &str,
+    retry_after: u64,
+) -> (StatusCode, Json<DownloadResponse>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(environment_broken_response(component, reason, retry_after)),
+    )
+}
+
+fn environment_broken_response(
+    component: &str,
+    reason: &str,
+    retry_after: u64,
+) -> DownloadResponse {
+    let mut response = DownloadResponse::error(format!("環境エラー ({}): {}", component, reason));
+    response.code = Some(ErrorCode::EnvironmentBroken);
+    response.retry_after = Some(retry_after);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::test_util::TempDir;
+
+    const TEST_API_KEY: &str = "test-api-key";
+
+    /// アプリケーションルート（テストではカレントディレクトリ）配下の一時フォルダ
+    ///
+    /// スクリプトはルート配下でないと実行しないため、test_state にはこれを渡す。
+    fn app_dir() -> TempDir {
+        TempDir::new_in(&get_application_root().unwrap().join("target"))
+    }
+
+    /// dir をアプリケーションの保存先にした状態（セットアップ完了済み、script.js は何もしないスクリプト）
+    fn test_state(dir: &TempDir) -> Arc<AppState> {
+        test_state_with_clock(dir, TestClock::new("2024-01-31T00:00:00Z".parse().unwrap()))
+    }
+
+    fn test_state_with_clock(dir: &TempDir, clock: Arc<TestClock>) -> Arc<AppState> {
+        let clock: Arc<dyn clock::Clock> = clock;
+        let config = config::Config::default();
+        let data = dir.path().join("data");
+        let invoices_dir = dir.path().join("invoices");
+        std::fs::create_dir_all(&invoices_dir).unwrap();
+        let script_path = dir.path().join("script.js");
+        if !script_path.exists() {
+            std::fs::write(&script_path, "").unwrap();
+        }
+        let outbox_policy = outbox::RetryPolicy {
+            base_delay: chrono::Duration::seconds(60),
+            max_delay: chrono::Duration::seconds(3600),
+            max_age: chrono::Duration::hours(72),
+            discard_after: chrono::Duration::hours(168),
+        };
+        Arc::new(AppState {
+            env_cache: env_cache::EnvCache::new(env_cache::ENV_FAILURE_TTL, clock.clone()),
+            sink: sink::OutputSink::Local,
+            outbox: Arc::new(
+                outbox::Outbox::load(data.join("outbox.json"), outbox_policy, clock.clone())
+                    .unwrap(),
+            ),
+            jobs: jobs::JobStore::new(clock.clone()),
+            history: Some(history::HistoryStore::open(&data.join("history.db")).unwrap()),
+            rate_limiter: rate_limit::RateLimiter::new(config.rate_limit.clone(), clock.clone()),
+            children: process::ChildTracker::default(),
+            stats: ServerStats::default(),
+            metrics: metrics::Metrics::default(),
+            started_at: clock.now(),
+            port: 0,
+            instance: None,
+            last_download: std::sync::Mutex::new(None),
+            last_runs: last_run::LastRunStore::load(data.join("last_run.json")).unwrap(),
+            readiness: readiness::ReadinessCache::new(readiness::READINESS_TTL, clock.clone()),
+            invoices_dir,
+            providers: providers::ProviderRegistry::new(
+                dir.path(),
+                script_path.clone(),
+                &config.providers,
+            ),
+            script_path,
+            clock,
+            api_key: TEST_API_KEY.to_string(),
+            posture: std::sync::RwLock::new(security::PostureInput {
+                api_key_from_env: false,
+                cors_allows_any: false,
+                bind_is_loopback: true,
+                tls_enabled: false,
+                credentials_via_env: false,
+            }),
+            startup_config: config.clone(),
+            config: std::sync::RwLock::new(config.clone()),
+            reloadable: std::sync::RwLock::new(ReloadableSettings {
+                download_timeout: std::time::Duration::from_secs(60),
+                retry: retry::RetryPolicy {
+                    retries: 0,
+                    base_delay: std::time::Duration::from_secs(5),
+                },
+                queue_downloads: false,
+            }),
+            cors: std::sync::RwLock::new(config.cors.clone()),
+            schedules: std::sync::RwLock::new(Vec::new()),
+            schedules_changed: tokio::sync::Notify::new(),
+            retention: std::sync::RwLock::new(retention::Policy::default()),
+            invoice_cleanup: tokio::sync::Notify::new(),
+            setup: std::sync::Mutex::new(setup::SetupStatus::Done(Default::default())),
+            always_200: false,
+            download_lock: Arc::new(tokio::sync::Mutex::new(())),
+            running_download: std::sync::Mutex::new(None),
+            legacy_env_credentials: false,
+            browser: config::PlaywrightBrowser::default(),
+            min_node_version: config.download.min_node_version,
+            mirrors: setup::Mirrors::default(),
+            proxy: proxy::Proxy::default(),
+            log_api_max_bytes: config.log.api_max_bytes,
+            offline: false,
+            paused: AtomicBool::new(false),
+            log_remote_addr: false,
+            webhooks: None,
+        })
+    }
+
+    /// 空きポートでルーターを起動し、ベース URL を返す
+    async fn serve(state: Arc<AppState>) -> String {
+        let app = build_router(state.clone(), build_cors_layer(state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+        base
+    }
+
+    /// テスト用サーバーへ直接つなぐクライアント（環境のプロキシを使わない）
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder().no_proxy().build().unwrap()
+    }
+
+    async fn get(base: &str, path: &str) -> (StatusCode, String) {
+        let response = client()
+            .get(format!("{}{}", base, path))
+            .header("X-Api-Key", TEST_API_KEY)
+            .send()
+            .await
+            .unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        (status, response.text().await.unwrap())
+    }
+
+    async fn post_download(base: &str, body: serde_json::Value) -> (u16, serde_json::Value) {
+        let response = client()
+            .post(format!("{}/api/download", base))
+            .header("X-Api-Key", TEST_API_KEY)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        (
+            status,
+            serde_json::from_str(&response.text().await.unwrap()).unwrap(),
+        )
+    }
+
+    /// POST /api/download の {"skipSetup": true} と同じ内容の実行枠を取る
+    fn acquire_download(state: &Arc<AppState>) -> DownloadSlot {
+        let slot = prepare_download(
+            state,
+            &HeaderMap::new(),
+            rate_limit::DEFAULT_PROFILE,
+            providers::DEFAULT_PROVIDER,
+            None,
+            true,
+            &state.invoices_dir,
+            true,
+        );
+        match slot {
+            Ok((_, slot)) => slot,
+            Err(response) => panic!("実行枠を取れません: {}", response.status()),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_one_download_runs_at_a_time() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+
+        let first = acquire_download(&state);
+        assert!(matches!(first, DownloadSlot::Acquired(_)));
+        let second = prepare_download(
+            &state,
+            &HeaderMap::new(),
+            "someone-else",
+            providers::DEFAULT_PROVIDER,
+            Some("en-US"),
+            true,
+            &state.invoices_dir,
+            true,
+        );
+        assert_eq!(second.err().unwrap().status(), StatusCode::CONFLICT);
+
+        let base = serve(state.clone()).await;
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 409);
+        assert_eq!(body["code"], "DownloadInProgress");
+
+        // 終了すれば次のダウンロードを受け付ける
+        drop(first);
+        assert!(matches!(
+            acquire_download(&state),
+            DownloadSlot::Acquired(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn conflicts_report_the_running_job_and_differing_downloads_can_queue() {
+        let dir = app_dir();
+        let mut state = test_state(&dir);
+        Arc::get_mut(&mut state)
+            .unwrap()
+            .startup_config
+            .download
+            .output_base = dir.path().join("downloads");
+        let running = acquire_download(&state);
+        if let DownloadSlot::Acquired(guard) = &running {
+            guard.set_job_id("running-job");
+        }
+        let base = serve(state.clone()).await;
+
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 409);
+        assert_eq!(body["jobId"], "running-job");
+        assert_eq!(body["message"], "同じ内容のダウンロードが既に実行中です");
+
+        state.reloadable.write().unwrap().queue_downloads = true;
+        // 同じ内容は順番待ちにしても意味がないため、queue が有効でも 409
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 409);
+        assert_eq!(body["jobId"], "running-job");
+
+        let (status, body) = post_download(
+            &base,
+            serde_json::json!({ "skipSetup": true, "locale": "en-US" }),
+        )
+        .await;
+        assert_eq!(status, 202);
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["message"], "実行中のダウンロードの終了後に開始します");
+        let queued = body["jobId"].as_str().unwrap().to_string();
+        assert_ne!(queued, "running-job");
+
+        // 保存先だけが違うリクエストも別のダウンロードとして順番待ちにする
+        let (status, body) = post_download(
+            &base,
+            serde_json::json!({ "skipSetup": true, "outputDir": "other" }),
+        )
+        .await;
+        assert_eq!(status, 202);
+        assert_eq!(body["status"], "pending");
+        assert_ne!(body["jobId"], "running-job");
+
+        // 順番待ちのジョブは開始前に中止できる
+        let response = client()
+            .post(format!("{}/api/download/{}/cancel", base, queued))
+            .header("X-Api-Key", TEST_API_KEY)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(body["status"], "cancelled");
+        drop(running);
+    }
+
+    #[tokio::test]
+    async fn a_missing_script_is_an_environment_error_even_with_skip_setup() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        std::fs::remove_file(&state.script_path).unwrap();
+        let base = serve(state).await;
+
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 503);
+        assert_eq!(body["code"], "EnvironmentBroken");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn timed_out_downloads_kill_the_script_and_its_children() {
+        use crate::test_util::{process_alive, wait_until};
+
+        let dir = app_dir();
+        let clock = TestClock::new("2024-01-31T00:00:00Z".parse().unwrap());
+        let state = test_state_with_clock(&dir, clock.clone());
+        let pid_file = dir.path().join("grandchild.pid");
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(format!(
+            "sleep 300 & echo $! > '{}'; wait",
+            pid_file.display()
+        ));
+        let timeout = std::time::Duration::from_secs(30);
+
+        let task_state = state.clone();
+        let download = tokio::spawn(async move {
+            let cancel = tokio::sync::Notify::new();
+            let input = ScriptInput {
+                stdin: None,
+                secrets: Vec::new(),
+                provider: providers::DEFAULT_PROVIDER.to_string(),
+            };
+            let limits = RunLimits {
+                timeout,
+                retry: task_state.settings().retry,
+            };
+            let download_dir = task_state.invoices_dir.clone();
+            run_download(
+                &task_state,
+                cmd,
+                input,
+                &download_dir,
+                true,
+                limits,
+                &cancel,
+            )
+            .await
+        });
+
+        let mut grandchild = None;
+        while grandchild.is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            grandchild = std::fs::read_to_string(&pid_file)
+                .ok()
+                .filter(|pid| pid.ends_with('\n'))
+                .map(|pid| pid.trim().parse::<u32>().unwrap());
+        }
+        let grandchild = grandchild.unwrap();
+        assert_eq!(state.children.len(), 1);
+
+        // 上限の手前では終わらない
+        clock.advance(timeout - std::time::Duration::from_secs(1));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!download.is_finished());
+
+        clock.advance(std::time::Duration::from_secs(1));
+        let response = download.await.unwrap();
+        assert_eq!(response.status, "timeout");
+        assert_eq!(response.code, Some(ErrorCode::DownloadTimeout));
+        assert_eq!(response.attempts, Some(1));
+        assert_eq!(state.children.len(), 0);
+        assert!(wait_until(|| !process_alive(grandchild)));
+    }
+
+    /// cond が true になるまで待つ（最大 5 秒、待つ間もサーバーのタスクを動かす）
+    async fn eventually(mut cond: impl FnMut() -> bool) -> bool {
+        for _ in 0..500 {
+            if cond() {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    async fn post(base: &str, path: &str) -> (u16, serde_json::Value) {
+        let response = client()
+            .post(format!("{}{}", base, path))
+            .header("X-Api-Key", TEST_API_KEY)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        (
+            status,
+            serde_json::from_str(&response.text().await.unwrap()).unwrap(),
+        )
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn running_downloads_can_be_cancelled() {
+        use crate::test_util::process_alive;
+
+        let dir = app_dir();
+        let state = test_state(&dir);
+        let pid_file = dir.path().join("grandchild.pid");
+        std::fs::write(
+            &state.script_path,
+            format!(
+                "import {{ spawn }} from 'node:child_process';\n\
+                 import {{ writeFileSync }} from 'node:fs';\n\
+                 const child = spawn('sleep', ['300'], {{ stdio: 'ignore' }});\n\
+                 writeFileSync({:?}, `${{child.pid}}\\n`);\n\
+                 setInterval(() => {{}}, 1000);\n",
+                pid_file.display().to_string()
+            ),
+        )
+        .unwrap();
+        let base = serve(state.clone()).await;
+
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 202, "{}", body);
+        let job_id = body["jobId"].as_str().unwrap().to_string();
+
+        let mut grandchild = None;
+        while grandchild.is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            grandchild = std::fs::read_to_string(&pid_file)
+                .ok()
+                .filter(|pid| pid.ends_with('\n'))
+                .map(|pid| pid.trim().parse::<u32>().unwrap());
+        }
+        let grandchild = grandchild.unwrap();
+        assert!(process_alive(grandchild));
+        assert_eq!(state.children.len(), 1);
+
+        let (status, body) = post(&base, &format!("/api/download/{}/cancel", job_id)).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "cancelled");
+        assert_eq!(body["jobId"], job_id.as_str());
+        assert!(eventually(|| !process_alive(grandchild)).await);
+        assert!(eventually(|| state.children.len() == 0).await);
+        assert_eq!(
+            state
+                .history
+                .as_ref()
+                .unwrap()
+                .get(&job_id)
+                .unwrap()
+                .unwrap()
+                .status,
+            "cancelled"
+        );
+
+        let (status, body) = post(&base, &format!("/api/download/{}/cancel", job_id)).await;
+        assert_eq!(status, 409);
+        assert_eq!(body["status"], "cancelled");
+
+        let (status, body) = post(&base, "/api/download/missing-job/cancel").await;
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "JobNotFound");
+    }
+
+    /// ジョブが終わるまで GET /api/download/{jobId} を問い合わせ、最終状態を返す
+    async fn wait_for_job(base: &str, job_id: &str) -> serde_json::Value {
+        for _ in 0..500 {
+            let (_, body) = get(base, &format!("/api/download/{}", job_id)).await;
+            let job: serde_json::Value = serde_json::from_str(&body).unwrap();
+            if job["status"] != "pending" {
+                return job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("ジョブ {} が終わりません", job_id);
+    }
+
+    /// 標準入力を EOF まで読み、読んだ内容と GITHUB_* 環境変数を echo_file に書くスクリプトで実行する
+    async fn run_echo_script(legacy_env: bool) -> serde_json::Value {
+        let dir = app_dir();
+        let mut state = test_state(&dir);
+        Arc::get_mut(&mut state).unwrap().legacy_env_credentials = legacy_env;
+        let echo_file = dir.path().join("echo.json");
+        std::fs::write(
+            &state.script_path,
+            format!(
+                "import {{ readFileSync, writeFileSync }} from 'node:fs';\n\
+                 const stdin = readFileSync(0, 'utf8');\n\
+                 writeFileSync({:?}, JSON.stringify({{\n\
+                   stdin,\n\
+                   username: process.env.GITHUB_USERNAME ?? null,\n\
+                   password: process.env.GITHUB_PASSWORD ?? null,\n\
+                 }}));\n",
+                echo_file.display().to_string()
+            ),
+        )
+        .unwrap();
+        let base = serve(state).await;
+
+        let (status, body) = post_download(
+            &base,
+            serde_json::json!({
+                "skipSetup": true,
+                "githubUsername": "octocat",
+                "githubPassword": "s3cret",
+            }),
+        )
+        .await;
+        assert_eq!(status, 202, "{}", body);
+        // 標準入力が閉じられなければスクリプトは終わらない
+        let job = wait_for_job(&base, body["jobId"].as_str().unwrap()).await;
+        assert_eq!(job["status"], "success", "{}", job);
+        serde_json::from_str(&std::fs::read_to_string(echo_file).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn credentials_are_written_to_stdin_and_the_pipe_is_closed() {
+        let echo = run_echo_script(false).await;
+        let stdin = echo["stdin"].as_str().unwrap();
+        assert_eq!(stdin.lines().count(), 1);
+        assert!(stdin.ends_with('\n'));
+        let line: serde_json::Value = serde_json::from_str(stdin).unwrap();
+        assert_eq!(line["githubUsername"], "octocat");
+        assert_eq!(line["githubPassword"], "s3cret");
+        assert_eq!(echo["username"], serde_json::Value::Null);
+        assert_eq!(echo["password"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn legacy_env_passes_credentials_in_the_environment() {
+        let echo = run_echo_script(true).await;
+        assert_eq!(echo["stdin"], "");
+        assert_eq!(echo["username"], "octocat");
+        assert_eq!(echo["password"], "s3cret");
+    }
+
+    /// script.js を source にして POST /api/download を実行し、ジョブの最終状態を返す
+    async fn download_with_script(source: &str) -> (TempDir, serde_json::Value) {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        std::fs::write(&state.script_path, source).unwrap();
+        let base = serve(state).await;
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 202, "{}", body);
+        let job = wait_for_job(&base, body["jobId"].as_str().unwrap()).await;
+        (dir, job)
+    }
+
+    /// DENCHO_OUTPUT_DIR に a.pdf を保存するスクリプト（最後に last_line を出力する）
+    fn saving_script(last_line: &str) -> String {
+        format!(
+            "import {{ writeFileSync }} from 'node:fs';\n\
+             import {{ join }} from 'node:path';\n\
+             const file = join(process.env.DENCHO_OUTPUT_DIR, 'a.pdf');\n\
+             writeFileSync(file, '12345');\n\
+             console.log('[2024-01-31 09:00:00] 保存しました');\n\
+             {}\n",
+            last_line
+        )
+    }
+
+    #[tokio::test]
+    async fn files_reported_by_the_script_are_returned() {
+        let (dir, job) = download_with_script(&saving_script(
+            "console.log(JSON.stringify({ files: [file] }));",
+        ))
+        .await;
+        assert_eq!(job["status"], "success", "{}", job);
+        assert_eq!(job["filesWarning"], serde_json::Value::Null);
+        let files = job["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        let dir_name = dir.path().file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            files[0]["path"],
+            format!("target/{}/invoices/a.pdf", dir_name)
+        );
+        assert_eq!(files[0]["size"], 5);
+    }
+
+    #[tokio::test]
+    async fn without_a_files_line_the_output_folder_is_scanned() {
+        let (_dir, job) = download_with_script(&saving_script("")).await;
+        assert_eq!(job["status"], "success", "{}", job);
+        assert_eq!(job["filesWarning"], serde_json::Value::Null);
+        let files = job["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("/invoices/a.pdf"));
+    }
+
+    #[tokio::test]
+    async fn unparseable_files_lines_do_not_fail_the_download() {
+        let (_dir, job) =
+            download_with_script(&saving_script("console.log('{\"files\": \"a.pdf\"');")).await;
+        assert_eq!(job["status"], "success", "{}", job);
+        assert_eq!(job["files"], serde_json::json!([]));
+        assert!(job["filesWarning"].as_str().unwrap().contains("files"));
+    }
+
+    /// ルーターに 1 リクエストだけ送る（送信元はループバック、API キー付き）
+    async fn oneshot(
+        state: Arc<AppState>,
+        request: axum::http::request::Builder,
+        body: &'static str,
+    ) -> (StatusCode, HeaderMap, serde_json::Value) {
+        use tower::ServiceExt;
+
+        let mut request = request
+            .header("X-Api-Key", TEST_API_KEY)
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 50000))));
+        let response = build_router(state.clone(), build_cors_layer(state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (
+            parts.status,
+            parts.headers,
+            serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+        )
+    }
+
+    /// 本文の requestId がレスポンスヘッダーの X-Request-Id と同じか
+    fn has_request_id(headers: &HeaderMap, body: &serde_json::Value) -> bool {
+        let header = headers.get(request_id::HEADER).unwrap().to_str().unwrap();
+        body["requestId"] == header
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_get_json_errors() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        let download = || {
+            axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/api/download")
+        };
+
+        let (status, headers, body) = oneshot(
+            state.clone(),
+            download().header(header::CONTENT_TYPE, "application/json"),
+            "{\"skipSetup\": ",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["code"], "InvalidRequest");
+        assert!(has_request_id(&headers, &body), "{}", body);
+
+        let (status, headers, body) = oneshot(state.clone(), download(), "{}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "InvalidRequest");
+        assert!(body["message"].as_str().unwrap().contains("Content-Type"));
+        assert!(has_request_id(&headers, &body), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn downloads_run_without_history_when_it_is_disabled() {
+        let dir = app_dir();
+        let mut state = test_state(&dir);
+        Arc::get_mut(&mut state).unwrap().history = None;
+        let base = serve(state).await;
+
+        let (status, body) = post_download(&base, serde_json::json!({ "skipSetup": true })).await;
+        assert_eq!(status, 202, "{}", body);
+        let job_id = body["jobId"].as_str().unwrap().to_string();
+        let job = wait_for_job(&base, &job_id).await;
+        assert_eq!(job["status"], "success", "{}", job);
+
+        for path in ["/api/history".to_string(), format!("/api/jobs/{}", job_id)] {
+            let (status, body) = get(&base, &path).await;
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "NotFound");
+        }
+        // 履歴が無くても、ファイルが無ければ 404 のまま
+        let (status, _) = get(&base, "/api/invoices/missing.pdf").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_and_methods_get_json_errors() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+
+        let (status, headers, body) = oneshot(
+            state.clone(),
+            axum::http::Request::builder().uri("/api/nope"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "NotFound");
+        assert!(has_request_id(&headers, &body), "{}", body);
+
+        let (status, headers, body) = oneshot(
+            state,
+            axum::http::Request::builder()
+                .method(Method::DELETE)
+                .uri("/api/download"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body["code"], "MethodNotAllowed");
+        assert!(headers.contains_key(header::ALLOW));
+        assert!(has_request_id(&headers, &body), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn api_requires_the_api_key() {
+        let dir = app_dir();
+        let base = serve(test_state(&dir)).await;
+        let status = |request: reqwest::RequestBuilder| async move {
+            request.send().await.unwrap().status().as_u16()
+        };
+        let url = format!("{}/api/status", base);
+
+        assert_eq!(status(client().get(&url)).await, 401);
+        assert_eq!(
+            status(client().get(&url).header("X-Api-Key", "wrong")).await,
+            401
+        );
+        assert_eq!(
+            status(client().get(&url).header("X-Api-Key", TEST_API_KEY)).await,
+            200
+        );
+        assert_eq!(
+            status(client().get(&url).bearer_auth(TEST_API_KEY)).await,
+            200
+        );
+        // 認証前に拒否するため、存在しない API でも 401
+        assert_eq!(
+            status(client().get(format!("{}/api/invoices/x.pdf", base))).await,
+            401
+        );
+        // 公開エンドポイントはキー無しで使える（環境チェックの結果で 200 / 503）
+        assert_ne!(status(client().get(format!("{}/health", base))).await, 401);
+        assert_eq!(status(client().get(format!("{}/version", base))).await, 200);
+    }
+
+    #[tokio::test]
+    async fn invoice_names_cannot_escape_the_folder() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        std::fs::write(state.invoices_dir.join("invoice.pdf"), "invoice").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let base = serve(state).await;
+
+        assert_eq!(
+            get(&base, "/api/invoices/invoice.pdf").await,
+            (StatusCode::OK, "invoice".to_string())
+        );
+        for name in [
+            "..%2Fsecret.txt",
+            "..%5Csecret.txt",
+            "%2E%2E%2Fsecret.txt",
+            "sub%2F..%2F..%2Fsecret.txt",
+            "invoice.pdf%00",
+            "a%22b.pdf",
+        ] {
+            let (status, body) = get(&base, &format!("/api/invoices/{}", name)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", name);
+            assert!(!body.contains("secret"), "{}", name);
+        }
+        assert_eq!(
+            get(&base, "/api/invoices/missing.pdf").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn cancelling_removes_only_reported_and_in_progress_files() {
+        let dir = app_dir();
+        let started_at = SystemTime::now();
+        let before = started_at - std::time::Duration::from_secs(60);
+        let after = started_at + std::time::Duration::from_secs(1);
+        let invoices = TempDir::new();
+        invoices.file("old.pdf", before);
+        invoices.file("old.pdf.crdownload", before);
+        // 実行中に別のプロセスやユーザーが置いたファイル
+        invoices.file("someone-elses.pdf", after);
+        let partial = invoices.file("invoice.pdf.crdownload", after);
+        let reported = invoices.file("reported.pdf", after);
+        let outside = dir.file("outside.pdf", after);
+        let stdout = format!(
+            "[2024-01-31] 保存しました\n{}\n",
+            serde_json::json!({ "files": [reported, outside] })
+        );
+
+        let mut files = cancelled_download_files(invoices.path(), started_at, Some(&stdout));
+        files.sort();
+        assert_eq!(files, [partial.clone(), reported]);
+
+        // files 行が無ければ一時ファイルだけ
+        assert_eq!(
+            cancelled_download_files(invoices.path(), started_at, None),
+            std::slice::from_ref(&partial)
+        );
+        assert_eq!(
+            cancelled_download_files(invoices.path(), started_at, Some("{\"files\": 1}")),
+            [partial]
+        );
+    }
+
+    #[test]
+    fn scripts_must_be_inside_the_application_root() {
+        let dir = app_dir();
+        let root = dir.path().join("app");
+        std::fs::create_dir_all(root.join("dist")).unwrap();
+        std::fs::write(root.join("dist").join("script.js"), "").unwrap();
+        std::fs::write(dir.path().join("evil.js"), "").unwrap();
+
+        assert!(ensure_within_root(&root, &root.join("dist").join("script.js")).is_ok());
+        assert!(ensure_within_root(&root, &dir.path().join("evil.js")).is_err());
+        assert!(ensure_within_root(&root, &root.join("..").join("evil.js")).is_err());
+        // 解決できないパスはルート配下と確認できないため拒否する
+        assert!(ensure_within_root(&root, &root.join("missing.js")).is_err());
+        assert!(ensure_within_root(
+            &dir.path().join("no-root"),
+            &root.join("dist").join("script.js")
+        )
+        .is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("evil.js"), root.join("link.js")).unwrap();
+            assert!(ensure_within_root(&root, &root.join("link.js")).is_err());
+            std::os::unix::fs::symlink(root.join("gone.js"), root.join("dangling.js")).unwrap();
+            assert!(ensure_within_root(&root, &root.join("dangling.js")).is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn invoice_symlinks_outside_the_folder_are_rejected() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(
+            dir.path().join("secret.txt"),
+            state.invoices_dir.join("link.pdf"),
+        )
+        .unwrap();
+        let base = serve(state).await;
+
+        let (status, body) = get(&base, "/api/invoices/link.pdf").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!body.contains("secret"));
+    }
+
+    /// Origin から POST のプリフライトを送り、返ってきた Access-Control-Allow-Origin
+    async fn preflight(state: Arc<AppState>, origin: &'static str) -> Option<String> {
+        let (status, headers, _) = oneshot(
+            state,
+            axum::http::Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/api/download")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        headers
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn preflight_reflects_only_allowed_origins() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        *state.cors.write().unwrap() = config::CorsConfig {
+            allowed_origins: vec!["http://localhost:5173".into(), "app://dencho".into()],
+            allow_any: false,
+        };
+
+        assert_eq!(
+            preflight(state.clone(), "http://localhost:5173")
+                .await
+                .as_deref(),
+            Some("http://localhost:5173")
+        );
+        assert_eq!(
+            preflight(state.clone(), "app://dencho").await.as_deref(),
+            Some("app://dencho")
+        );
+        assert_eq!(preflight(state.clone(), "http://evil.example").await, None);
+
+        state.cors.write().unwrap().allow_any = true;
+        assert_eq!(
+            preflight(state, "http://evil.example").await.as_deref(),
+            Some("http://evil.example")
+        );
+    }
+
+    #[tokio::test]
+    async fn status_reports_uptime_version_and_the_last_download() {
+        let dir = app_dir();
+        let clock = TestClock::new("2024-01-31T00:00:00Z".parse().unwrap());
+        let state = test_state_with_clock(&dir, clock.clone());
+        clock.advance(std::time::Duration::from_secs(90));
+        let status_request = || axum::http::Request::builder().uri("/api/status");
+
+        let (status, _, body) = oneshot(state.clone(), status_request(), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["mode"], "console");
+        assert_eq!(body["port"], 0);
+        assert_eq!(body["paused"], false);
+        assert_eq!(body["uptimeSeconds"], 90);
+        assert_eq!(body["lastDownload"], serde_json::Value::Null);
+
+        *state.last_download.lock().unwrap() = Some(LastDownload {
+            finished_at: state.clock.now().with_timezone(&chrono::Local),
+            status: "error".to_string(),
+            message: "タイムアウトしました".to_string(),
+            code: Some(ErrorCode::DownloadTimeout),
+        });
+        let (_, _, body) = oneshot(state, status_request(), "").await;
+        let last = &body["lastDownload"];
+        assert_eq!(last["status"], "error");
+        assert_eq!(last["message"], "タイムアウトしました");
+        assert_eq!(last["code"], "DownloadTimeout");
+        assert!(last["finishedAt"].is_string());
+    }
+
+    #[tokio::test]
+    async fn setup_and_download_use_the_same_browsers_path() {
+        let browsers = app_dir();
+        // 他のテストが読んでも困らない値（この一時フォルダ）にしておく
+        std::env::set_var("PLAYWRIGHT_BROWSERS_PATH", browsers.path());
+        // セットアップ・環境チェックは readiness::playwright_browsers_path() をそのまま使う
+        assert_eq!(readiness::playwright_browsers_path(), browsers.path());
+
+        let (dir, job) = download_with_script(
+            "import { writeFileSync } from 'node:fs';\n\
+             import { join } from 'node:path';\n\
+             writeFileSync(join(process.env.DENCHO_OUTPUT_DIR, 'browsers.txt'),\n\
+               process.env.PLAYWRIGHT_BROWSERS_PATH);\n",
+        )
+        .await;
+        assert_eq!(job["status"], "success", "{}", job);
+        let passed = std::fs::read_to_string(dir.path().join("invoices/browsers.txt")).unwrap();
+        assert_eq!(std::path::Path::new(&passed), browsers.path());
+    }
+
+    #[tokio::test]
+    async fn downloads_get_503_while_paused() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        let download = || {
+            axum::http::Request::builder()
+                .method(Method::POST)
+                .uri("/api/download")
+                .header(header::CONTENT_TYPE, "application/json")
+        };
+        let control = |path: &'static str| {
+            axum::http::Request::builder()
+                .method(Method::POST)
+                .uri(path)
+        };
+
+        let (status, _, body) = oneshot(state.clone(), control("/api/pause"), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["paused"], true);
+
+        let (status, _, body) = oneshot(state.clone(), download(), r#"{"skipSetup": true}"#).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "Paused");
+        assert!(body["message"].as_str().unwrap().contains("一時停止中"));
+        let (_, _, body) = oneshot(
+            state.clone(),
+            axum::http::Request::builder().uri("/health"),
+            "",
+        )
+        .await;
+        assert_eq!(body["paused"], true);
+        assert!(state.running_download.lock().unwrap().is_none());
+
+        let (_, _, body) = oneshot(state.clone(), control("/api/resume"), "").await;
+        assert_eq!(body["paused"], false);
+        let (status, _, body) = oneshot(state, download(), r#"{"skipSetup": true}"#).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn disconnecting_from_a_stream_records_the_last_download() {
+        let dir = app_dir();
+        let state = test_state(&dir);
+        std::fs::write(
+            &state.script_path,
+            "console.log('started');\nsetInterval(() => {}, 1000);\n",
+        )
+        .unwrap();
+        let base = serve(state.clone()).await;
+
+        let mut response = client()
+            .get(format!("{}/api/download/stream?skipSetup=true", base))
+            .header("X-Api-Key", TEST_API_KEY)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        // 最初のイベントが届いてから切断する
+        let chunk = response.chunk().await.unwrap().unwrap();
+        assert!(String::from_utf8_lossy(&chunk).contains("started"));
+        drop(response);
+
+        assert!(
+            eventually(|| state
+                .last_download
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|last| last.status == "cancelled"))
+            .await
+        );
+        assert!(eventually(|| state.children.len() == 0).await);
+    }
 }
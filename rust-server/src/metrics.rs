@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// ダウンロード結果の種類（DownloadResponse の status、これ以外は error として数える）
+const RESULTS: [&str; 4] = ["success", "error", "timeout", "cancelled"];
+
+/// 所要時間ヒストグラムのバケット上限（秒）
+const DURATION_BUCKETS: [f64; 8] = [5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+/// GET /metrics で公開するダウンロードの統計（Prometheus テキスト形式）
+#[derive(Default)]
+pub struct Metrics {
+    /// RESULTS と同じ並びの終了件数
+    finished: [AtomicU64; RESULTS.len()],
+    /// 受け付けなかったリクエストの件数（HTTP ステータス別）
+    rejected: Mutex<BTreeMap<u16, u64>>,
+    /// 実行中のダウンロード数
+    running: AtomicI64,
+    /// DURATION_BUCKETS と同じ並び（最後は上限超過）の件数
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_millis: AtomicU64,
+}
+
+impl Metrics {
+    /// ダウンロードの終了を記録（duration が None なら実行前に終わったもの）
+    pub fn record_finished(&self, status: &str, duration: Option<Duration>) {
+        let index = RESULTS.iter().position(|r| *r == status).unwrap_or(1);
+        self.finished[index].fetch_add(1, Ordering::Relaxed);
+        if let Some(duration) = duration {
+            let secs = duration.as_secs_f64();
+            let bucket = DURATION_BUCKETS
+                .iter()
+                .position(|le| secs <= *le)
+                .unwrap_or(DURATION_BUCKETS.len());
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            self.duration_sum_millis
+                .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// 受け付けなかったダウンロード要求を記録（同時実行・レート制限・一時停止など）
+    pub fn record_rejected(&self, status: u16) {
+        *self
+            .rejected
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(status)
+            .or_default() += 1;
+    }
+
+    pub fn download_started(&self) {
+        self.running.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn download_stopped(&self) {
+        self.running.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Prometheus テキスト形式（version 0.0.4）で出力
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP dencho_downloads_total 終了したダウンロードの件数（結果別）\n");
+        out.push_str("# TYPE dencho_downloads_total counter\n");
+        for (result, count) in RESULTS.iter().zip(&self.finished) {
+            let _ = writeln!(
+                out,
+                "dencho_downloads_total{{result=\"{}\"}} {}",
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str(
+            "# HELP dencho_download_rejections_total 受け付けなかったダウンロード要求の件数（HTTP ステータス別）\n",
+        );
+        out.push_str("# TYPE dencho_download_rejections_total counter\n");
+        for (status, count) in self
+            .rejected
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "dencho_download_rejections_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+
+        out.push_str("# HELP dencho_downloads_running 実行中のダウンロード数\n");
+        out.push_str("# TYPE dencho_downloads_running gauge\n");
+        let _ = writeln!(
+            out,
+            "dencho_downloads_running {}",
+            self.running.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP dencho_download_duration_seconds ダウンロードの所要時間\n");
+        out.push_str("# TYPE dencho_download_duration_seconds histogram\n");
+        let mut cumulative = 0;
+        for (index, count) in self.duration_buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = DURATION_BUCKETS
+                .get(index)
+                .map_or_else(|| "+Inf".to_string(), |le| le.to_string());
+            let _ = writeln!(
+                out,
+                "dencho_download_duration_seconds_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "dencho_download_duration_seconds_sum {}",
+            self.duration_sum_millis.load(Ordering::Relaxed) as f64 / 1000.0
+        );
+        let _ = writeln!(out, "dencho_download_duration_seconds_count {}", cumulative);
+        out
+    }
+}
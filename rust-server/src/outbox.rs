@@ -0,0 +1,295 @@
+use crate::clock::Clock;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// 配信の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxKind {
+    /// 出力先（S3 / Azure）へのアップロード
+    Upload,
+    /// [[webhooks]]・DENCHO_WEBHOOK_URL への通知（destination は通知先のホストと URL のハッシュ）
+    Webhook,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// 再送待ち
+    Pending,
+    /// 最大保持期間を超えたため再送を諦めた（手動で retry / discard する、discard_after を過ぎると自動で破棄）
+    Failed,
+}
+
+/// 配信に失敗したイベント
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEntry {
+    pub id: String,
+    /// 受信側で重複排除するためのキー（最初の配信から再送まで変わらない）
+    pub idempotency_key: String,
+    pub kind: OutboxKind,
+    pub destination: String,
+    pub payload: serde_json::Value,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+/// 再送ポリシー
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_age: Duration,
+    /// 登録からこの時間を過ぎた failed のエントリは破棄する
+    pub discard_after: Duration,
+}
+
+impl RetryPolicy {
+    /// attempts 回失敗した後の待ち時間（指数バックオフ、上限あり）
+    fn delay(&self, attempts: u32) -> Duration {
+        let factor = 1i32 << attempts.saturating_sub(1).min(20);
+        (self.base_delay * factor).min(self.max_delay)
+    }
+}
+
+/// 永続化される送信キュー（data/outbox.json）
+pub struct Outbox {
+    path: PathBuf,
+    policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<Vec<OutboxEntry>>,
+}
+
+impl Outbox {
+    /// ファイルから読み込む（無ければ空）
+    pub fn load(path: PathBuf, policy: RetryPolicy, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        let entries = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("outbox 読み込み失敗: {} ({})", path.display(), e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("outbox の形式が不正です: {} ({})", path.display(), e))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            policy,
+            clock,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// 一時ファイルに書いてから置き換える
+    fn persist(&self, entries: &[OutboxEntry]) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("outbox ディレクトリ作成失敗: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(entries)
+            .map_err(|e| format!("outbox シリアライズ失敗: {}", e))?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json).map_err(|e| format!("outbox 書き込み失敗: {}", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| format!("outbox 書き込み失敗: {}", e))
+    }
+
+    pub fn list(&self) -> Vec<OutboxEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// 失敗した配信を登録し、エントリ ID を返す
+    ///
+    /// idempotency_key は失敗した最初の配信で送ったもの（再送でも同じキーを送る）。
+    pub fn enqueue(
+        &self,
+        kind: OutboxKind,
+        destination: &str,
+        payload: serde_json::Value,
+        idempotency_key: &str,
+        error: &str,
+    ) -> Result<String, String> {
+        let now = self.clock.now();
+        let entry = OutboxEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            idempotency_key: idempotency_key.to_string(),
+            kind,
+            destination: destination.to_string(),
+            payload,
+            status: OutboxStatus::Pending,
+            attempts: 1,
+            created_at: now,
+            next_attempt_at: now + self.policy.delay(1),
+            last_error: Some(error.to_string()),
+        };
+        let id = entry.id.clone();
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(entry);
+        self.persist(&entries)?;
+        Ok(id)
+    }
+
+    /// 再送時刻を過ぎたエントリ
+    pub fn due(&self) -> Vec<OutboxEntry> {
+        let now = self.clock.now();
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.status == OutboxStatus::Pending && e.next_attempt_at <= now)
+            .cloned()
+            .collect()
+    }
+
+    /// 配信成功: キューから削除
+    pub fn mark_delivered(&self, id: &str) -> Result<(), String> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.id != id);
+        self.persist(&entries)
+    }
+
+    /// 配信失敗: 次回時刻を延ばす。最大保持期間を超えたら Failed にして true を返す
+    pub fn mark_failed(&self, id: &str, error: &str) -> Result<bool, String> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter_mut().find(|e| e.id == id) else {
+            return Ok(false);
+        };
+
+        entry.attempts += 1;
+        entry.last_error = Some(error.to_string());
+        let gave_up = now - entry.created_at >= self.policy.max_age;
+        if gave_up {
+            entry.status = OutboxStatus::Failed;
+        } else {
+            entry.next_attempt_at = now + self.policy.delay(entry.attempts);
+        }
+
+        self.persist(&entries)?;
+        Ok(gave_up)
+    }
+
+    /// failed のまま discard_after を過ぎたエントリを破棄し、破棄したものを返す
+    pub fn discard_expired(&self) -> Result<Vec<OutboxEntry>, String> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        let (expired, kept): (Vec<_>, Vec<_>) = entries.drain(..).partition(|e| {
+            e.status == OutboxStatus::Failed && now - e.created_at >= self.policy.discard_after
+        });
+        *entries = kept;
+        if !expired.is_empty() {
+            self.persist(&entries)?;
+        }
+        Ok(expired)
+    }
+
+    /// 手動再送: 即座に再送対象にする（保持期間切れのものは 1 回だけ試行）
+    pub fn retry_now(&self, id: &str) -> Result<bool, String> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter_mut().find(|e| e.id == id) else {
+            return Ok(false);
+        };
+
+        entry.status = OutboxStatus::Pending;
+        entry.next_attempt_at = self.clock.now();
+        self.persist(&entries)?;
+        Ok(true)
+    }
+
+    /// 手動破棄
+    pub fn discard(&self, id: &str) -> Result<bool, String> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| e.id != id);
+        if entries.len() == before {
+            return Ok(false);
+        }
+        self.persist(&entries)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::test_util::TempDir;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::seconds(60),
+            max_delay: Duration::seconds(3600),
+            max_age: Duration::hours(72),
+            discard_after: Duration::hours(7 * 24),
+        }
+    }
+
+    fn outbox(dir: &TempDir, clock: &Arc<TestClock>) -> Outbox {
+        Outbox::load(dir.path().join("outbox.json"), policy(), clock.clone()).unwrap()
+    }
+
+    fn hours(n: u64) -> std::time::Duration {
+        std::time::Duration::from_secs(n * 3600)
+    }
+
+    #[test]
+    fn idempotency_key_is_kept_and_persisted() {
+        let dir = TempDir::new();
+        let clock = TestClock::new("2024-01-31T00:00:00Z".parse().unwrap());
+        let id = outbox(&dir, &clock)
+            .enqueue(
+                OutboxKind::Upload,
+                "s3",
+                serde_json::json!({}),
+                "key-1",
+                "HTTP 503",
+            )
+            .unwrap();
+
+        let reloaded = outbox(&dir, &clock);
+        let entry = reloaded.list().into_iter().find(|e| e.id == id).unwrap();
+        assert_eq!(entry.idempotency_key, "key-1");
+    }
+
+    #[test]
+    fn retries_back_off_then_fail_and_expire() {
+        let dir = TempDir::new();
+        let clock = TestClock::new("2024-01-31T00:00:00Z".parse().unwrap());
+        let outbox = outbox(&dir, &clock);
+        let id = outbox
+            .enqueue(
+                OutboxKind::Upload,
+                "s3",
+                serde_json::json!({}),
+                "key-1",
+                "HTTP 503",
+            )
+            .unwrap();
+
+        assert!(outbox.due().is_empty());
+        clock.advance(std::time::Duration::from_secs(60));
+        assert_eq!(outbox.due().len(), 1);
+        assert!(!outbox.mark_failed(&id, "HTTP 503").unwrap());
+        // 2 回目の失敗の後は 120 秒待つ
+        clock.advance(std::time::Duration::from_secs(119));
+        assert!(outbox.due().is_empty());
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(outbox.due().len(), 1);
+
+        clock.advance(hours(72));
+        assert!(outbox.mark_failed(&id, "HTTP 503").unwrap());
+        assert!(outbox.due().is_empty());
+        assert!(outbox.discard_expired().unwrap().is_empty());
+
+        clock.advance(hours(7 * 24 - 72));
+        let expired = outbox.discard_expired().unwrap();
+        assert_eq!(expired.len(), 1);
+        assert!(outbox.list().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::{Component, Path, PathBuf};
+
+/// リクエストの outputDir を検証してフォルダを作成し、その絶対パスを返す
+///
+/// 相対パスは base（download.output_base）からのパスとして扱う。絶対パスは base 配下のみ許可し、
+/// `..` やシンボリックリンクで base の外に出るものは拒否する。
+pub fn resolve(base: &Path, requested: &str) -> Result<PathBuf, String> {
+    let requested_path = Path::new(requested);
+    let outside = || {
+        format!(
+            "outputDir は {} の中を指定してください: {}",
+            base.display(),
+            requested
+        )
+    };
+    if requested_path
+        .components()
+        .any(|c| matches!(c, Component::ParentDir))
+    {
+        return Err(format!("outputDir に .. は使えません: {}", requested));
+    }
+    let dir = if requested_path.is_absolute() {
+        if !requested_path.starts_with(base) {
+            return Err(outside());
+        }
+        requested_path.to_path_buf()
+    } else {
+        // Windows の \foo や C:foo のように、相対パスでもルートやドライブを含むものは拒否する
+        if !requested_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!("outputDir が不正です: {}", requested));
+        }
+        base.join(requested_path)
+    };
+
+    std::fs::create_dir_all(base)
+        .map_err(|e| format!("{} を作成できません: {}", base.display(), e))?;
+    let canonical_base = base
+        .canonicalize()
+        .map_err(|e| format!("{} を確認できません: {}", base.display(), e))?;
+    // シンボリックリンクで外に出る場合は、外にフォルダを作る前に拒否する
+    let existing = dir
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .and_then(|ancestor| ancestor.canonicalize().ok());
+    if !existing.is_some_and(|existing| existing.starts_with(&canonical_base)) {
+        return Err(outside());
+    }
+
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("{} を作成できません: {}", dir.display(), e))?;
+    let canonical = dir
+        .canonicalize()
+        .map_err(|e| format!("{} を確認できません: {}", dir.display(), e))?;
+    if !canonical.starts_with(&canonical_base) {
+        return Err(outside());
+    }
+    if !canonical.is_dir() {
+        return Err(format!("outputDir がフォルダではありません: {}", requested));
+    }
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn relative_dirs_are_created_under_the_base() {
+        let dir = TempDir::new();
+        let base = dir.path().join("downloads");
+        let resolved = resolve(&base, "2024/01").unwrap();
+        assert!(resolved.is_dir());
+        assert_eq!(
+            resolved,
+            base.canonicalize().unwrap().join("2024").join("01")
+        );
+
+        let absolute = base.join("abs");
+        assert!(resolve(&base, absolute.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn dirs_outside_the_base_are_rejected_without_creating_them() {
+        let dir = TempDir::new();
+        let base = dir.path().join("downloads");
+        let outside = dir.path().join("outside");
+
+        assert!(resolve(&base, "../outside").is_err());
+        assert!(resolve(&base, "a/../../outside").is_err());
+        assert!(resolve(&base, outside.to_str().unwrap()).is_err());
+        assert!(!outside.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_base_are_rejected() {
+        let dir = TempDir::new();
+        let base = dir.path().join("downloads");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, base.join("link")).unwrap();
+
+        assert!(resolve(&base, "link").is_err());
+        assert!(resolve(&base, "link/new").is_err());
+        assert!(!outside.join("new").exists());
+    }
+
+    #[test]
+    fn files_are_not_accepted_as_dirs() {
+        let dir = TempDir::new();
+        let base = dir.path().join("downloads");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("file.pdf"), "").unwrap();
+        assert!(resolve(&base, "file.pdf").is_err());
+    }
+}
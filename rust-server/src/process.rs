@@ -0,0 +1,140 @@
+use std::process::Command;
+
+/// 子プロセスを新しいプロセスグループで起動する（ツリーごと終了できるようにする）
+pub fn isolate_process_group(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = cmd;
+    }
+}
+
+/// プロセスとその子孫（Playwright が起動したブラウザ等）をまとめて強制終了する
+pub fn kill_process_tree(pid: u32) {
+    let result = if cfg!(target_os = "windows") {
+        Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .output()
+    } else {
+        // isolate_process_group で pid = プロセスグループ ID になっている
+        // （`--` が無いと procps-ng の kill は -<pgid> をオプションとして読み、何も終了させない）
+        Command::new("kill")
+            .args(["-KILL", "--", &format!("-{}", pid)])
+            .output()
+    };
+
+    if let Err(e) = result {
+        crate::logging::log_to_file(
+            crate::logging::Level::Warn,
+            &format!("プロセスツリーの終了に失敗しました (pid={}): {}", pid, e),
+        );
+    }
+}
+
+/// サーバーが起動した子プロセスの一覧（終了時にまとめて止めるため）
+#[derive(Default)]
+pub struct ChildTracker {
+    pids: std::sync::Mutex<std::collections::HashSet<u32>>,
+}
+
+impl ChildTracker {
+    /// pid を登録する（返り値をドロップすると登録解除）
+    pub fn track(&self, pid: u32) -> TrackedChild<'_> {
+        self.pids.lock().unwrap().insert(pid);
+        TrackedChild { tracker: self, pid }
+    }
+
+    /// 登録中のプロセス数
+    pub fn len(&self) -> usize {
+        self.pids.lock().unwrap().len()
+    }
+
+    /// 登録中のプロセスツリーをすべて終了し、終了させた数を返す
+    pub fn kill_all(&self) -> usize {
+        let pids: Vec<u32> = self.pids.lock().unwrap().drain().collect();
+        for pid in &pids {
+            kill_process_tree(*pid);
+        }
+        pids.len()
+    }
+}
+
+/// ChildTracker への登録（ドロップで解除）
+pub struct TrackedChild<'a> {
+    tracker: &'a ChildTracker,
+    pid: u32,
+}
+
+impl Drop for TrackedChild<'_> {
+    fn drop(&mut self) {
+        self.tracker.pids.lock().unwrap().remove(&self.pid);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::{process_alive, wait_for_file, wait_until, TempDir};
+
+    /// 孫プロセス（sleep）を起動して待つシェルを、新しいプロセスグループで起動する
+    fn spawn_sleeping_tree(dir: &TempDir) -> (std::process::Child, u32) {
+        let pid_file = dir.path().join("grandchild.pid");
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(format!(
+            "sleep 300 & echo $! > '{}'; wait",
+            pid_file.display()
+        ));
+        isolate_process_group(&mut cmd);
+        let child = cmd.spawn().unwrap();
+        let grandchild = wait_for_file(&pid_file).trim().parse().unwrap();
+        (child, grandchild)
+    }
+
+    #[test]
+    fn kill_process_tree_kills_grandchildren() {
+        let dir = TempDir::new();
+        let (mut child, grandchild) = spawn_sleeping_tree(&dir);
+        assert!(process_alive(grandchild));
+
+        kill_process_tree(child.id());
+        assert!(wait_until(|| child.try_wait().unwrap().is_some()));
+        assert!(wait_until(|| !process_alive(grandchild)));
+    }
+
+    #[test]
+    fn tracker_kills_registered_trees_only() {
+        let dir = TempDir::new();
+        let tracker = ChildTracker::default();
+        let (mut tracked_child, tracked_grandchild) = spawn_sleeping_tree(&dir);
+        let other = TempDir::new();
+        let (mut untracked_child, untracked_grandchild) = spawn_sleeping_tree(&other);
+
+        let tracked = tracker.track(tracked_child.id());
+        assert_eq!(tracker.len(), 1);
+        assert_eq!(tracker.kill_all(), 1);
+        assert_eq!(tracker.len(), 0);
+        drop(tracked);
+
+        assert!(wait_until(|| tracked_child.try_wait().unwrap().is_some()));
+        assert!(wait_until(|| !process_alive(tracked_grandchild)));
+        assert!(process_alive(untracked_grandchild));
+
+        kill_process_tree(untracked_child.id());
+        untracked_child.wait().unwrap();
+    }
+
+    #[test]
+    fn dropping_the_registration_untracks() {
+        let tracker = ChildTracker::default();
+        {
+            let _tracked = tracker.track(1234);
+            assert_eq!(tracker.len(), 1);
+        }
+        assert_eq!(tracker.len(), 0);
+        assert_eq!(tracker.kill_all(), 0);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::config::ProviderConfig;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// provider を省略したリクエストで実行するプロバイダー（download.script）
+pub const DEFAULT_PROVIDER: &str = "supabase";
+
+/// DEFAULT_PROVIDER の表示名
+const DEFAULT_PROVIDER_DISPLAY_NAME: &str = "Supabase";
+
+/// GET /api/providers の要素
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderInfo {
+    pub name: String,
+    pub display_name: String,
+    /// スクリプト（アプリケーションルートからの相対パス）
+    pub script: String,
+    pub script_exists: bool,
+    pub default: bool,
+}
+
+/// 登録済みのプロバイダー
+struct Provider {
+    script: PathBuf,
+    display_name: String,
+}
+
+/// 実行を許可するスクリプトの一覧
+///
+/// リクエストは名前でしか指定できず、ここに無いパスは実行しない。
+pub struct ProviderRegistry {
+    app_root: PathBuf,
+    providers: BTreeMap<String, Provider>,
+}
+
+impl ProviderRegistry {
+    /// default_script は DEFAULT_PROVIDER として登録する（[providers] の同名の設定より優先）
+    pub fn new(
+        app_root: &Path,
+        default_script: PathBuf,
+        configured: &HashMap<String, ProviderConfig>,
+    ) -> Self {
+        let mut providers: BTreeMap<String, Provider> = configured
+            .iter()
+            .map(|(name, provider)| {
+                let display_name = provider.display_name().unwrap_or(name).to_string();
+                let script = app_root.join(provider.script());
+                (
+                    name.clone(),
+                    Provider {
+                        script,
+                        display_name,
+                    },
+                )
+            })
+            .collect();
+        let display_name = configured
+            .get(DEFAULT_PROVIDER)
+            .and_then(ProviderConfig::display_name)
+            .unwrap_or(DEFAULT_PROVIDER_DISPLAY_NAME)
+            .to_string();
+        providers.insert(
+            DEFAULT_PROVIDER.to_string(),
+            Provider {
+                script: default_script,
+                display_name,
+            },
+        );
+        Self {
+            app_root: app_root.to_path_buf(),
+            providers,
+        }
+    }
+
+    /// 登録済みのスクリプトの絶対パス
+    pub fn script(&self, name: &str) -> Option<&Path> {
+        self.providers
+            .get(name)
+            .map(|provider| provider.script.as_path())
+    }
+
+    /// 表示名（登録されていなければ name をそのまま返す）
+    pub fn display_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.providers
+            .get(name)
+            .map_or(name, |provider| provider.display_name.as_str())
+    }
+
+    pub fn list(&self) -> Vec<ProviderInfo> {
+        self.providers
+            .iter()
+            .map(|(name, provider)| ProviderInfo {
+                name: name.clone(),
+                display_name: provider.display_name.clone(),
+                script: provider
+                    .script
+                    .strip_prefix(&self.app_root)
+                    .unwrap_or(&provider.script)
+                    .display()
+                    .to_string(),
+                script_exists: provider.script.exists(),
+                default: name == DEFAULT_PROVIDER,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderTable;
+
+    fn registry() -> ProviderRegistry {
+        let root = Path::new("/app");
+        let configured = HashMap::from([
+            (
+                "freee".to_string(),
+                ProviderConfig::Script("dist/download-freee-invoice.js".into()),
+            ),
+            (
+                "mf".to_string(),
+                ProviderConfig::Table(ProviderTable {
+                    script: "dist/download-mf-invoice.js".into(),
+                    display_name: Some("マネーフォワード".to_string()),
+                }),
+            ),
+        ]);
+        ProviderRegistry::new(root, root.join("dist/default.js"), &configured)
+    }
+
+    #[test]
+    fn display_names_come_from_the_registry() {
+        let registry = registry();
+        assert_eq!(registry.display_name(DEFAULT_PROVIDER), "Supabase");
+        assert_eq!(registry.display_name("mf"), "マネーフォワード");
+        assert_eq!(registry.display_name("freee"), "freee");
+        assert_eq!(registry.display_name("unknown"), "unknown");
+    }
+
+    #[test]
+    fn both_config_forms_resolve_under_the_root() {
+        let registry = registry();
+        let root = Path::new("/app");
+        assert_eq!(
+            registry.script("freee"),
+            Some(root.join("dist/download-freee-invoice.js").as_path())
+        );
+        assert_eq!(
+            registry.script("mf"),
+            Some(root.join("dist/download-mf-invoice.js").as_path())
+        );
+        assert_eq!(registry.script("unknown"), None);
+    }
+
+    #[test]
+    fn config_accepts_a_path_or_a_table() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [providers]
+            freee = "dist/download-freee-invoice.js"
+            mf = { script = "dist/download-mf-invoice.js", display_name = "マネーフォワード" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.providers["freee"].display_name(), None);
+        assert_eq!(
+            config.providers["mf"].display_name(),
+            Some("マネーフォワード")
+        );
+
+        // 未知のキーは無視する（load_config が警告を出す）
+        let unknown_key: crate::config::Config = toml::from_str(
+            r#"
+            [providers]
+            mf = { script = "dist/download-mf-invoice.js", label = "x" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            unknown_key.providers["mf"].script(),
+            std::path::Path::new("dist/download-mf-invoice.js")
+        );
+    }
+}
@@ -0,0 +1,849 @@
+use crate::config;
+use crate::credentials::{self, Credentials};
+use crate::logging::{log_to_file, Level};
+use chrono::{DateTime, Local};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+/// 初回起動時に取り込む設定ファイル（アプリケーションルート直下）
+pub const FILE_NAME: &str = "provisioning.toml";
+
+/// FILE_NAME を `provisioning encrypt` で暗号化したもの
+pub const ENCRYPTED_FILE_NAME: &str = "provisioning.toml.enc";
+
+/// 暗号化したファイルのパスフレーズ
+pub const PASSPHRASE_ENV: &str = "DENCHO_PROVISIONING_PASSPHRASE";
+
+/// 秘密情報を含まないファイルは、適用後にこの拡張子を付けて残す
+const APPLIED_EXTENSION: &str = "applied";
+
+/// 暗号化ファイルの先頭（形式のバージョンを兼ねる）
+const MAGIC: &[u8] = b"DENCHOP1";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 200_000;
+
+/// 適用済みの記録（data/provisioning.json、同じファイルを二度適用しない）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Marker {
+    /// 適用したファイルの SHA-256（暗号化ファイルは暗号文のまま）
+    sha256: String,
+    file: String,
+    applied_at: DateTime<Local>,
+}
+
+/// 適用の監査記録（data/provisioning-audit.jsonl に 1 行ずつ追記する）
+///
+/// 設定・認証情報の値は書かず、取り込んだセクションと認証情報の保存先の名前だけを残す。
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry {
+    time: DateTime<Local>,
+    file: String,
+    /// 適用したファイルの SHA-256（Marker と同じ）
+    sha256: String,
+    /// dencho.toml に取り込んだセクション
+    sections: Vec<String>,
+    /// 認証情報を保存した資格情報マネージャーのターゲット名
+    credential_sets: Vec<String>,
+    /// 秘密情報を含むためファイルを削除したか（false は .applied に名前を変更）
+    deleted: bool,
+}
+
+/// 認証情報の保存先（テストでは OS の資格情報マネージャーの代わりを使う）
+pub trait CredentialStore {
+    fn load(&self) -> Result<Option<Credentials>, String>;
+    fn store(&self, credentials: &Credentials) -> Result<(), String>;
+    fn delete(&self) -> Result<bool, String>;
+}
+
+/// Windows 資格情報マネージャー（credentials サブコマンドと同じ保存先）
+pub struct SystemCredentials;
+
+impl CredentialStore for SystemCredentials {
+    fn load(&self) -> Result<Option<Credentials>, String> {
+        credentials::load()
+    }
+
+    fn store(&self, credentials: &Credentials) -> Result<(), String> {
+        credentials::store(credentials)
+    }
+
+    fn delete(&self) -> Result<bool, String> {
+        credentials::delete()
+    }
+}
+
+/// apply の結果
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// プロビジョニングファイルが無い
+    NotFound,
+    /// 同じファイルを適用済み（イメージの再展開など）。ファイルは適用時と同じく片付けた
+    AlreadyApplied,
+    Applied {
+        /// 秘密情報を含んでいたため、ファイルを削除した
+        deleted: bool,
+    },
+}
+
+/// 検証済みの取り込み内容
+struct Provisioning {
+    /// dencho.toml に重ねる内容（[credentials] を除いたもの）
+    settings: toml::Table,
+    config: config::Config,
+    credentials: Option<Credentials>,
+}
+
+impl Provisioning {
+    /// 適用後にファイルを残してはいけないか（認証情報・Webhook の secret）
+    fn has_secrets(&self) -> bool {
+        self.credentials.is_some() || self.config.webhooks.iter().any(|w| w.secret.is_some())
+    }
+}
+
+/// [credentials] テーブル（資格情報マネージャーに保存する GitHub 認証情報）
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CredentialsTable {
+    username: String,
+    password: String,
+}
+
+/// app_root のプロビジョニングファイルを取り込む
+///
+/// 設定（[[schedule]]・[[webhooks]] を含む）を dencho.toml に重ね、[credentials] を資格情報マネージャーに書き込む。
+/// 検証に失敗した場合は何も変更せず、項目ごとのエラーを改行区切りで返す（ファイルはそのまま残す）。
+/// 書き込みの途中で失敗した場合は、保存した認証情報を元に戻す。
+pub fn apply(
+    app_root: &Path,
+    passphrase: Option<&str>,
+    store: &dyn CredentialStore,
+    now: DateTime<Local>,
+) -> Result<Outcome, String> {
+    let plain_path = app_root.join(FILE_NAME);
+    let encrypted_path = app_root.join(ENCRYPTED_FILE_NAME);
+    let (path, encrypted) = match (plain_path.exists(), encrypted_path.exists()) {
+        (false, false) => return Ok(Outcome::NotFound),
+        (true, true) => {
+            return Err(format!(
+                "{} と {} の両方があります。どちらか一方にしてください",
+                FILE_NAME, ENCRYPTED_FILE_NAME
+            ))
+        }
+        (true, false) => (plain_path, false),
+        (false, true) => (encrypted_path, true),
+    };
+    let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let bytes =
+        std::fs::read(&path).map_err(|e| format!("{} を読み込めません: {}", path.display(), e))?;
+    let sha256 = hex::encode(Sha256::digest(&bytes));
+
+    // イメージの再展開では同じファイルが残っている（パスフレーズが無くても判別できるよう暗号文で比べる）
+    let marker_path = marker_path(app_root);
+    if read_marker(&marker_path).is_some_and(|marker| marker.sha256 == sha256) {
+        log_to_file(
+            Level::Info,
+            &format!(
+                "{} は適用済みのため取り込みません（{}）",
+                file_name,
+                marker_path.display()
+            ),
+        );
+        // 秘密情報を含むか判別できなければ削除する
+        let delete_file = encrypted
+            || String::from_utf8(bytes)
+                .ok()
+                .and_then(|content| validate(&file_name, &content).ok())
+                .is_none_or(|provisioning| provisioning.has_secrets());
+        dispose(&path, delete_file)?;
+        return Ok(Outcome::AlreadyApplied);
+    }
+
+    let content = if encrypted {
+        let passphrase = passphrase.filter(|p| !p.is_empty()).ok_or_else(|| {
+            format!(
+                "{} を復号するには {} を設定してください",
+                file_name, PASSPHRASE_ENV
+            )
+        })?;
+        decrypt(&bytes, passphrase).map_err(|e| format!("{}: {}", file_name, e))?
+    } else {
+        bytes
+    };
+    let content = String::from_utf8(content)
+        .map_err(|_| format!("{} が UTF-8 のテキストではありません", file_name))?;
+    let provisioning = validate(&file_name, &content)?;
+    let delete_file = encrypted || provisioning.has_secrets();
+
+    write_stores(app_root, &provisioning, store)?;
+
+    let marker = Marker {
+        sha256: sha256.clone(),
+        file: file_name.clone(),
+        applied_at: now,
+    };
+    // 記録できなくても次回の起動で同じ内容を適用し直すだけなので、適用は取り消さない
+    if let Err(e) = write_marker(&marker_path, &marker) {
+        log_to_file(Level::Warn, &e);
+    }
+    dispose(&path, delete_file)?;
+
+    let audit = AuditEntry {
+        time: now,
+        file: file_name.clone(),
+        sha256,
+        sections: provisioning.settings.keys().cloned().collect(),
+        credential_sets: provisioning
+            .credentials
+            .iter()
+            .map(|_| credentials::TARGET_NAME.to_string())
+            .collect(),
+        deleted: delete_file,
+    };
+    if let Err(e) = append_audit(&audit_path(app_root), &audit) {
+        log_to_file(Level::Warn, &e);
+    }
+
+    let config = &provisioning.config;
+    log_to_file(
+        Level::Info,
+        &format!(
+            "プロビジョニングファイルを適用しました: {} (設定: {}, スケジュール: {} 件, Webhook: {} 件, 認証情報: {}, ファイル: {})",
+            file_name,
+            config::config_path(app_root).display(),
+            config.schedule.len(),
+            config.webhooks.len(),
+            if provisioning.credentials.is_some() {
+                credentials::TARGET_NAME
+            } else {
+                "なし"
+            },
+            if delete_file { "削除" } else { "名前を変更" }
+        ),
+    );
+    Ok(Outcome::Applied {
+        deleted: delete_file,
+    })
+}
+
+/// 内容を検証する（起動時・設定の再読み込みと同じ検査、未知のキーもエラーにする）
+fn validate(file_name: &str, content: &str) -> Result<Provisioning, String> {
+    let mut settings = toml::from_str::<toml::Table>(content)
+        .map_err(|e| format!("{} の形式が不正です:\n{}", file_name, e))?;
+    let credentials = settings.remove("credentials");
+    let mut errors = Vec::new();
+
+    let credentials = match credentials.map(|value| value.try_into::<CredentialsTable>()) {
+        None => None,
+        Some(Err(e)) => {
+            errors.push(format!("credentials: {}", e.message()));
+            None
+        }
+        Some(Ok(table)) => {
+            if table.username.is_empty() {
+                errors.push("credentials.username: 空にできません".to_string());
+            }
+            if table.password.is_empty() {
+                errors.push("credentials.password: 空にできません".to_string());
+            }
+            Some(Credentials {
+                username: table.username,
+                password: table.password,
+            })
+        }
+    };
+
+    let settings_text = toml::to_string(&settings)
+        .map_err(|e| format!("{} の設定を書き出せません: {}", file_name, e))?;
+    let config = match config::parse_config(Path::new(file_name), Some(&settings_text), &[]) {
+        Ok((config, unknown_keys)) => {
+            errors.extend(
+                unknown_keys
+                    .iter()
+                    .map(|key| format!("{}: 未知のキーです", key)),
+            );
+            for (index, entry) in config.schedule.iter().enumerate() {
+                if let Err(e) = crate::schedule::parse(std::slice::from_ref(entry)) {
+                    errors.push(format!("schedule[{}].cron: {}", index, e));
+                }
+            }
+            for (index, webhook) in config.webhooks.iter().enumerate() {
+                if let Err(e) =
+                    crate::webhook::check_url(&format!("webhooks[{}].url", index), &webhook.url)
+                {
+                    errors.push(e);
+                }
+            }
+            if let Err(e) = crate::validate_cors_config(&config.cors) {
+                errors.push(format!("cors.allowed_origins: {}", e));
+            }
+            if let Err(e) = crate::retention::Policy::from_config(&config.invoices) {
+                errors.push(e);
+            }
+            Some(config)
+        }
+        Err(e) => {
+            errors.push(e);
+            None
+        }
+    };
+
+    match config {
+        Some(config) if errors.is_empty() => Ok(Provisioning {
+            settings,
+            config,
+            credentials,
+        }),
+        _ => Err(format!(
+            "{} の内容が不正なため、何も適用していません:\n{}",
+            file_name,
+            errors.join("\n")
+        )),
+    }
+}
+
+/// 認証情報、dencho.toml の順に書き込む（dencho.toml の置き換えに失敗したら認証情報を戻す）
+fn write_stores(
+    app_root: &Path,
+    provisioning: &Provisioning,
+    store: &dyn CredentialStore,
+) -> Result<(), String> {
+    let config_path = config::config_path(app_root);
+    let merged = merge_config(&config_path, &provisioning.settings)
+        .map_err(|e| format!("{}（何も適用していません）", e))?;
+
+    let previous = match &provisioning.credentials {
+        Some(credentials) => {
+            let previous = store.load()?;
+            store
+                .store(credentials)
+                .map_err(|e| format!("認証情報を保存できないため、何も適用していません: {}", e))?;
+            Some(previous)
+        }
+        None => None,
+    };
+
+    if let Err(e) = write_config(&config_path, &merged) {
+        if let Some(previous) = previous {
+            let restored = match previous {
+                Some(previous) => store.store(&previous),
+                None => store.delete().map(|_| ()),
+            };
+            if let Err(restore_error) = restored {
+                log_to_file(
+                    Level::Error,
+                    &format!("認証情報を元に戻せませんでした: {}", restore_error),
+                );
+            }
+        }
+        return Err(format!("{}（何も適用していません）", e));
+    }
+    Ok(())
+}
+
+/// 既存の dencho.toml に取り込む内容を重ねる（無ければ取り込む内容だけ）
+///
+/// テーブルはキーごとに重ね、それ以外の値と配列（[[schedule]]・[[webhooks]]）は置き換える。
+fn merge_config(path: &Path, settings: &toml::Table) -> Result<toml::Table, String> {
+    let mut merged = match std::fs::read_to_string(path) {
+        Ok(content) => toml::from_str::<toml::Table>(&content)
+            .map_err(|e| format!("{} の形式が不正です:\n{}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => toml::Table::new(),
+        Err(e) => return Err(format!("{} を読み込めません: {}", path.display(), e)),
+    };
+    merge_table(&mut merged, settings);
+    Ok(merged)
+}
+
+fn merge_table(base: &mut toml::Table, overlay: &toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_table(base, overlay)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// 一時ファイルに書いてから置き換える
+fn write_config(path: &Path, settings: &toml::Table) -> Result<(), String> {
+    let content = toml::to_string(settings).map_err(|e| format!("設定を書き出せません: {}", e))?;
+    let tmp = path.with_extension("toml.tmp");
+    let written = std::fs::write(&tmp, content).and_then(|_| std::fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(format!("{} を書き込めません: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+fn marker_path(app_root: &Path) -> PathBuf {
+    app_root.join("data").join("provisioning.json")
+}
+
+fn read_marker(path: &Path) -> Option<Marker> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+fn write_marker(path: &Path, marker: &Marker) -> Result<(), String> {
+    let written = (|| {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(marker).map_err(std::io::Error::other)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, path)
+    })();
+    written.map_err(|e| {
+        format!(
+            "プロビジョニングの適用記録を書き込めません: {} ({})",
+            path.display(),
+            e
+        )
+    })
+}
+
+fn audit_path(app_root: &Path) -> PathBuf {
+    app_root.join("data").join("provisioning-audit.jsonl")
+}
+
+fn append_audit(path: &Path, entry: &AuditEntry) -> Result<(), String> {
+    use std::io::Write;
+
+    let written = (|| {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_string(entry).map_err(std::io::Error::other)?;
+        line.push('\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    })();
+    written.map_err(|e| {
+        format!(
+            "プロビジョニングの監査記録を書き込めません: {} ({})",
+            path.display(),
+            e
+        )
+    })
+}
+
+/// 適用したファイルを片付ける（秘密情報を含むものは削除、それ以外は .applied を付けて残す）
+fn dispose(path: &Path, delete: bool) -> Result<(), String> {
+    let result = if delete {
+        secure_delete(path)
+    } else {
+        let mut applied = path.as_os_str().to_owned();
+        applied.push(".");
+        applied.push(APPLIED_EXTENSION);
+        std::fs::rename(path, applied)
+    };
+    result.map_err(|e| format!("{} を片付けられません: {}", path.display(), e))
+}
+
+/// 中身をゼロで上書きしてから削除する
+///
+/// SSD やコピーオンライトのファイルシステムでは元のブロックが残りうるため、暗号化ファイルの利用を勧める。
+fn secure_delete(path: &Path) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let len = std::fs::metadata(path)?.len() as usize;
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len])?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0u8; 32];
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).expect("鍵の長さは AES-256 と同じ"))
+}
+
+/// provisioning.toml を暗号化する（MAGIC + salt + nonce + AES-256-GCM の暗号文）
+pub fn encrypt(plain: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| "乱数を生成できません".to_string())?;
+
+    let mut sealed = plain.to_vec();
+    derive_key(passphrase, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut sealed,
+        )
+        .map_err(|_| "暗号化に失敗しました".to_string())?;
+    Ok([MAGIC, &salt, &nonce, &sealed].concat())
+}
+
+fn decrypt(bytes: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+    if bytes.len() < header_len || !bytes.starts_with(MAGIC) {
+        return Err("暗号化ファイルの形式が不正です".to_string());
+    }
+    let salt = &bytes[MAGIC.len()..MAGIC.len() + SALT_LEN];
+    let nonce = Nonce::try_assume_unique_for_key(&bytes[MAGIC.len() + SALT_LEN..header_len])
+        .map_err(|_| "暗号化ファイルの形式が不正です".to_string())?;
+    let mut sealed = bytes[header_len..].to_vec();
+    let plain = derive_key(passphrase, salt)
+        .open_in_place(nonce, Aad::from(MAGIC), &mut sealed)
+        .map_err(|_| {
+            "復号できません（パスフレーズが違うか、ファイルが壊れています）".to_string()
+        })?;
+    Ok(plain.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::cell::RefCell;
+
+    /// 資格情報マネージャーの代わり（fail_store で保存を失敗させる）
+    #[derive(Default)]
+    struct MemoryStore {
+        stored: RefCell<Option<(String, String)>>,
+        fail_store: bool,
+    }
+
+    impl MemoryStore {
+        fn with(username: &str, password: &str) -> Self {
+            Self {
+                stored: RefCell::new(Some((username.to_string(), password.to_string()))),
+                fail_store: false,
+            }
+        }
+
+        fn stored(&self) -> Option<(String, String)> {
+            self.stored.borrow().clone()
+        }
+    }
+
+    impl CredentialStore for MemoryStore {
+        fn load(&self) -> Result<Option<Credentials>, String> {
+            Ok(self
+                .stored()
+                .map(|(username, password)| Credentials { username, password }))
+        }
+
+        fn store(&self, credentials: &Credentials) -> Result<(), String> {
+            if self.fail_store {
+                return Err("保存できません".to_string());
+            }
+            *self.stored.borrow_mut() =
+                Some((credentials.username.clone(), credentials.password.clone()));
+            Ok(())
+        }
+
+        fn delete(&self) -> Result<bool, String> {
+            Ok(self.stored.borrow_mut().take().is_some())
+        }
+    }
+
+    const SETTINGS: &str = r#"
+[server]
+port = 4000
+
+[[schedule]]
+name = "monthly"
+cron = "0 9 1 * *"
+
+[[webhooks]]
+url = "https://hooks.example.com/dencho"
+"#;
+
+    const CREDENTIALS: &str = r#"
+[credentials]
+username = "octocat"
+password = "s3cret"
+"#;
+
+    fn run(
+        dir: &TempDir,
+        passphrase: Option<&str>,
+        store: &MemoryStore,
+    ) -> Result<Outcome, String> {
+        apply(dir.path(), passphrase, store, Local::now())
+    }
+
+    fn written_config(dir: &TempDir) -> config::Config {
+        let content = std::fs::read_to_string(config::config_path(dir.path())).unwrap();
+        toml::from_str(&content).unwrap()
+    }
+
+    #[test]
+    fn nothing_happens_without_a_provisioning_file() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        assert_eq!(run(&dir, None, &store), Ok(Outcome::NotFound));
+        assert!(!config::config_path(dir.path()).exists());
+    }
+
+    #[test]
+    fn settings_are_imported_and_the_file_is_renamed() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        std::fs::write(dir.path().join(FILE_NAME), SETTINGS).unwrap();
+
+        assert_eq!(
+            run(&dir, None, &store),
+            Ok(Outcome::Applied { deleted: false })
+        );
+        let config = written_config(&dir);
+        assert_eq!(config.server.port, 4000);
+        assert_eq!(config.schedule[0].cron, "0 9 1 * *");
+        assert_eq!(config.webhooks[0].url, "https://hooks.example.com/dencho");
+        assert!(!dir.path().join(FILE_NAME).exists());
+        assert!(dir.path().join("provisioning.toml.applied").exists());
+        assert!(store.stored().is_none());
+    }
+
+    #[test]
+    fn settings_are_merged_into_the_existing_config() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        std::fs::write(
+            config::config_path(dir.path()),
+            "[server]\nbind = \"0.0.0.0\"\nport = 3000\n\n[log]\nlevel = \"debug\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join(FILE_NAME), SETTINGS).unwrap();
+
+        assert_eq!(
+            run(&dir, None, &store),
+            Ok(Outcome::Applied { deleted: false })
+        );
+        let config = written_config(&dir);
+        assert_eq!(config.server.port, 4000);
+        // 取り込む内容に無いキーは、同じテーブルの中でもそのまま残す
+        assert_eq!(config.server.bind, "0.0.0.0");
+        assert_eq!(config.log.level, crate::logging::Level::Debug);
+        assert_eq!(config.schedule.len(), 1);
+
+        // 既存の dencho.toml が壊れている場合は上書きしない
+        std::fs::write(config::config_path(dir.path()), "[server\n").unwrap();
+        std::fs::write(dir.path().join(FILE_NAME), "[server]\nport = 6000\n").unwrap();
+        assert!(run(&dir, None, &store).is_err());
+        assert_eq!(
+            std::fs::read_to_string(config::config_path(dir.path())).unwrap(),
+            "[server\n"
+        );
+        assert!(dir.path().join(FILE_NAME).exists());
+    }
+
+    #[test]
+    fn files_with_credentials_are_deleted_after_import() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        std::fs::write(
+            dir.path().join(FILE_NAME),
+            format!("{}{}", SETTINGS, CREDENTIALS),
+        )
+        .unwrap();
+
+        assert_eq!(
+            run(&dir, None, &store),
+            Ok(Outcome::Applied { deleted: true })
+        );
+        assert_eq!(store.stored(), Some(("octocat".into(), "s3cret".into())));
+        assert!(!dir.path().join(FILE_NAME).exists());
+        assert!(!dir.path().join("provisioning.toml.applied").exists());
+        // 認証情報は dencho.toml に書かない
+        let written = std::fs::read_to_string(config::config_path(dir.path())).unwrap();
+        assert!(!written.contains("s3cret"));
+        assert_eq!(written_config(&dir).server.port, 4000);
+    }
+
+    fn audit_entries(dir: &TempDir) -> Vec<AuditEntry> {
+        std::fs::read_to_string(audit_path(dir.path()))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn each_import_is_audited_without_values() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        let content = format!("{}{}", SETTINGS, CREDENTIALS);
+        std::fs::write(dir.path().join(FILE_NAME), &content).unwrap();
+        let now = Local::now();
+
+        assert_eq!(
+            apply(dir.path(), None, &store, now),
+            Ok(Outcome::Applied { deleted: true })
+        );
+        let entries = audit_entries(&dir);
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.time, now);
+        assert_eq!(entry.file, FILE_NAME);
+        assert_eq!(
+            entry.sha256,
+            hex::encode(Sha256::digest(content.as_bytes()))
+        );
+        assert_eq!(entry.sections, ["schedule", "server", "webhooks"]);
+        assert_eq!(entry.credential_sets, [credentials::TARGET_NAME]);
+        assert!(entry.deleted);
+        let written = std::fs::read_to_string(audit_path(dir.path())).unwrap();
+        for value in ["octocat", "s3cret", "4000", "hooks.example.com"] {
+            assert!(!written.contains(value), "{} が記録されています", value);
+        }
+
+        // 適用済みのファイルは取り込まないため記録しない、新しいファイルは追記する
+        std::fs::write(dir.path().join(FILE_NAME), &content).unwrap();
+        assert_eq!(run(&dir, None, &store), Ok(Outcome::AlreadyApplied));
+        std::fs::write(dir.path().join(FILE_NAME), "[server]\nport = 6000\n").unwrap();
+        assert_eq!(
+            run(&dir, None, &store),
+            Ok(Outcome::Applied { deleted: false })
+        );
+        let entries = audit_entries(&dir);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].sections, ["server"]);
+        assert!(entries[1].credential_sets.is_empty());
+    }
+
+    #[test]
+    fn encrypted_files_need_the_passphrase() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        let path = dir.path().join(ENCRYPTED_FILE_NAME);
+        let content = format!("{}{}", SETTINGS, CREDENTIALS);
+        let encrypted = encrypt(content.as_bytes(), "deploy-pass").unwrap();
+        std::fs::write(&path, &encrypted).unwrap();
+
+        let e = run(&dir, None, &store).unwrap_err();
+        assert!(e.contains(PASSPHRASE_ENV), "{}", e);
+        let e = run(&dir, Some("wrong"), &store).unwrap_err();
+        assert!(e.contains("復号できません"), "{}", e);
+        assert!(path.exists());
+        assert!(!config::config_path(dir.path()).exists());
+
+        assert_eq!(
+            run(&dir, Some("deploy-pass"), &store),
+            Ok(Outcome::Applied { deleted: true })
+        );
+        assert_eq!(store.stored(), Some(("octocat".into(), "s3cret".into())));
+        assert_eq!(written_config(&dir).server.port, 4000);
+        assert!(!path.exists());
+
+        // 再展開したイメージに残っていた場合は、パスフレーズが無くても適用済みと分かる
+        std::fs::write(&path, &encrypted).unwrap();
+        assert_eq!(run(&dir, None, &store), Ok(Outcome::AlreadyApplied));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn invalid_files_report_every_field_and_change_nothing() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        let path = dir.path().join(FILE_NAME);
+        std::fs::write(
+            &path,
+            "[server]\nprot = 4000\n\n[[schedule]]\ncron = \"every day\"\n\n\
+             [[webhooks]]\nurl = \"ftp://example.com\"\n\n\
+             [credentials]\nusername = \"octocat\"\npassword = \"\"\n",
+        )
+        .unwrap();
+
+        let e = run(&dir, None, &store).unwrap_err();
+        for field in [
+            "server.prot",
+            "schedule[0].cron",
+            "webhooks[0].url",
+            "credentials.password",
+        ] {
+            assert!(e.contains(field), "{} が含まれていません: {}", field, e);
+        }
+        assert!(path.exists());
+        assert!(!config::config_path(dir.path()).exists());
+        assert!(!marker_path(dir.path()).exists());
+        assert!(audit_entries(&dir).is_empty());
+        assert!(store.stored().is_none());
+
+        std::fs::write(&path, "[server]\nport = \"x\"\n").unwrap();
+        let e = run(&dir, None, &store).unwrap_err();
+        assert!(e.contains("port"), "{}", e);
+    }
+
+    #[test]
+    fn credentials_are_restored_when_the_config_cannot_be_written() {
+        let dir = TempDir::new();
+        let store = MemoryStore::with("previous", "old-pass");
+        // 一時ファイルの場所をディレクトリにして書き込みを失敗させる
+        std::fs::create_dir(config::config_path(dir.path()).with_extension("toml.tmp")).unwrap();
+        let path = dir.path().join(FILE_NAME);
+        std::fs::write(&path, format!("{}{}", SETTINGS, CREDENTIALS)).unwrap();
+
+        assert!(run(&dir, None, &store).is_err());
+        assert_eq!(store.stored(), Some(("previous".into(), "old-pass".into())));
+        assert!(path.exists());
+        assert!(!marker_path(dir.path()).exists());
+
+        // 認証情報を保存できない場合は dencho.toml も書かない
+        let dir = TempDir::new();
+        let store = MemoryStore {
+            fail_store: true,
+            ..Default::default()
+        };
+        std::fs::write(
+            dir.path().join(FILE_NAME),
+            format!("{}{}", SETTINGS, CREDENTIALS),
+        )
+        .unwrap();
+        assert!(run(&dir, None, &store).is_err());
+        assert!(!config::config_path(dir.path()).exists());
+    }
+
+    #[test]
+    fn an_already_applied_file_is_not_imported_again() {
+        let dir = TempDir::new();
+        let store = MemoryStore::default();
+        let path = dir.path().join(FILE_NAME);
+        std::fs::write(&path, SETTINGS).unwrap();
+        assert_eq!(
+            run(&dir, None, &store),
+            Ok(Outcome::Applied { deleted: false })
+        );
+
+        // 適用後に変えた設定は、同じファイルが再び置かれても上書きしない（イメージの再展開）
+        std::fs::write(config::config_path(dir.path()), "[server]\nport = 5000\n").unwrap();
+        std::fs::write(&path, SETTINGS).unwrap();
+        assert_eq!(run(&dir, None, &store), Ok(Outcome::AlreadyApplied));
+        assert_eq!(written_config(&dir).server.port, 5000);
+        assert!(!path.exists());
+
+        // 内容が違うファイルは新しい設定として適用する
+        std::fs::write(&path, "[server]\nport = 6000\n").unwrap();
+        assert_eq!(
+            run(&dir, None, &store),
+            Ok(Outcome::Applied { deleted: false })
+        );
+        assert_eq!(written_config(&dir).server.port, 6000);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::clock::Clock;
+use crate::config::RateLimitConfig;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// 認証情報を指定しないリクエストのプロファイル名
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// 使われなくなったキーを掃除する間隔
+const PRUNE_INTERVAL_MINUTES: i64 = 1;
+
+/// プロファイル（GitHub アカウント）ごとのダウンロード回数制限と、送信元ごとの要求回数制限
+///
+/// 直近 window_minutes 分間の実行時刻を記録するスライディングウィンドウ方式。
+pub struct RateLimiter {
+    config: Mutex<RateLimitConfig>,
+    clock: Arc<dyn Clock>,
+    history: Mutex<History>,
+    /// [rate_limit.client] 用（キーは送信元 IP、ループバックでは LOCAL_CLIENT）
+    client_history: Mutex<History>,
+}
+
+/// キーごとの実行時刻
+#[derive(Default)]
+struct History {
+    keys: HashMap<String, Window>,
+    pruned_at: Option<DateTime<Utc>>,
+}
+
+struct Window {
+    /// 最後に記録したときの window_minutes
+    length: chrono::Duration,
+    times: VecDeque<DateTime<Utc>>,
+}
+
+impl History {
+    /// ウィンドウ内の記録が無くなったキーを削除する（送信元 IP が増え続けても溜まらないように）
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let interval = chrono::Duration::minutes(PRUNE_INTERVAL_MINUTES);
+        if self.pruned_at.is_some_and(|at| now - at < interval) {
+            return;
+        }
+        self.pruned_at = Some(now);
+        self.keys.retain(|_, window| {
+            window
+                .times
+                .back()
+                .is_some_and(|t| *t > now - window.length)
+        });
+    }
+}
+
+/// ループバックで待ち受けている場合に、すべての要求をまとめて数えるキー
+pub const LOCAL_CLIENT: &str = "local";
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config: Mutex::new(config),
+            clock,
+            history: Mutex::new(History::default()),
+            client_history: Mutex::new(History::default()),
+        }
+    }
+
+    /// 制限を変更する（記録済みの実行時刻はそのまま使う）
+    pub fn set_config(&self, config: RateLimitConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// 制限内なら実行を記録して Ok、超えていれば再試行までの秒数を返す
+    pub fn try_acquire(&self, profile: &str) -> Result<(), u64> {
+        let rule = self.config.lock().unwrap().rule(profile);
+        self.acquire(
+            &self.history,
+            profile,
+            rule.max_downloads,
+            rule.window_minutes,
+        )
+    }
+
+    /// 送信元ごとの要求回数の制限内なら記録して Ok、超えていれば再試行までの秒数を返す
+    pub fn try_acquire_client(&self, client: &str) -> Result<(), u64> {
+        let rule = self.config.lock().unwrap().client;
+        self.acquire(
+            &self.client_history,
+            client,
+            rule.max_requests,
+            rule.window_minutes,
+        )
+    }
+
+    fn acquire(
+        &self,
+        history: &Mutex<History>,
+        key: &str,
+        max: u32,
+        window_minutes: u32,
+    ) -> Result<(), u64> {
+        if max == 0 {
+            return Ok(());
+        }
+
+        let now = self.clock.now();
+        let window = chrono::Duration::minutes(window_minutes as i64);
+        let mut history = history.lock().unwrap();
+        history.prune(now);
+        let entry = history
+            .keys
+            .entry(key.to_string())
+            .or_insert_with(|| Window {
+                length: window,
+                times: VecDeque::new(),
+            });
+        entry.length = window;
+        let times = &mut entry.times;
+        while times.front().is_some_and(|t| *t <= now - window) {
+            times.pop_front();
+        }
+
+        if times.len() >= max as usize {
+            let oldest = times[0];
+            let retry_after = (oldest + window - now).num_seconds().max(1) as u64;
+            return Err(retry_after);
+        }
+
+        times.push_back(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::config::ClientRateLimit;
+    use std::time::Duration;
+
+    fn limiter() -> (RateLimiter, Arc<TestClock>) {
+        let clock = TestClock::new("2024-01-01T00:00:00Z".parse().unwrap());
+        let config = RateLimitConfig {
+            max_downloads: 2,
+            window_minutes: 60,
+            client: ClientRateLimit {
+                max_requests: 2,
+                window_minutes: 10,
+            },
+            ..RateLimitConfig::default()
+        };
+        (RateLimiter::new(config, clock.clone()), clock)
+    }
+
+    fn client_keys(limiter: &RateLimiter) -> usize {
+        limiter.client_history.lock().unwrap().keys.len()
+    }
+
+    #[test]
+    fn window_slides_with_the_clock() {
+        let (limiter, clock) = limiter();
+        assert!(limiter.try_acquire("alice").is_ok());
+        clock.advance(Duration::from_secs(30 * 60));
+        assert!(limiter.try_acquire("alice").is_ok());
+        assert_eq!(limiter.try_acquire("alice"), Err(30 * 60));
+        // 他のプロファイルは別に数える
+        assert!(limiter.try_acquire("bob").is_ok());
+
+        clock.advance(Duration::from_secs(30 * 60));
+        assert!(limiter.try_acquire("alice").is_ok());
+    }
+
+    #[test]
+    fn idle_clients_are_pruned() {
+        let (limiter, clock) = limiter();
+        for i in 0..100 {
+            assert!(limiter
+                .try_acquire_client(&format!("192.0.2.{}", i))
+                .is_ok());
+        }
+        assert_eq!(client_keys(&limiter), 100);
+
+        // ウィンドウ内の記録が残っている間は消さない
+        clock.advance(Duration::from_secs(9 * 60));
+        assert!(limiter.try_acquire_client("198.51.100.1").is_ok());
+        assert_eq!(client_keys(&limiter), 101);
+
+        clock.advance(Duration::from_secs(60));
+        assert!(limiter.try_acquire_client("198.51.100.1").is_ok());
+        assert_eq!(client_keys(&limiter), 1);
+        assert_eq!(limiter.try_acquire_client("198.51.100.1"), Err(9 * 60));
+    }
+
+    #[test]
+    fn unlimited_rules_record_nothing() {
+        let clock = TestClock::new("2024-01-01T00:00:00Z".parse().unwrap());
+        let limiter = RateLimiter::new(RateLimitConfig::default(), clock);
+        for _ in 0..10 {
+            assert!(limiter.try_acquire("alice").is_ok());
+        }
+        assert!(limiter.history.lock().unwrap().keys.is_empty());
+    }
+}
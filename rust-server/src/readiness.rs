@@ -0,0 +1,206 @@
+use crate::clock::Clock;
+use crate::config::PlaywrightBrowser;
+use crate::logging::{log_to_file, Level};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// チェック結果を再利用する期間（/health のポーリングごとに node を起動しない）
+pub const READINESS_TTL: Duration = Duration::from_secs(5);
+
+/// ダウンロードに必要な環境の読み取り専用チェック結果（/health 用）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    /// node --version の出力（見つからなければ null）
+    pub node_version: Option<String>,
+    pub script_exists: bool,
+    pub node_modules_exists: bool,
+    pub browsers_installed: bool,
+}
+
+impl Readiness {
+    /// すべてのチェックを実行する（インストール等は行わない）
+    pub fn check(app_root: &Path, script_path: &Path, browser: PlaywrightBrowser) -> Self {
+        Self {
+            node_version: node_version(),
+            script_exists: script_path.exists(),
+            node_modules_exists: node_modules_exists(app_root),
+            browsers_installed: browsers_installed(&playwright_browsers_path(), browser),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.node_version.is_some()
+            && self.script_exists
+            && self.node_modules_exists
+            && self.browsers_installed
+    }
+}
+
+/// 直近のチェック結果のキャッシュ
+pub struct ReadinessCache {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    cached: Mutex<Option<(DateTime<Utc>, Readiness)>>,
+}
+
+impl ReadinessCache {
+    pub fn new(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// TTL 内の結果
+    pub fn get(&self) -> Option<Readiness> {
+        let cached = self.cached.lock().unwrap();
+        let (checked_at, readiness) = cached.as_ref()?;
+        let age = (self.clock.now() - *checked_at)
+            .to_std()
+            .unwrap_or_default();
+        (age < self.ttl).then(|| readiness.clone())
+    }
+
+    pub fn store(&self, readiness: Readiness) {
+        *self.cached.lock().unwrap() = Some((self.clock.now(), readiness));
+    }
+
+    /// 次の get で確認し直させる（ブラウザを入れ直した後など）
+    pub fn clear(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+/// Node.js のバージョン（見つからなければ None）
+pub fn node_version() -> Option<String> {
+    match Command::new("node").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        _ => None,
+    }
+}
+
+/// `node --version` の出力（v18.19.0 など）からメジャーバージョンを取り出す
+pub fn node_major_version(version: &str) -> Option<u32> {
+    let version = version.trim();
+    let version = version.strip_prefix('v').unwrap_or(version);
+    version.split('.').next()?.parse().ok()
+}
+
+/// Node.js が min_major 以上か確認する（古ければ検出・必要バージョンを含むエラー）
+///
+/// 解釈できないバージョン表記（nightly ビルド等）は、警告を残して通す。
+pub fn check_node_version(version: &str, min_major: u32) -> Result<(), String> {
+    match node_major_version(version) {
+        Some(major) if major < min_major => Err(format!(
+            "Node.js のバージョンが古すぎます: {} (必要: {} 以上)",
+            version, min_major
+        )),
+        Some(_) => Ok(()),
+        None => {
+            log_to_file(
+                Level::Warn,
+                &format!(
+                    "Node.js のバージョンを判別できません: {} (必要: {} 以上、確認せずに続行します)",
+                    version, min_major
+                ),
+            );
+            Ok(())
+        }
+    }
+}
+
+/// インストール済みの Playwright のバージョン（node_modules/@playwright/test/package.json）
+pub fn playwright_version(app_root: &Path) -> Option<String> {
+    let path = app_root
+        .join("node_modules")
+        .join("@playwright")
+        .join("test")
+        .join("package.json");
+    let package: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+    package["version"].as_str().map(str::to_string)
+}
+
+pub fn node_modules_exists(app_root: &Path) -> bool {
+    app_root.join("node_modules").exists()
+}
+
+/// Playwright ブラウザのインストール先
+///
+/// PLAYWRIGHT_BROWSERS_PATH 環境変数 > %APPDATA%\dencho-cli\browsers（Windows）
+/// / $XDG_DATA_HOME/dencho-cli/browsers（既定は ~/.local/share、Linux・macOS）。
+/// セットアップ・環境チェック・ダウンロードのすべてがここを使う（ずれるとインストール済みでも見つからない）。
+pub fn playwright_browsers_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("PLAYWRIGHT_BROWSERS_PATH").filter(|p| !p.is_empty()) {
+        return PathBuf::from(path);
+    }
+    data_dir().join("dencho-cli").join("browsers")
+}
+
+#[cfg(windows)]
+fn data_dir() -> PathBuf {
+    PathBuf::from(std::env::var_os("APPDATA").unwrap_or_else(|| ".".into()))
+}
+
+#[cfg(not(windows))]
+fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    std::env::var_os("HOME")
+        .map(|home| Path::new(&home).join(".local").join("share"))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// 指定したブラウザがインストール済みか（Playwright は chromium-1234 のようなディレクトリを作る）
+pub fn browsers_installed(path: &Path, browser: PlaywrightBrowser) -> bool {
+    std::fs::read_dir(path).is_ok_and(|entries| {
+        entries.flatten().any(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&format!("{}-", browser.as_str()))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_major_version_parses_node_output() {
+        assert_eq!(node_major_version("v18.19.0"), Some(18));
+        assert_eq!(node_major_version("v20.11.1\n"), Some(20));
+        assert_eq!(node_major_version("22.0.0"), Some(22));
+        assert_eq!(
+            node_major_version("v23.0.0-nightly20240101abcdef"),
+            Some(23)
+        );
+        assert_eq!(node_major_version("nightly"), None);
+        assert_eq!(node_major_version(""), None);
+    }
+
+    #[test]
+    fn old_node_versions_are_rejected_with_both_versions() {
+        let error = check_node_version("v16.20.2", 18).unwrap_err();
+        assert!(error.contains("v16.20.2"), "{}", error);
+        assert!(error.contains("18"), "{}", error);
+
+        assert!(check_node_version("v18.0.0", 18).is_ok());
+        assert!(check_node_version("v20.11.1", 18).is_ok());
+    }
+
+    #[test]
+    fn unparseable_node_versions_only_warn() {
+        assert!(check_node_version("vNext", 18).is_ok());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error_code::ErrorCode;
+use crate::logging::{log_to_file, Level};
+use crate::DownloadResponse;
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        FromRequest, FromRequestParts, Query, Request,
+    },
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::de::DeserializeOwned;
+
+/// axum::Json と同じだが、読み取れない本文は DownloadResponse 形式のエラーを返す
+///
+/// axum 標準のエラーはテキストなので、フロントエンドで解析できない。
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(request, state).await {
+            Ok(Json(value)) => Ok(Self(value)),
+            Err(rejection) => Err(json_rejection(rejection)),
+        }
+    }
+}
+
+fn json_rejection(rejection: JsonRejection) -> Response {
+    let (status, message) = match &rejection {
+        JsonRejection::MissingJsonContentType(_) => (
+            StatusCode::BAD_REQUEST,
+            "Content-Type: application/json を指定してください".to_string(),
+        ),
+        JsonRejection::JsonSyntaxError(_) => (
+            StatusCode::BAD_REQUEST,
+            format!("JSON の形式が不正です: {}", rejection.body_text()),
+        ),
+        JsonRejection::JsonDataError(_) => (
+            StatusCode::BAD_REQUEST,
+            format!("リクエストの内容が不正です: {}", rejection.body_text()),
+        ),
+        // 本文が大きすぎる・読み取れないなどは axum のステータスのまま
+        _ => (rejection.status(), rejection.body_text()),
+    };
+    invalid_request(status, message)
+}
+
+/// axum::extract::Query と同じだが、解釈できないクエリは DownloadResponse 形式の 400 を返す
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Query::<T>::from_request_parts(parts, state).await {
+            Ok(Query(value)) => Ok(Self(value)),
+            Err(rejection) => Err(query_rejection(rejection)),
+        }
+    }
+}
+
+fn query_rejection(rejection: QueryRejection) -> Response {
+    invalid_request(
+        StatusCode::BAD_REQUEST,
+        format!("クエリパラメーターが不正です: {}", rejection.body_text()),
+    )
+}
+
+fn invalid_request(status: StatusCode, message: String) -> Response {
+    log_to_file(Level::Warn, &format!("不正なリクエスト: {}", message));
+    error_response(status, ErrorCode::InvalidRequest, message)
+}
+
+/// 存在しないパスへのリクエスト（Router::fallback）
+pub async fn not_found(request: Request) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+        format!("{} は存在しません", request.uri().path()),
+    )
+}
+
+/// 存在するパスへの、対応していないメソッドのリクエスト（Allow ヘッダーは axum が付ける）
+pub async fn method_not_allowed(request: Request) -> Response {
+    error_response(
+        StatusCode::METHOD_NOT_ALLOWED,
+        ErrorCode::MethodNotAllowed,
+        format!(
+            "{} は {} に対応していません",
+            request.uri().path(),
+            request.method()
+        ),
+    )
+}
+
+fn error_response(status: StatusCode, code: ErrorCode, message: String) -> Response {
+    let mut response = DownloadResponse::error(message);
+    response.code = Some(code);
+    (status, Json(response)).into_response()
+}
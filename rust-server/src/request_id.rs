@@ -0,0 +1,194 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::future::Future;
+
+/// リクエスト ID のヘッダー（受け取った値があればそのまま使い、レスポンスにも付ける）
+pub const HEADER: &str = "x-request-id";
+
+/// 受け取ったリクエスト ID として使う最大長
+const MAX_LEN: usize = 64;
+
+/// エラーレスポンスに requestId を追加する際に読み込む本文の上限
+const MAX_ERROR_BODY_BYTES: usize = 1024 * 1024;
+
+tokio::task_local! {
+    /// 処理中のリクエストの ID（ログの各行に付ける）
+    static REQUEST_ID: String;
+}
+
+/// 処理中のリクエストの ID（リクエストの外では None）
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// id をリクエスト ID として fut を実行する（リクエスト以外から起動するジョブ用）
+pub async fn scope<F: Future>(id: String, fut: F) -> F::Output {
+    REQUEST_ID.scope(id, fut).await
+}
+
+/// 現在のリクエスト ID を引き継いで fut を実行する（tokio::spawn するジョブ用）
+pub fn inherit<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let id = current();
+    async move {
+        match id {
+            Some(id) => REQUEST_ID.scope(id, fut).await,
+            None => fut.await,
+        }
+    }
+}
+
+/// ログやヘッダーに載せても安全な ID か（英数字と - _ . のみ）
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// 8 桁の 16 進数
+fn generate() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// リクエストに ID を割り当て、処理中のログ・レスポンスヘッダー・エラーの JSON に含める
+pub async fn assign(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map_or_else(generate, str::to_string);
+
+    let response = REQUEST_ID.scope(id.clone(), next.run(request)).await;
+    let mut response = add_to_error_body(response, &id).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, value);
+    }
+    response
+}
+
+/// エラーの JSON（HTTP ステータスがエラー、または "status": "error"）に requestId が無ければ追加する
+///
+/// DENCHO_ALWAYS_200 でエラーも 200 になるため、本文の status でも判定する。
+async fn add_to_error_body(response: Response, id: &str) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    // 大きな JSON はエラーではない（一覧など）ので読み込まない
+    let small = response
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|len| len <= MAX_ERROR_BODY_BYTES as u64);
+    if !is_json || !small {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let mut value = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => return Response::from_parts(parts, Body::from(bytes)),
+    };
+    let is_error = parts.status.is_client_error()
+        || parts.status.is_server_error()
+        || value.get("status").and_then(|s| s.as_str()) == Some("error");
+    if !is_error {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+
+    // ジョブの結果には開始したリクエストの ID が入っているので、そのまま残す
+    if value.contains_key("requestId") {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    value.insert("requestId".to_string(), id.into());
+    let body = serde_json::to_vec(&value).unwrap_or_else(|_| bytes.to_vec());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::{log_file_path, log_to_file, Level};
+    use axum::{
+        http::StatusCode,
+        response::{IntoResponse, Json},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    /// ログを 1 行書いて JSON のエラーを返すハンドラー
+    async fn failing(request: Request) -> Response {
+        let marker = request.uri().query().unwrap_or_default().to_string();
+        log_to_file(Level::Error, &marker);
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "status": "error", "message": marker })),
+        )
+            .into_response()
+    }
+
+    async fn send(id: Option<&str>, marker: &str) -> (Response, serde_json::Value) {
+        let app = Router::new()
+            .route("/fail", get(failing))
+            .layer(axum::middleware::from_fn(assign));
+        let mut request = Request::builder().uri(format!("/fail?{}", marker));
+        if let Some(id) = id {
+            request = request.header(HEADER, id);
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (
+            Response::from_parts(parts, Body::empty()),
+            serde_json::from_slice(&bytes).unwrap(),
+        )
+    }
+
+    fn logged_line(marker: &str) -> String {
+        std::fs::read_to_string(log_file_path())
+            .unwrap()
+            .lines()
+            .find(|line| line.ends_with(marker))
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn the_request_id_round_trips_into_the_log_and_error_body() {
+        let marker = format!("marker-{}", uuid::Uuid::new_v4().simple());
+        let (response, body) = send(Some("client-id-1"), &marker).await;
+
+        assert_eq!(response.headers()[HEADER], "client-id-1");
+        assert_eq!(body["requestId"], "client-id-1");
+        assert!(logged_line(&marker).contains("[req:client-id-1]"));
+    }
+
+    #[tokio::test]
+    async fn missing_or_unsafe_ids_are_replaced() {
+        for id in [None, Some("bad id"), Some(&*"a".repeat(MAX_LEN + 1))] {
+            let marker = format!("marker-{}", uuid::Uuid::new_v4().simple());
+            let (response, body) = send(id, &marker).await;
+
+            let generated = response.headers()[HEADER].to_str().unwrap().to_string();
+            assert_eq!(generated.len(), 8, "{:?}", id);
+            assert_eq!(body["requestId"], generated.as_str());
+            assert!(logged_line(&marker).contains(&format!("[req:{}]", generated)));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::InvoicesConfig;
+use crate::logging::{log_to_file, Level};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 保存済み請求書の保持ルール（[invoices] keep_days / keep_count、どちらも無ければ削除しない）
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Policy {
+    pub keep_days: Option<u32>,
+    pub keep_count: Option<u32>,
+}
+
+impl Policy {
+    /// 設定から作る（0 は全削除になるため不正とする）
+    pub fn from_config(config: &InvoicesConfig) -> Result<Self, String> {
+        if config.keep_days == Some(0) {
+            return Err("invoices.keep_days は 1 以上を指定してください".to_string());
+        }
+        if config.keep_count == Some(0) {
+            return Err("invoices.keep_count は 1 以上を指定してください".to_string());
+        }
+        Ok(Self {
+            keep_days: config.keep_days,
+            keep_count: config.keep_count,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.keep_days.is_some() || self.keep_count.is_some()
+    }
+}
+
+/// 削除した（dry run では削除する）ファイル
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovedFile {
+    pub name: String,
+    pub size_bytes: u64,
+    pub modified_at: DateTime<Local>,
+}
+
+/// 削除の結果（POST /api/invoices/cleanup のレスポンス）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub dry_run: bool,
+    pub keep_days: Option<u32>,
+    pub keep_count: Option<u32>,
+    /// 古い順
+    pub files: Vec<RemovedFile>,
+    pub freed_bytes: u64,
+    /// 削除できなかったファイルと理由
+    pub errors: Vec<String>,
+}
+
+/// dir の中で保持ルールを超えたファイルを古い順に削除する（dry_run なら一覧だけ返す）
+///
+/// ダウンロード途中のファイルは対象にしない。保存日時は更新日時で判定する。
+pub fn run(dir: &Path, policy: Policy, now: DateTime<Local>, dry_run: bool) -> Report {
+    let mut files: Vec<RemovedFile> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if crate::is_partial_download(&name) {
+                return None;
+            }
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some(RemovedFile {
+                name,
+                size_bytes: metadata.len(),
+                modified_at: metadata.modified().ok()?.into(),
+            })
+        })
+        .collect();
+    files.sort_by_key(|file| std::cmp::Reverse(file.modified_at));
+
+    let cutoff = policy
+        .keep_days
+        .map(|days| now - chrono::Duration::days(days.into()));
+    let keep_count = policy.keep_count.map_or(usize::MAX, |count| count as usize);
+    let mut expired: Vec<RemovedFile> = files
+        .into_iter()
+        .enumerate()
+        .filter(|(index, file)| {
+            *index >= keep_count || cutoff.is_some_and(|cutoff| file.modified_at < cutoff)
+        })
+        .map(|(_, file)| file)
+        .collect();
+    expired.reverse();
+
+    let mut report = Report {
+        dry_run,
+        keep_days: policy.keep_days,
+        keep_count: policy.keep_count,
+        files: Vec::new(),
+        freed_bytes: 0,
+        errors: Vec::new(),
+    };
+    for file in expired {
+        if !dry_run {
+            if let Err(e) = std::fs::remove_file(dir.join(&file.name)) {
+                let error = format!("{}: {}", file.name, e);
+                log_to_file(
+                    Level::Warn,
+                    &format!("古い請求書を削除できません: {}", error),
+                );
+                report.errors.push(error);
+                continue;
+            }
+            log_to_file(
+                Level::Info,
+                &format!(
+                    "古い請求書を削除しました: {} ({} バイト, 更新 {})",
+                    file.name,
+                    file.size_bytes,
+                    file.modified_at.format("%Y-%m-%d %H:%M:%S")
+                ),
+            );
+        }
+        report.freed_bytes += file.size_bytes;
+        report.files.push(file);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, TestClock};
+    use crate::test_util::TempDir;
+    use std::time::Duration;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn names(report: &Report) -> Vec<&str> {
+        report.files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    #[test]
+    fn keep_days_cutoff_moves_with_the_clock() {
+        let clock = TestClock::new(chrono::Utc::now());
+        let now: std::time::SystemTime = clock.now().into();
+        let dir = TempDir::new();
+        dir.file("old.pdf", now - 30 * DAY - Duration::from_secs(1));
+        dir.file("recent.pdf", now - 30 * DAY + Duration::from_secs(60));
+        let policy = Policy {
+            keep_days: Some(30),
+            keep_count: None,
+        };
+
+        let report = run(dir.path(), policy, clock.now().into(), true);
+        assert_eq!(names(&report), ["old.pdf"]);
+
+        // 2 分進めると、もう 1 つも 30 日を過ぎる
+        clock.advance(Duration::from_secs(120));
+        let report = run(dir.path(), policy, clock.now().into(), true);
+        assert_eq!(names(&report), ["old.pdf", "recent.pdf"]);
+    }
+
+    #[test]
+    fn keep_count_removes_the_oldest_and_skips_partial_downloads() {
+        let now = std::time::SystemTime::now();
+        let dir = TempDir::new();
+        dir.file("a.pdf", now - 3 * DAY);
+        dir.file("b.pdf", now - 2 * DAY);
+        dir.file("c.pdf", now - DAY);
+        dir.file("d.pdf.crdownload", now - 10 * DAY);
+        let policy = Policy {
+            keep_days: None,
+            keep_count: Some(2),
+        };
+
+        let report = run(dir.path(), policy, now.into(), false);
+        assert_eq!(names(&report), ["a.pdf"]);
+        assert!(!dir.path().join("a.pdf").exists());
+        assert!(dir.path().join("d.pdf.crdownload").exists());
+    }
+}
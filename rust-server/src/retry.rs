@@ -0,0 +1,116 @@
+use crate::error_code::{LOGIN_FAILED_EXIT_CODE, TRANSIENT_EXIT_CODE};
+use std::time::Duration;
+
+/// 再試行する stderr のパターン（ネットワークの一時的な失敗、小文字で比較）
+const TRANSIENT_PATTERNS: &[&str] = &[
+    "etimedout",
+    "econnreset",
+    "econnrefused",
+    "eai_again",
+    "socket hang up",
+    "net::err_",
+    "timeouterror",
+    "navigation timeout",
+    "network error",
+];
+
+/// 再試行しても結果が変わらない stderr のパターン（認証情報の誤りなど、小文字で比較）
+const PERMANENT_PATTERNS: &[&str] = &[
+    "incorrect username or password",
+    "invalid credentials",
+    "authentication failed",
+    "two-factor",
+    "認証",
+];
+
+/// スクリプトが一時的なエラーで失敗したときの再試行
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// 初回を除く再試行回数（0 で再試行しない）
+    pub retries: u32,
+    /// 1 回目の再試行までの待ち時間（以降は倍々）
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// attempt 回目（1 始まり）の失敗後、次の実行までの待ち時間
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// 1 リクエストで指定できる再試行回数の上限（POST /api/download の retries）
+pub const MAX_REQUEST_RETRIES: u32 = 10;
+
+/// 失敗したスクリプトを再試行するか（ログイン失敗は再試行しない）
+pub fn is_retryable(exit_code: Option<i32>, stderr: &str) -> bool {
+    match exit_code {
+        Some(LOGIN_FAILED_EXIT_CODE) => false,
+        Some(TRANSIENT_EXIT_CODE) => true,
+        _ => is_transient(stderr),
+    }
+}
+
+/// 再試行で成功する見込みのある失敗か（stderr で判定）
+pub fn is_transient(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    TRANSIENT_PATTERNS.iter().any(|p| stderr.contains(p))
+        && !PERMANENT_PATTERNS.iter().any(|p| stderr.contains(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{is_pending, Clock, TestClock};
+    use std::sync::Arc;
+
+    #[test]
+    fn backoff_doubles_from_the_base_delay() {
+        let policy = RetryPolicy {
+            retries: 4,
+            base_delay: Duration::from_secs(5),
+        };
+        let delays: Vec<u64> = (1..=4).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, [5, 10, 20, 40]);
+    }
+
+    #[test]
+    fn backoff_saturates_instead_of_overflowing() {
+        let policy = RetryPolicy {
+            retries: 100,
+            base_delay: Duration::from_secs(5),
+        };
+        assert_eq!(policy.delay(100), Duration::from_secs(5) * u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn each_retry_waits_its_delay_on_the_clock() {
+        let start = "2024-01-31T00:00:00Z".parse().unwrap();
+        let test_clock = TestClock::new(start);
+        let clock: Arc<dyn Clock> = test_clock.clone();
+        let policy = RetryPolicy {
+            retries: 3,
+            base_delay: Duration::from_secs(5),
+        };
+
+        for attempt in 1..=policy.retries {
+            let delay = policy.delay(attempt);
+            let mut sleep = clock.sleep(delay);
+            test_clock.advance(delay - Duration::from_millis(1));
+            assert!(is_pending(sleep.as_mut()), "attempt {}", attempt);
+            test_clock.advance(Duration::from_millis(1));
+            sleep.await;
+        }
+        assert_eq!((clock.now() - start).num_seconds(), 5 + 10 + 20);
+    }
+
+    #[test]
+    fn login_failures_are_never_retried() {
+        assert!(!is_retryable(Some(LOGIN_FAILED_EXIT_CODE), "ETIMEDOUT"));
+        assert!(is_retryable(Some(TRANSIENT_EXIT_CODE), ""));
+        assert!(is_retryable(Some(1), "Error: net::ERR_CONNECTION_RESET"));
+        assert!(!is_retryable(Some(1), "TypeError: undefined"));
+        assert!(!is_transient("ETIMEDOUT after invalid credentials"));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::config::ScheduleEntry;
+use chrono::{DateTime, Local};
+use croner::Cron;
+
+/// cron 式を解釈済みのスケジュール
+#[derive(Clone)]
+pub struct Schedule {
+    pub entry: ScheduleEntry,
+    cron: Cron,
+}
+
+impl Schedule {
+    /// ログ・履歴に出す名前（省略時は cron 式）
+    pub fn name(&self) -> &str {
+        self.entry.name.as_deref().unwrap_or(&self.entry.cron)
+    }
+
+    /// after より後の次回実行時刻
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.cron.find_next_occurrence(after, false).ok()
+    }
+}
+
+/// [[schedule]] を解釈する（不正な cron 式があればエラー）
+pub fn parse(entries: &[ScheduleEntry]) -> Result<Vec<Schedule>, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let cron = Cron::new(&entry.cron).parse().map_err(|e| {
+                format!(
+                    "schedule の cron 式が不正です: {} ({}、「分 時 日 月 曜日」の 5 項目で指定してください)",
+                    entry.cron, e
+                )
+            })?;
+            Ok(Schedule {
+                entry: entry.clone(),
+                cron,
+            })
+        })
+        .collect()
+}
+
+/// after より後で最も早い実行時刻と、その時刻に実行するスケジュール
+pub fn next_due(
+    schedules: &[Schedule],
+    after: &DateTime<Local>,
+) -> Option<(DateTime<Local>, Vec<Schedule>)> {
+    let next = schedules.iter().filter_map(|s| s.next_after(after)).min()?;
+    let due = schedules
+        .iter()
+        .filter(|s| s.next_after(after) == Some(next))
+        .cloned()
+        .collect();
+    Some((next, due))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{is_pending, Clock, TestClock};
+    use chrono::TimeZone;
+    use std::sync::Arc;
+
+    fn schedule(cron: &str) -> Vec<Schedule> {
+        parse(&[ScheduleEntry {
+            name: None,
+            cron: cron.to_string(),
+            provider: None,
+            locale: None,
+        }])
+        .unwrap()
+    }
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn next_run_crosses_the_month_boundary() {
+        let schedules = schedule("0 9 1 * *");
+        let (at, due) = next_due(&schedules, &local(2024, 1, 31, 23, 0)).unwrap();
+        assert_eq!(at, local(2024, 2, 1, 9, 0));
+        assert_eq!(due.len(), 1);
+    }
+
+    #[test]
+    fn day_31_skips_months_without_it() {
+        let schedules = schedule("0 9 31 * *");
+        let (at, _) = next_due(&schedules, &local(2024, 1, 31, 9, 0)).unwrap();
+        assert_eq!(at, local(2024, 3, 31, 9, 0));
+    }
+
+    #[tokio::test]
+    async fn scheduled_run_fires_when_the_clock_reaches_the_next_month() {
+        let test_clock = TestClock::new(local(2024, 1, 31, 23, 0).with_timezone(&chrono::Utc));
+        let clock: Arc<dyn Clock> = test_clock.clone();
+        let schedules = schedule("0 9 1 * *");
+        let now = clock.now().with_timezone(&Local);
+        let (at, _) = next_due(&schedules, &now).unwrap();
+
+        let mut sleep = clock.sleep_until(at.with_timezone(&chrono::Utc));
+        test_clock.advance(std::time::Duration::from_secs(10 * 3600 - 1));
+        assert!(is_pending(sleep.as_mut()));
+        test_clock.advance(std::time::Duration::from_secs(1));
+        sleep.await;
+        assert_eq!(clock.now().with_timezone(&Local), local(2024, 2, 1, 9, 0));
+    }
+
+    #[test]
+    fn invalid_cron_is_rejected() {
+        let error = parse(&[ScheduleEntry {
+            name: None,
+            cron: "every month".to_string(),
+            provider: None,
+            locale: None,
+        }])
+        .err()
+        .unwrap();
+        assert!(error.contains("every month"));
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// スクリプトが保存したファイル（レスポンスの files）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadedFile {
+    /// アプリケーションルートからの相対パス（ルートの外なら絶対パス、区切りは /）
+    pub path: String,
+    pub size: u64,
+    pub modified_at: DateTime<Local>,
+}
+
+/// スクリプトが標準出力の最後に出力する行（`{"files": ["..."]}`）
+#[derive(Deserialize)]
+struct FilesLine {
+    files: Vec<PathBuf>,
+}
+
+/// 標準出力の files 行の解釈結果
+pub enum ReportedFiles {
+    /// files 行にあったパス
+    Found(Vec<PathBuf>),
+    /// files 行が無い（古いスクリプト）
+    Missing,
+    /// files 行らしいが解釈できない
+    Invalid(String),
+}
+
+/// 標準出力から最後の files 行を探す
+///
+/// ログの行は `[日時] ...` の形なので、`{` で始まる行だけを候補にする。
+pub fn reported_files(stdout: &str) -> ReportedFiles {
+    let Some(line) = stdout
+        .lines()
+        .map(str::trim)
+        .rev()
+        .find(|line| line.starts_with('{') && line.contains("\"files\""))
+    else {
+        return ReportedFiles::Missing;
+    };
+    match serde_json::from_str::<FilesLine>(line) {
+        Ok(line) => ReportedFiles::Found(line.files),
+        Err(e) => ReportedFiles::Invalid(format!("files 行を解釈できません: {}", e)),
+    }
+}
+
+/// ファイルのサイズと更新日時を調べる（見つからないファイルは 2 つ目に返す）
+pub fn describe(app_root: Option<&Path>, paths: &[PathBuf]) -> (Vec<DownloadedFile>, Vec<String>) {
+    let mut files = Vec::new();
+    let mut unreadable = Vec::new();
+    for path in paths {
+        let absolute = match app_root {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path.clone(),
+        };
+        let metadata = match std::fs::metadata(&absolute) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => {
+                unreadable.push(path.display().to_string());
+                continue;
+            }
+        };
+        let display = app_root
+            .and_then(|root| absolute.strip_prefix(root).ok())
+            .unwrap_or(&absolute);
+        files.push(DownloadedFile {
+            path: display.to_string_lossy().replace('\\', "/"),
+            size: metadata.len(),
+            modified_at: metadata
+                .modified()
+                .map(DateTime::<Local>::from)
+                .unwrap_or_else(|_| Local::now()),
+        });
+    }
+    (files, unreadable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn the_last_files_line_wins() {
+        let stdout = "[2024-01-31 09:00:00] ログイン\n\
+                      {\"files\": [\"old.pdf\"]}\n\
+                      {\"progress\": 1}\n\
+                      {\"files\": [\"downloads/invoice/a.pdf\", \"b.pdf\"]}\n";
+        let ReportedFiles::Found(paths) = reported_files(stdout) else {
+            panic!("files 行が見つかりません");
+        };
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("downloads/invoice/a.pdf"),
+                PathBuf::from("b.pdf")
+            ]
+        );
+    }
+
+    #[test]
+    fn output_without_a_files_line_is_missing() {
+        assert!(matches!(
+            reported_files("[2024-01-31 09:00:00] 完了\n{\"status\": \"ok\"}\n"),
+            ReportedFiles::Missing
+        ));
+        assert!(matches!(reported_files(""), ReportedFiles::Missing));
+    }
+
+    #[test]
+    fn unparseable_files_lines_are_invalid() {
+        for stdout in ["{\"files\": \"a.pdf\"}", "{\"files\": [\"a.pdf\""] {
+            assert!(
+                matches!(reported_files(stdout), ReportedFiles::Invalid(_)),
+                "{}",
+                stdout
+            );
+        }
+    }
+
+    #[test]
+    fn describe_reports_paths_relative_to_the_root() {
+        let root = TempDir::new();
+        std::fs::create_dir_all(root.path().join("invoices")).unwrap();
+        std::fs::write(root.path().join("invoices").join("a.pdf"), "12345").unwrap();
+        let outside = TempDir::new();
+        std::fs::write(outside.path().join("b.pdf"), "12").unwrap();
+
+        let (files, missing) = describe(
+            Some(root.path()),
+            &[
+                PathBuf::from("invoices/a.pdf"),
+                outside.path().join("b.pdf"),
+                PathBuf::from("invoices/missing.pdf"),
+            ],
+        );
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "invoices/a.pdf");
+        assert_eq!(files[0].size, 5);
+        // ルートの外は絶対パスのまま
+        assert_eq!(
+            files[1].path,
+            outside
+                .path()
+                .join("b.pdf")
+                .to_string_lossy()
+                .replace('\\', "/")
+        );
+        assert_eq!(files[1].size, 2);
+        assert_eq!(missing, ["invoices/missing.pdf"]);
+    }
+}
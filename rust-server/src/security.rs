@@ -0,0 +1,180 @@
+use serde::Serialize;
+
+/// 判定結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// セキュリティ状態の 1 項目
+#[derive(Debug, Clone, Serialize)]
+pub struct PostureItem {
+    pub name: &'static str,
+    pub verdict: Verdict,
+    pub detail: String,
+    /// strict モードで起動を拒否する場合の終了コード
+    #[serde(skip)]
+    pub exit_code: i32,
+}
+
+/// 判定に使う実効設定
+#[derive(Debug, Clone)]
+pub struct PostureInput {
+    /// API キーを DENCHO_API_KEY から読んだか（false なら data/api_key）
+    pub api_key_from_env: bool,
+    pub cors_allows_any: bool,
+    pub bind_is_loopback: bool,
+    pub tls_enabled: bool,
+    pub credentials_via_env: bool,
+}
+
+/// 実効設定からセキュリティ状態を評価する
+pub fn evaluate(input: &PostureInput) -> Vec<PostureItem> {
+    let mut items = Vec::new();
+
+    // /api/* は常に API キーを要求するため、無効になることはない
+    items.push(item(
+        "auth",
+        Verdict::Pass,
+        if input.api_key_from_env {
+            "API キー認証が有効です (DENCHO_API_KEY)"
+        } else {
+            "API キー認証が有効です (data/api_key)"
+        },
+        0,
+    ));
+
+    items.push(if input.cors_allows_any {
+        item(
+            "cors",
+            Verdict::Fail,
+            "CORS がすべてのオリジンを許可しています",
+            10,
+        )
+    } else {
+        item(
+            "cors",
+            Verdict::Pass,
+            "CORS は許可オリジンに限定されています",
+            10,
+        )
+    });
+
+    items.push(match (input.bind_is_loopback, input.tls_enabled) {
+        (_, true) => item("transport", Verdict::Pass, "HTTPS で待ち受けています", 12),
+        (true, false) => item(
+            "transport",
+            Verdict::Pass,
+            "ループバックアドレスでのみ待ち受けています (HTTP)",
+            12,
+        ),
+        (false, false) => item(
+            "transport",
+            Verdict::Fail,
+            "ループバック以外のアドレスで TLS なしに待ち受けています",
+            12,
+        ),
+    });
+
+    items.push(if input.credentials_via_env {
+        item(
+            "credentials",
+            Verdict::Fail,
+            "認証情報を環境変数でスクリプトに渡しています",
+            13,
+        )
+    } else {
+        item(
+            "credentials",
+            Verdict::Pass,
+            "認証情報は環境変数を経由しません",
+            13,
+        )
+    });
+
+    items.push(item(
+        "pinning",
+        Verdict::Warn,
+        "出力先・通知先の証明書ピンニングは設定されていません",
+        0,
+    ));
+
+    items
+}
+
+fn item(name: &'static str, verdict: Verdict, detail: &str, exit_code: i32) -> PostureItem {
+    PostureItem {
+        name,
+        verdict,
+        detail: detail.to_string(),
+        exit_code,
+    }
+}
+
+/// 全体の判定（最も悪いもの）
+pub fn overall(items: &[PostureItem]) -> Verdict {
+    if items.iter().any(|i| i.verdict == Verdict::Fail) {
+        Verdict::Fail
+    } else if items.iter().any(|i| i.verdict == Verdict::Warn) {
+        Verdict::Warn
+    } else {
+        Verdict::Pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> PostureInput {
+        PostureInput {
+            api_key_from_env: false,
+            cors_allows_any: false,
+            bind_is_loopback: true,
+            tls_enabled: false,
+            credentials_via_env: false,
+        }
+    }
+
+    fn verdict(items: &[PostureItem], name: &str) -> (Verdict, i32) {
+        let item = items.iter().find(|item| item.name == name).unwrap();
+        (item.verdict, item.exit_code)
+    }
+
+    #[test]
+    fn auth_always_passes_and_names_the_key_source() {
+        let items = evaluate(&input());
+        assert_eq!(verdict(&items, "auth").0, Verdict::Pass);
+        assert!(items[0].detail.contains("data/api_key"));
+
+        let items = evaluate(&PostureInput {
+            api_key_from_env: true,
+            ..input()
+        });
+        assert!(items[0].detail.contains("DENCHO_API_KEY"));
+    }
+
+    #[test]
+    fn failures_carry_their_strict_exit_codes() {
+        let items = evaluate(&PostureInput {
+            cors_allows_any: true,
+            bind_is_loopback: false,
+            credentials_via_env: true,
+            ..input()
+        });
+        assert_eq!(verdict(&items, "cors"), (Verdict::Fail, 10));
+        assert_eq!(verdict(&items, "transport"), (Verdict::Fail, 12));
+        assert_eq!(verdict(&items, "credentials"), (Verdict::Fail, 13));
+        assert_eq!(overall(&items), Verdict::Fail);
+    }
+
+    #[test]
+    fn safe_defaults_only_warn_about_pinning() {
+        let items = evaluate(&input());
+        assert_eq!(overall(&items), Verdict::Warn);
+        assert_eq!(verdict(&items, "pinning").0, Verdict::Warn);
+    }
+}
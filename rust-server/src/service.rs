@@ -0,0 +1,415 @@
+use std::path::{Path, PathBuf};
+
+/// サービス名のデフォルト（--name 省略時）
+pub const DEFAULT_SERVICE_NAME: &str = "dencho-cli";
+
+/// install / uninstall の対象と、install 時の起動設定
+#[derive(Debug, Clone)]
+pub struct ServiceOptions {
+    /// サービス名（定義ファイル名と、サーバーのインスタンス名になる）
+    pub name: String,
+    /// 表示名（systemd の Description）
+    pub display_name: Option<String>,
+    /// 待ち受けポート（省略時は dencho.toml の server.port）
+    pub port: Option<u16>,
+    /// 異常終了時に自動で再起動する（--no-recovery で無効）
+    pub recovery: bool,
+    /// ユーザー単位ではなくシステム全体に登録する（--system、root 権限が必要）
+    pub system: bool,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_SERVICE_NAME.to_string(),
+            display_name: None,
+            port: None,
+            recovery: true,
+            system: false,
+        }
+    }
+}
+
+impl ServiceOptions {
+    /// 定義ファイル・サービスマネージャーに渡す起動引数
+    fn run_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "run".to_string(),
+            "--foreground".to_string(),
+            "--instance".to_string(),
+            self.name.clone(),
+        ];
+        if let Some(port) = self.port {
+            args.extend(["--port".to_string(), port.to_string()]);
+        }
+        args
+    }
+}
+
+/// サービス名・インスタンス名として使える文字列か（英数字・-・_、64 文字まで）
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "サービス名が不正です: {} (英数字・-・_ の 64 文字以内)",
+            name
+        ))
+    }
+}
+
+/// 異常終了から再起動までの待ち時間（秒）
+const RESTART_DELAY_SECS: u32 = 10;
+
+/// install が生成する定義ファイルと、登録手順
+pub struct ServiceDefinition {
+    pub path: PathBuf,
+    /// 異常終了時の動作（install のみ）
+    pub recovery: Option<String>,
+    pub instructions: Vec<String>,
+}
+
+/// 定義ファイルに書き込んだ再起動ポリシーの説明
+fn describe_recovery(options: &ServiceOptions) -> String {
+    if options.recovery {
+        format!(
+            "異常終了（終了コード 0 以外・シグナル）時に {} 秒後に再起動",
+            RESTART_DELAY_SECS
+        )
+    } else {
+        "自動再起動なし (--no-recovery)".to_string()
+    }
+}
+
+/// サービス定義（Linux: systemd ユニット、macOS: launchd plist）をアプリケーションルートに生成する
+///
+/// システムへの登録は行わず、手順を返す（--system の手順は root で実行する）。
+/// 名前を変えれば同じアプリケーションルートから複数のインスタンスを登録できる。
+/// 手順は apply で実行することもできる（install / uninstall --apply）。
+pub fn install(
+    app_root: &Path,
+    exe: &Path,
+    options: &ServiceOptions,
+) -> Result<ServiceDefinition, String> {
+    validate_name(&options.name)?;
+    imp::install(app_root, exe, options)
+}
+
+/// install で生成した定義ファイルを削除する（無ければ None）
+pub fn uninstall(
+    app_root: &Path,
+    options: &ServiceOptions,
+) -> Result<Option<ServiceDefinition>, String> {
+    validate_name(&options.name)?;
+    imp::uninstall(app_root, options)
+}
+
+/// 登録・解除の手順をシェルで順に実行する（失敗した時点で中断）
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn apply(definition: &ServiceDefinition) -> Result<(), String> {
+    for line in &definition.instructions {
+        println!("  $ {}", line);
+        let status = std::process::Command::new("sh")
+            .args(["-c", line])
+            .status()
+            .map_err(|e| format!("コマンドを実行できません: {} ({})", line, e))?;
+        if !status.success() {
+            return Err(format!(
+                "コマンドが失敗しました (終了コード {}): {}",
+                status
+                    .code()
+                    .map_or_else(|| "なし".to_string(), |c| c.to_string()),
+                line
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn apply(_definition: &ServiceDefinition) -> Result<(), String> {
+    Err("この OS ではサービスの登録に対応していません".to_string())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::{ServiceDefinition, ServiceOptions, RESTART_DELAY_SECS};
+    use std::path::{Path, PathBuf};
+
+    fn unit_file_name(name: &str) -> String {
+        format!("{}.service", name)
+    }
+
+    fn unit_path(app_root: &Path, name: &str) -> PathBuf {
+        app_root.join(unit_file_name(name))
+    }
+
+    /// 登録先ディレクトリと systemctl コマンド（--system ならシステム全体）
+    fn scope(options: &ServiceOptions) -> (&'static str, &'static str) {
+        if options.system {
+            ("/etc/systemd/system", "systemctl")
+        } else {
+            ("~/.config/systemd/user", "systemctl --user")
+        }
+    }
+
+    /// システム全体に登録するユニットを実行するユーザー（sudo 経由なら元のユーザー）
+    fn service_user() -> Option<String> {
+        ["SUDO_USER", "USER"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|user| !user.is_empty() && user != "root")
+    }
+
+    pub fn install(
+        app_root: &Path,
+        exe: &Path,
+        options: &ServiceOptions,
+    ) -> Result<ServiceDefinition, String> {
+        let description = options
+            .display_name
+            .clone()
+            .unwrap_or_else(|| format!("{} (Supabase invoice downloader)", options.name));
+        // StartLimitIntervalSec=0 で、繰り返し落ちても再起動を諦めない
+        let (start_limit, restart) = if options.recovery {
+            (
+                "StartLimitIntervalSec=0\n".to_string(),
+                format!("Restart=on-failure\nRestartSec={}", RESTART_DELAY_SECS),
+            )
+        } else {
+            (String::new(), "Restart=no".to_string())
+        };
+        let (user, wanted_by) = if options.system {
+            (
+                service_user()
+                    .map(|user| format!("User={}\n", user))
+                    .unwrap_or_default(),
+                "multi-user.target",
+            )
+        } else {
+            (String::new(), "default.target")
+        };
+        let unit = format!(
+            "[Unit]\n\
+             Description={}\n\
+             After=network.target\n\
+             {}\
+             \n\
+             [Service]\n\
+             Type=simple\n\
+             {}\
+             ExecStart=\"{}\" {}\n\
+             WorkingDirectory={}\n\
+             {}\n\
+             # SIGTERM で実行中のスクリプトの終了を待ってから停止する\n\
+             KillSignal=SIGTERM\n\
+             TimeoutStopSec=30\n\
+             \n\
+             [Install]\n\
+             WantedBy={}\n",
+            description.replace('\n', " "),
+            start_limit,
+            user,
+            exe.display(),
+            options.run_args().join(" "),
+            app_root.display(),
+            restart,
+            wanted_by
+        );
+        let path = unit_path(app_root, &options.name);
+        super::write(&path, &unit)?;
+        let (dir, systemctl) = scope(options);
+        Ok(ServiceDefinition {
+            recovery: Some(super::describe_recovery(options)),
+            instructions: vec![
+                format!("mkdir -p {} && cp \"{}\" {}/", dir, path.display(), dir),
+                format!("{} daemon-reload", systemctl),
+                format!("{} enable --now {}", systemctl, options.name),
+            ],
+            path,
+        })
+    }
+
+    pub fn uninstall(
+        app_root: &Path,
+        options: &ServiceOptions,
+    ) -> Result<Option<ServiceDefinition>, String> {
+        let path = unit_path(app_root, &options.name);
+        if !super::remove(&path)? {
+            return Ok(None);
+        }
+        let (dir, systemctl) = scope(options);
+        Ok(Some(ServiceDefinition {
+            path,
+            recovery: None,
+            instructions: vec![
+                format!("{} disable --now {}", systemctl, options.name),
+                format!("rm {}/{}", dir, unit_file_name(&options.name)),
+                format!("{} daemon-reload", systemctl),
+            ],
+        }))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::{ServiceDefinition, ServiceOptions, RESTART_DELAY_SECS};
+    use std::path::{Path, PathBuf};
+
+    fn label(name: &str) -> String {
+        format!("com.{}", name)
+    }
+
+    fn plist_path(app_root: &Path, name: &str) -> PathBuf {
+        app_root.join(format!("{}.plist", label(name)))
+    }
+
+    /// 登録先ディレクトリ（--system なら LaunchDaemons）
+    fn agents_dir(options: &ServiceOptions) -> &'static str {
+        if options.system {
+            "/Library/LaunchDaemons"
+        } else {
+            "~/Library/LaunchAgents"
+        }
+    }
+
+    fn escape_xml(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    /// launchd には表示名が無いため display_name は使わない
+    pub fn install(
+        app_root: &Path,
+        exe: &Path,
+        options: &ServiceOptions,
+    ) -> Result<ServiceDefinition, String> {
+        let label = label(&options.name);
+        let args: String = options
+            .run_args()
+            .iter()
+            .map(|arg| format!("\n        <string>{}</string>", escape_xml(arg)))
+            .collect();
+        // ThrottleInterval は再起動の最短間隔
+        let keep_alive = if options.recovery {
+            format!(
+                "<dict>\n        <key>SuccessfulExit</key>\n        <false/>\n    </dict>\n    <key>ThrottleInterval</key>\n    <integer>{}</integer>",
+                RESTART_DELAY_SECS
+            )
+        } else {
+            "<false/>".to_string()
+        };
+        let plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{exe}</string>{args}
+    </array>
+    <key>WorkingDirectory</key>
+    <string>{root}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    {keep_alive}
+</dict>
+</plist>
+"#,
+            label = label,
+            exe = escape_xml(&exe.display().to_string()),
+            args = args,
+            keep_alive = keep_alive,
+            root = escape_xml(&app_root.display().to_string())
+        );
+        let path = plist_path(app_root, &options.name);
+        super::write(&path, &plist)?;
+        Ok(ServiceDefinition {
+            recovery: Some(super::describe_recovery(options)),
+            instructions: vec![
+                format!("cp \"{}\" {}/", path.display(), agents_dir(options)),
+                format!("launchctl load {}/{}.plist", agents_dir(options), label),
+            ],
+            path,
+        })
+    }
+
+    pub fn uninstall(
+        app_root: &Path,
+        options: &ServiceOptions,
+    ) -> Result<Option<ServiceDefinition>, String> {
+        let path = plist_path(app_root, &options.name);
+        if !super::remove(&path)? {
+            return Ok(None);
+        }
+        let label = label(&options.name);
+        let dir = agents_dir(options);
+        Ok(Some(ServiceDefinition {
+            path,
+            recovery: None,
+            instructions: vec![
+                format!("launchctl unload {}/{}.plist", dir, label),
+                format!("rm {}/{}.plist", dir, label),
+            ],
+        }))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod imp {
+    use super::{ServiceDefinition, ServiceOptions};
+    use std::path::Path;
+
+    const UNSUPPORTED: &str =
+        "この OS ではサービス定義の生成に対応していません（dencho-cli.exe をコンソールで起動してください）";
+
+    pub fn install(
+        _app_root: &Path,
+        _exe: &Path,
+        _options: &ServiceOptions,
+    ) -> Result<ServiceDefinition, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub fn uninstall(
+        _app_root: &Path,
+        _options: &ServiceOptions,
+    ) -> Result<Option<ServiceDefinition>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn write(path: &Path, contents: &str) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|e| {
+        format!(
+            "サービス定義の書き込みに失敗しました: {} ({})",
+            path.display(),
+            e
+        )
+    })
+}
+
+/// 削除した場合は true
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn remove(path: &Path) -> Result<bool, String> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(format!(
+            "サービス定義の削除に失敗しました: {} ({})",
+            path.display(),
+            e
+        )),
+    }
+}
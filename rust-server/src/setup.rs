@@ -0,0 +1,459 @@
+use crate::config::PlaywrightBrowser;
+use crate::logging::{log_to_file, Level};
+use crate::{get_application_root, output_tail, proxy, readiness, redact};
+use serde::Serialize;
+use std::process::Command;
+
+/// 環境セットアップの実行方法
+#[derive(Debug, Clone, Default)]
+pub struct SetupOptions {
+    /// ブラウザが既にあっても Playwright ブラウザをインストールし直す
+    pub force_browsers: bool,
+    /// インストールの前に、ブラウザのフォルダ（chromium-1234 など）を削除する（壊れたブラウザの復旧用）
+    pub wipe_browsers: bool,
+    /// npm install の確認・実行を省略する
+    pub skip_npm: bool,
+    /// インストールせず、node_modules と Playwright ブラウザが既にあるか確認するだけにする
+    /// （依存関係を事前に配置したネットワーク非接続の環境用、DENCHO_OFFLINE / download.offline）
+    pub offline: bool,
+    /// npm / npx の出力をそのまま端末に流す（setup サブコマンド用）
+    pub live_output: bool,
+    /// インストールする Playwright ブラウザ
+    pub browser: PlaywrightBrowser,
+    /// 必要な Node.js のメジャーバージョン（download.min_node_version）
+    pub min_node_version: u32,
+    /// npm レジストリと Playwright ブラウザのダウンロード元
+    pub mirrors: Mirrors,
+    /// npm / npx に渡すプロキシ
+    pub proxy: proxy::Proxy,
+}
+
+/// 社内ミラーなどのインストール元（未設定なら npm・Playwright のデフォルト）
+#[derive(Debug, Clone, Default)]
+pub struct Mirrors {
+    /// npm install の --registry（npx にも npm_config_registry で渡す）
+    pub npm_registry: Option<String>,
+    /// npx playwright install に PLAYWRIGHT_DOWNLOAD_HOST で渡す
+    pub playwright_download_host: Option<String>,
+}
+
+impl Mirrors {
+    /// 環境変数 > 設定ファイルの順で決め、http / https の URL か確認する
+    pub fn resolve(
+        npm_registry: Option<&str>,
+        playwright_download_host: Option<&str>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            npm_registry: mirror_url("DENCHO_NPM_REGISTRY", "download.npm_registry", npm_registry)?,
+            playwright_download_host: mirror_url(
+                "PLAYWRIGHT_DOWNLOAD_HOST",
+                "download.playwright_download_host",
+                playwright_download_host,
+            )?,
+        })
+    }
+
+    /// 起動ログ用（URL に含まれるユーザー名・パスワードは伏せる）
+    pub fn describe(&self) -> String {
+        let show = |url: &Option<String>| {
+            url.as_deref()
+                .map_or_else(|| "デフォルト".to_string(), redact::mask_url_credentials)
+        };
+        format!(
+            "npm レジストリ: {}, Playwright ダウンロード元: {}",
+            show(&self.npm_registry),
+            show(&self.playwright_download_host)
+        )
+    }
+}
+
+fn mirror_url(env: &str, key: &str, configured: Option<&str>) -> Result<Option<String>, String> {
+    let (name, value) = match std::env::var(env) {
+        Ok(value) if !value.trim().is_empty() => (env, value),
+        _ => match configured {
+            Some(value) if !value.trim().is_empty() => (key, value.to_string()),
+            _ => return Ok(None),
+        },
+    };
+    check_http_url(name, value.trim()).map(Some)
+}
+
+/// http / https の URL か確認する（エラーメッセージの URL は認証情報を伏せる）
+pub fn check_http_url(name: &str, value: &str) -> Result<String, String> {
+    let masked = redact::mask_url_credentials(value);
+    let parsed = reqwest::Url::parse(value)
+        .map_err(|e| format!("{} が URL として不正です: {} ({})", name, masked, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err(format!(
+            "{} には http:// または https:// の URL を指定してください: {}",
+            name, masked
+        ));
+    }
+    Ok(value.to_string())
+}
+
+/// 環境セットアップで実際に行った処理
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SetupReport {
+    #[serde(rename = "nodeVersion")]
+    pub node_version: String,
+    /// npm install を実行したか
+    #[serde(rename = "npmInstall")]
+    pub npm_install: bool,
+    /// Playwright ブラウザをインストールしたか
+    #[serde(rename = "browserInstall")]
+    pub browser_install: bool,
+    /// Playwright ブラウザのインストールの出力（末尾、live_output では None）
+    #[serde(rename = "browserOutput", skip_serializing_if = "Option::is_none")]
+    pub browser_output: Option<String>,
+}
+
+impl SetupReport {
+    pub fn summary(&self) -> String {
+        match (self.npm_install, self.browser_install) {
+            (false, false) => "インストール不要（既存環境を使用）".to_string(),
+            (true, false) => "npm install を実行".to_string(),
+            (false, true) => "Playwright ブラウザをインストール".to_string(),
+            (true, true) => "npm install と Playwright ブラウザのインストールを実行".to_string(),
+        }
+    }
+}
+
+/// サーバー起動時の環境セットアップの進行状況（GET /api/status の setup）
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum SetupStatus {
+    InProgress,
+    Done(SetupReport),
+    Failed { error: String },
+}
+
+/// Node.js・node_modules・Playwright ブラウザを確認し、足りなければインストールする
+///
+/// サーバー起動時（バックグラウンド）と setup サブコマンドの両方から呼ばれる。
+pub fn run(options: SetupOptions) -> Result<SetupReport, String> {
+    println!("🔍 環境チェック中...");
+
+    if options.offline && (options.force_browsers || options.wipe_browsers) {
+        return Err(
+            "オフラインモードでは Playwright ブラウザをインストールし直せません".to_string(),
+        );
+    }
+
+    let app_root = get_application_root()?;
+    let mut report = SetupReport::default();
+    // オフラインモードで見つからなかったもの（最後にまとめてエラーにする）
+    let mut missing = Vec::new();
+
+    // Node.js チェック
+    println!("  [1/3] Node.js チェック...");
+    match readiness::node_version() {
+        Some(version) => {
+            if let Err(e) = readiness::check_node_version(&version, options.min_node_version) {
+                log_to_file(Level::Error, &e);
+                return Err(e);
+            }
+            println!("    ✓ Node.js: {}", version);
+            report.node_version = version;
+        }
+        None => {
+            return Err(format!(
+                "Node.js が見つかりません（Node.js {} 以上をインストールしてください）",
+                options.min_node_version
+            ))
+        }
+    }
+
+    // node_modules チェック
+    println!("  [2/3] 依存関係チェック...");
+    if options.skip_npm {
+        println!("    - --skip-npm 指定のため省略");
+    } else if options.offline {
+        match missing_node_modules(&app_root) {
+            None => println!("    ✓ node_modules 存在確認（オフライン）"),
+            Some(item) => {
+                println!(
+                    "    ✗ node_modules がありません（オフラインのため npm install は行いません）"
+                );
+                missing.push(item);
+            }
+        }
+    } else if !readiness::node_modules_exists(&app_root) {
+        println!("    ⚙ npm install を実行中...");
+        let npm_cmd = if cfg!(target_os = "windows") {
+            "npm.cmd"
+        } else {
+            "npm"
+        };
+        let mut cmd = Command::new(npm_cmd);
+        cmd.arg("install").current_dir(&app_root);
+        if let Some(registry) = &options.mirrors.npm_registry {
+            cmd.args(["--registry", registry]);
+        }
+        options.proxy.apply(&mut cmd);
+        run_setup_command(&mut cmd, "npm install", options.live_output)?;
+        println!("    ✓ npm install 完了");
+        report.npm_install = true;
+    } else {
+        println!("    ✓ node_modules 存在確認");
+    }
+
+    // Playwright ブラウザチェック
+    println!("  [3/3] Playwright ブラウザチェック...");
+    let browsers_path = readiness::playwright_browsers_path();
+
+    if options.wipe_browsers {
+        remove_browser_dirs(&browsers_path, options.browser)?;
+    }
+    if options.offline {
+        match missing_browsers(&browsers_path, options.browser) {
+            None => println!(
+                "    ✓ Playwright ブラウザ ({}) 存在確認（オフライン）",
+                options.browser.as_str()
+            ),
+            Some(item) => {
+                println!(
+                    "    ✗ Playwright ブラウザ ({}) がありません（オフラインのためダウンロードは行いません）",
+                    options.browser.as_str()
+                );
+                missing.push(item);
+            }
+        }
+    } else if options.force_browsers
+        || !readiness::browsers_installed(&browsers_path, options.browser)
+    {
+        println!(
+            "    ⚙ Playwright ブラウザ ({}) をダウンロード中...",
+            options.browser.as_str()
+        );
+        let npx_cmd = if cfg!(target_os = "windows") {
+            "npx.cmd"
+        } else {
+            "npx"
+        };
+        let mut cmd = Command::new(npx_cmd);
+        cmd.args(["playwright", "install", options.browser.as_str()])
+            .current_dir(&app_root)
+            .env("PLAYWRIGHT_BROWSERS_PATH", &browsers_path);
+        if let Some(registry) = &options.mirrors.npm_registry {
+            cmd.env("npm_config_registry", registry);
+        }
+        if let Some(host) = &options.mirrors.playwright_download_host {
+            cmd.env("PLAYWRIGHT_DOWNLOAD_HOST", host);
+        }
+        options.proxy.apply(&mut cmd);
+        if options.force_browsers {
+            cmd.arg("--force");
+        }
+        report.browser_output = run_setup_command(
+            &mut cmd,
+            "Playwright ブラウザのインストール",
+            options.live_output,
+        )?;
+        println!("    ✓ Playwright ブラウザインストール完了");
+        report.browser_install = true;
+    } else {
+        println!(
+            "    ✓ Playwright ブラウザ ({}) 存在確認",
+            options.browser.as_str()
+        );
+    }
+
+    if !missing.is_empty() {
+        let reason = offline_missing_error(&missing);
+        log_to_file(Level::Error, &reason);
+        return Err(reason);
+    }
+
+    println!("✓ 環境チェック完了: {}\n", report.summary());
+    log_to_file(
+        Level::Info,
+        &format!(
+            "環境セットアップ: {} (node={}, npmInstall={}, browserInstall={})",
+            report.summary(),
+            report.node_version,
+            report.npm_install,
+            report.browser_install
+        ),
+    );
+    Ok(report)
+}
+
+/// node_modules が無ければ、エラーに載せる項目を返す
+fn missing_node_modules(app_root: &std::path::Path) -> Option<String> {
+    (!readiness::node_modules_exists(app_root))
+        .then(|| format!("node_modules ({})", app_root.join("node_modules").display()))
+}
+
+/// browser が browsers_path に無ければ、エラーに載せる項目を返す
+fn missing_browsers(browsers_path: &std::path::Path, browser: PlaywrightBrowser) -> Option<String> {
+    (!readiness::browsers_installed(browsers_path, browser)).then(|| {
+        format!(
+            "Playwright ブラウザ {} ({})",
+            browser.as_str(),
+            browsers_path.display()
+        )
+    })
+}
+
+/// オフラインモードで必要なものが見つからなかったときのエラー
+fn offline_missing_error(missing: &[String]) -> String {
+    format!(
+        "オフラインモードのためインストールできません。次のものを事前に配置してください: {}",
+        missing.join(", ")
+    )
+}
+
+/// 指定したブラウザのフォルダ（chromium-1234・chromium_headless_shell-1234 など）を削除する
+///
+/// PLAYWRIGHT_BROWSERS_PATH が他のアプリと共有されている場合に備え、フォルダ全体は消さない。
+fn remove_browser_dirs(
+    browsers_path: &std::path::Path,
+    browser: PlaywrightBrowser,
+) -> Result<(), String> {
+    let Ok(entries) = std::fs::read_dir(browsers_path) else {
+        return Ok(());
+    };
+    let prefixes = [
+        format!("{}-", browser.as_str()),
+        format!("{}_", browser.as_str()),
+    ];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        let path = entry.path();
+        std::fs::remove_dir_all(&path).map_err(|e| {
+            let reason = format!("{} を削除できません: {}", path.display(), e);
+            log_to_file(Level::Error, &reason);
+            reason
+        })?;
+        println!("    ✓ {} を削除", path.display());
+        log_to_file(
+            Level::Info,
+            &format!("Playwright ブラウザを削除しました: {}", path.display()),
+        );
+    }
+    Ok(())
+}
+
+/// セットアップ用のコマンドを実行し、失敗時は終了コード（と出力）をログに残す
+///
+/// 成功時は標準出力の末尾を返す。live の場合は出力を端末にそのまま流すため、
+/// ログには終了コードだけを残し、None を返す。
+fn run_setup_command(cmd: &mut Command, label: &str, live: bool) -> Result<Option<String>, String> {
+    if live {
+        let status = cmd.status().map_err(|e| {
+            let reason = format!("{} を実行できません: {}", label, e);
+            log_to_file(Level::Error, &reason);
+            reason
+        })?;
+        if status.success() {
+            log_to_file(Level::Debug, &format!("{} 完了", label));
+            return Ok(None);
+        }
+        let exit_code = exit_code_text(status.code());
+        log_to_file(
+            Level::Error,
+            &format!("{} に失敗しました (終了コード {})", label, exit_code),
+        );
+        return Err(format!(
+            "{} に失敗しました (終了コード {})",
+            label, exit_code
+        ));
+    }
+
+    let output = match cmd.output() {
+        Ok(output) => output,
+        Err(e) => {
+            let reason = format!("{} を実行できません: {}", label, e);
+            log_to_file(Level::Error, &reason);
+            return Err(reason);
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if output.status.success() {
+        let last_line = stdout.lines().rev().find(|line| !line.trim().is_empty());
+        log_to_file(
+            Level::Debug,
+            &format!("{} 完了: {}", label, last_line.unwrap_or("").trim()),
+        );
+        return Ok(Some(output_tail(stdout.trim_end())));
+    }
+
+    let exit_code = exit_code_text(output.status.code());
+    log_to_file(
+        Level::Error,
+        &format!(
+            "{} に失敗しました (終了コード {})\n[stdout]\n{}\n[stderr]\n{}",
+            label,
+            exit_code,
+            output_tail(stdout.trim_end()),
+            output_tail(stderr.trim_end())
+        ),
+    );
+    Err(format!(
+        "{} に失敗しました (終了コード {}、詳細は logs/server.log)",
+        label, exit_code
+    ))
+}
+
+fn exit_code_text(code: Option<i32>) -> String {
+    code.map_or_else(|| "なし".to_string(), |code| code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn offline_reports_every_missing_dependency() {
+        let dir = TempDir::new();
+        let browsers = dir.path().join("browsers");
+
+        let missing: Vec<String> = [
+            missing_node_modules(dir.path()),
+            missing_browsers(&browsers, PlaywrightBrowser::Chromium),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        assert_eq!(missing.len(), 2);
+        let error = offline_missing_error(&missing);
+        assert!(error.contains("node_modules"));
+        assert!(error.contains("Playwright ブラウザ chromium"));
+        assert!(error.contains(&browsers.display().to_string()));
+    }
+
+    #[test]
+    fn offline_accepts_pre_staged_dependencies() {
+        let dir = TempDir::new();
+        let browsers = dir.path().join("browsers");
+        std::fs::create_dir_all(dir.path().join("node_modules")).unwrap();
+        std::fs::create_dir_all(browsers.join("chromium-1140")).unwrap();
+
+        assert_eq!(missing_node_modules(dir.path()), None);
+        assert_eq!(
+            missing_browsers(&browsers, PlaywrightBrowser::Chromium),
+            None
+        );
+        // 別のブラウザは配置されていない
+        assert!(missing_browsers(&browsers, PlaywrightBrowser::Firefox).is_some());
+    }
+
+    #[test]
+    fn offline_refuses_to_reinstall_browsers() {
+        let options = SetupOptions {
+            offline: true,
+            force_browsers: true,
+            wipe_browsers: true,
+            ..Default::default()
+        };
+        let error = run(options).unwrap_err();
+        assert!(error.contains("オフラインモード"));
+    }
+}
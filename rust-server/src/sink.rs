@@ -0,0 +1,372 @@
+use crate::clock::Clock;
+use crate::config::{AzureConfig, OutputConfig, S3Config, SinkKind};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 接続の待ち時間の上限
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 1 ファイルのアップロードの待ち時間の上限（応答が止まった出力先で再送キューを止めない）
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// ダウンロード済みファイルの出力先
+pub enum OutputSink {
+    /// ローカルに残すだけ（何もしない）
+    Local,
+    S3(S3Sink),
+    Azure(AzureSink),
+}
+
+impl OutputSink {
+    /// 設定から出力先を構築（必須項目が欠けている場合はエラー）
+    pub fn from_config(config: &OutputConfig, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        match config.sink {
+            SinkKind::Local => Ok(Self::Local),
+            SinkKind::S3 => {
+                let s3 = config
+                    .s3
+                    .clone()
+                    .ok_or("output.sink = \"s3\" には [output.s3] の設定が必要です")?;
+                Ok(Self::S3(S3Sink::new(s3, clock)?))
+            }
+            SinkKind::Azure => {
+                let azure = config
+                    .azure
+                    .clone()
+                    .ok_or("output.sink = \"azure\" には [output.azure] の設定が必要です")?;
+                Ok(Self::Azure(AzureSink::new(azure)?))
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::S3(_) => "s3",
+            Self::Azure(_) => "azure",
+        }
+    }
+
+    /// ファイルをアップロードし、リモート URL を返す（local の場合は空）
+    ///
+    /// idempotency_key はオブジェクトのメタデータ（idempotency-key）に付ける。再送でも同じ値を渡すため、
+    /// 出力先で同じダウンロードの再アップロードかどうかを判別できる。
+    pub async fn upload(
+        &self,
+        files: &[PathBuf],
+        idempotency_key: &str,
+    ) -> Result<Vec<String>, String> {
+        let mut urls = Vec::new();
+        for file in files {
+            let url = match self {
+                Self::Local => continue,
+                Self::S3(sink) => sink.put(file, idempotency_key).await?,
+                Self::Azure(sink) => sink.put(file, idempotency_key).await?,
+            };
+            urls.push(url);
+        }
+        Ok(urls)
+    }
+}
+
+fn file_name(path: &Path) -> Result<String, String> {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.to_string())
+        .ok_or_else(|| format!("ファイル名を取得できません: {}", path.display()))
+}
+
+fn object_key(prefix: &str, name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// RFC 3986 の非予約文字以外をパーセントエンコード（'/' はそのまま）
+fn uri_encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// 拡張子からの Content-Type（不明なものは application/octet-stream、GET /api/invoices/{name} でも使う）
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "csv" => "text/csv; charset=utf-8",
+        "json" => "application/json",
+        "html" | "htm" => "text/html",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "zip" => "application/zip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn http_client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| format!("HTTP クライアントの作成に失敗しました: {}", e))
+}
+
+async fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("ファイル読み込み失敗: {} ({})", path.display(), e))
+}
+
+async fn send_put(request: reqwest::RequestBuilder, url: &str) -> Result<(), String> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("アップロード失敗: {} ({})", url, e.without_url()))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!(
+            "アップロード失敗: {} (HTTP {}) {}",
+            url,
+            status,
+            body.trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Amazon S3（および S3 互換ストレージ）への出力
+pub struct S3Sink {
+    config: S3Config,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    client: reqwest::Client,
+    /// 署名の日時（x-amz-date）
+    clock: Arc<dyn Clock>,
+}
+
+impl S3Sink {
+    fn new(config: S3Config, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        let access_key_id = std::env::var("AWS_ACCESS_KEY_ID")
+            .map_err(|_| "環境変数 AWS_ACCESS_KEY_ID が設定されていません")?;
+        let secret_access_key = std::env::var("AWS_SECRET_ACCESS_KEY")
+            .map_err(|_| "環境変数 AWS_SECRET_ACCESS_KEY が設定されていません")?;
+        let session_token = std::env::var("AWS_SESSION_TOKEN")
+            .ok()
+            .filter(|t| !t.is_empty());
+
+        Ok(Self {
+            config,
+            access_key_id,
+            secret_access_key,
+            session_token,
+            client: http_client()?,
+            clock,
+        })
+    }
+
+    /// (host, パス, URL) を返す。エンドポイント指定時はパス形式
+    fn location(&self, key: &str) -> (String, String, String) {
+        let encoded_key = uri_encode_path(key);
+        match &self.config.endpoint {
+            Some(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let host = endpoint.split("://").nth(1).unwrap_or(endpoint).to_string();
+                let path = format!("/{}/{}", self.config.bucket, encoded_key);
+                let url = format!("{}{}", endpoint, path);
+                (host, path, url)
+            }
+            None => {
+                let host = format!(
+                    "{}.s3.{}.amazonaws.com",
+                    self.config.bucket, self.config.region
+                );
+                let path = format!("/{}", encoded_key);
+                let url = format!("https://{}{}", host, path);
+                (host, path, url)
+            }
+        }
+    }
+
+    async fn put(&self, file: &Path, idempotency_key: &str) -> Result<String, String> {
+        let key = object_key(&self.config.prefix, &file_name(file)?);
+        let body = read_file(file).await?;
+        let (host, path, url) = self.location(&key);
+
+        let now = self.clock.now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        // 署名対象ヘッダー（名前順）
+        let mut headers = vec![
+            ("host", host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+            ("x-amz-meta-idempotency-key", idempotency_key.to_string()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "PUT\n{}\n\n{}\n{}\n{}",
+            path, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let k_date = hmac_sha256(
+            format!("AWS4{}", self.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        );
+        let k_region = hmac_sha256(&k_date, self.config.region.as_bytes());
+        let k_service = hmac_sha256(&k_region, b"s3");
+        let k_signing = hmac_sha256(&k_service, b"aws4_request");
+        let signature = hex::encode(hmac_sha256(&k_signing, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, scope, signed_headers, signature
+        );
+
+        let mut request = self
+            .client
+            .put(&url)
+            .header("authorization", authorization)
+            .header("content-type", content_type(file))
+            .body(body);
+        for (name, value) in headers.iter().filter(|(k, _)| *k != "host") {
+            request = request.header(*name, value);
+        }
+
+        send_put(request, &url).await?;
+        Ok(url)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC は任意長の鍵を受け付ける");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Azure Blob Storage への出力（SAS トークン認証）
+pub struct AzureSink {
+    config: AzureConfig,
+    sas_token: String,
+    client: reqwest::Client,
+}
+
+impl AzureSink {
+    fn new(config: AzureConfig) -> Result<Self, String> {
+        let sas_token = std::env::var("AZURE_STORAGE_SAS_TOKEN")
+            .map_err(|_| "環境変数 AZURE_STORAGE_SAS_TOKEN が設定されていません")?;
+
+        Ok(Self {
+            config,
+            sas_token: sas_token.trim_start_matches('?').to_string(),
+            client: http_client()?,
+        })
+    }
+
+    async fn put(&self, file: &Path, idempotency_key: &str) -> Result<String, String> {
+        let key = object_key(&self.config.prefix, &file_name(file)?);
+        let body = read_file(file).await?;
+        let url = format!(
+            "{}/{}",
+            self.config.container_url.trim_end_matches('/'),
+            uri_encode_path(&key)
+        );
+
+        let request = self
+            .client
+            .put(format!("{}?{}", url, self.sas_token))
+            .header("x-ms-blob-type", "BlockBlob")
+            // Azure のメタデータ名は識別子のみ（ハイフン不可）
+            .header("x-ms-meta-idempotency_key", idempotency_key)
+            .header("content-type", content_type(file))
+            .body(body);
+
+        // ログやレスポンスに SAS トークンを含めない
+        send_put(request, &url).await?;
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_type_follows_the_extension() {
+        assert_eq!(content_type(Path::new("invoice.pdf")), "application/pdf");
+        assert_eq!(content_type(Path::new("INVOICE.PDF")), "application/pdf");
+        assert_eq!(
+            content_type(Path::new("usage.csv")),
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("receipt.bin")),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type(Path::new("no-extension")),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn object_key_joins_the_prefix() {
+        assert_eq!(object_key("", "a.pdf"), "a.pdf");
+        assert_eq!(
+            object_key("/invoices/2024/", "a.pdf"),
+            "invoices/2024/a.pdf"
+        );
+    }
+
+    #[test]
+    fn uri_encode_path_keeps_slashes() {
+        assert_eq!(
+            uri_encode_path("dir/請求書 1.pdf"),
+            "dir/%E8%AB%8B%E6%B1%82%E6%9B%B8%201.pdf"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+/// テスト用の一時フォルダ（ドロップで削除）
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        Self::new_in(&std::env::temp_dir())
+    }
+
+    /// parent の下に作る（アプリケーションルート配下に置く必要があるテスト用）
+    pub fn new_in(parent: &Path) -> Self {
+        let path = parent.join(format!("dencho-cli-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// ファイルを作り、更新日時を modified にする
+    pub fn file(&self, name: &str, modified: std::time::SystemTime) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, name).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(modified))
+            .unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// pid のプロセスが動いているか（終了済みで回収されていないゾンビは動いていないとみなす）
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // 2 番目の項目（コマンド名）は括弧で囲まれ、空白を含みうる
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .is_some_and(|(_, rest)| !rest.trim_start().starts_with(['Z', 'X'])),
+        Err(_) if std::path::Path::new("/proc/self").exists() => false,
+        // /proc が無い（macOS）場合はシグナル 0 で確認する
+        Err(_) => std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .status()
+            .is_ok_and(|status| status.success()),
+    }
+}
+
+/// path にファイルが作られるまで待ち、内容を返す
+pub fn wait_for_file(path: &Path) -> String {
+    for _ in 0..500 {
+        if let Ok(content) = std::fs::read_to_string(path) {
+            if content.ends_with('\n') {
+                return content;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("{} が作られませんでした", path.display());
+}
+
+/// cond が true になるまで待つ（最大 5 秒）
+pub fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+    for _ in 0..500 {
+        if cond() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    false
+}
@@ -0,0 +1,162 @@
+use crate::config::ServerConfig;
+use axum_server::tls_rustls::RustlsConfig;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// 自己署名証明書の保存先（アプリケーションルートからの相対パス）
+const SELF_SIGNED_DIR: &str = "certs";
+
+/// HTTPS で待ち受ける場合の証明書と秘密鍵（PEM）
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// server.tls = true で生成する localhost 用の自己署名証明書か
+    pub self_signed: bool,
+}
+
+/// 読み込んだ TLS 設定
+pub struct LoadedTls {
+    pub config: RustlsConfig,
+    /// 証明書の SHA-256 指紋（AB:CD:... 形式、ブラウザ・OS で信頼する際の確認用）
+    pub fingerprint: String,
+}
+
+/// 証明書・秘密鍵のパスを決定
+///
+/// DENCHO_TLS_CERT / DENCHO_TLS_KEY > server.tls_cert / server.tls_key > server.tls = true
+/// （certs/ の自己署名証明書）の順で、いずれも無ければ HTTP。
+/// 相対パスはアプリケーションルートからのパスとして扱う。片方だけの指定はエラー。
+pub fn resolve(server: &ServerConfig, app_root: &Path) -> Result<Option<TlsFiles>, String> {
+    let from_env = |name: &str| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    let cert = from_env("DENCHO_TLS_CERT").or_else(|| server.tls_cert.clone());
+    let key = from_env("DENCHO_TLS_KEY").or_else(|| server.tls_key.clone());
+    match (cert, key) {
+        (Some(cert), Some(key)) => Ok(Some(TlsFiles {
+            cert: app_root.join(cert),
+            key: app_root.join(key),
+            self_signed: false,
+        })),
+        (None, None) if server.tls => {
+            let dir = app_root.join(SELF_SIGNED_DIR);
+            Ok(Some(TlsFiles {
+                cert: dir.join("localhost.crt"),
+                key: dir.join("localhost.key"),
+                self_signed: true,
+            }))
+        }
+        (None, None) => Ok(None),
+        _ => Err(
+            "TLS には証明書と秘密鍵の両方が必要です (DENCHO_TLS_CERT / DENCHO_TLS_KEY または server.tls_cert / server.tls_key)"
+                .to_string(),
+        ),
+    }
+}
+
+/// 自己署名証明書が無ければ（regenerate なら常に）生成する。生成した場合は true
+///
+/// 一度生成した証明書は再起動後も使い回す（信頼し直さなくて済むように）。
+pub fn ensure_self_signed(files: &TlsFiles, regenerate: bool) -> Result<bool, String> {
+    if !regenerate && files.cert.exists() && files.key.exists() {
+        return Ok(false);
+    }
+
+    let subject_alt_names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let generated = (|| {
+        let mut params = rcgen::CertificateParams::new(subject_alt_names)?;
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "dencho-cli (localhost)");
+        let key = rcgen::KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+        Ok::<_, rcgen::Error>((cert.pem(), key.serialize_pem()))
+    })()
+    .map_err(|e| format!("自己署名証明書の生成に失敗しました: {}", e))?;
+    let (cert_pem, key_pem) = generated;
+
+    if let Some(dir) = files.cert.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("{} を作成できません: {}", dir.display(), e))?;
+    }
+    write_file(&files.cert, &cert_pem)?;
+    write_file(&files.key, &key_pem)?;
+    restrict_permissions(&files.key)?;
+    Ok(true)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    std::fs::write(path, contents)
+        .map_err(|e| format!("{} を書き込めません: {}", path.display(), e))
+}
+
+/// 秘密鍵を所有者だけが読めるようにする
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("{} の権限を変更できません: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// DER の SHA-256 指紋（AB:CD:... 形式）
+fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// 証明書と秘密鍵を読み込み、rustls の設定を作る（壊れていればエラー）
+pub async fn load(files: &TlsFiles) -> Result<LoadedTls, String> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    // reqwest と同じ ring を使う（既に設定済みなら何もしない）
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let certs: Vec<Vec<u8>> = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.map(|cert| cert.map(|c| c.to_vec())).collect())
+        .map_err(|e| {
+            format!(
+                "TLS 証明書を読み込めません: {} ({})",
+                files.cert.display(),
+                e
+            )
+        })?;
+    if certs.is_empty() {
+        return Err(format!(
+            "TLS 証明書に CERTIFICATE が含まれていません: {}",
+            files.cert.display()
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(&files.key).map_err(|e| {
+        format!(
+            "TLS 秘密鍵を読み込めません: {} ({})",
+            files.key.display(),
+            e
+        )
+    })?;
+
+    let fingerprint = fingerprint(&certs[0]);
+    // 証明書と秘密鍵の組み合わせもここで検証される
+    let config = RustlsConfig::from_der(certs, key.secret_der().to_vec())
+        .await
+        .map_err(|e| format!("TLS 設定を作成できません: {}", e))?;
+    Ok(LoadedTls {
+        config,
+        fingerprint,
+    })
+}
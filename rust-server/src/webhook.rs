@@ -0,0 +1,412 @@
+use crate::config::WebhookConfig;
+use crate::logging::{log_to_file, Level};
+use crate::outbox::{Outbox, OutboxEntry, OutboxKind};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 通知先 URL の環境変数（[[webhooks]] に加えて 1 件登録する）
+pub const URL_ENV: &str = "DENCHO_WEBHOOK_URL";
+
+/// DENCHO_WEBHOOK_URL の署名用の secret
+pub const SECRET_ENV: &str = "DENCHO_WEBHOOK_SECRET";
+
+/// 本文の HMAC-SHA256（secret がある通知先のみ、`sha256=<16 進>`）
+pub const SIGNATURE_HEADER: &str = "X-Dencho-Signature-256";
+
+/// 通知ごとの ID（再送しても変わらないため、受信側で重複を除ける）
+pub const DELIVERY_HEADER: &str = "X-Dencho-Delivery";
+
+/// 通知 1 回あたりの待ち時間の上限
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ダウンロード終了時に送る JSON
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Payload {
+    /// download.finished / test（POST /api/webhooks/test）
+    pub event: &'static str,
+    /// success / error / timeout / cancelled
+    pub status: String,
+    pub message: String,
+    pub provider: Option<String>,
+    /// 保存したファイル名（複数あれば最初のもの、失敗時は null）
+    pub filename: Option<String>,
+    /// 保存したファイル名すべて
+    pub files: Vec<String>,
+    /// 実行前に中止された場合は null
+    pub duration_ms: Option<u64>,
+    /// GET /api/download/stream では null
+    pub job_id: Option<String>,
+    /// Slack の Incoming Webhook でそのまま表示される要約
+    pub text: String,
+}
+
+/// 通知先 1 件
+struct Target {
+    url: String,
+    secret: Option<String>,
+}
+
+impl Target {
+    /// 再送キューの destination（URL はトークンを含むため、ホストと URL のハッシュの先頭）
+    fn destination(&self) -> String {
+        let hash = hex::encode(Sha256::digest(self.url.as_bytes()));
+        format!("{}#{}", host(&self.url), &hash[..12])
+    }
+}
+
+/// 1 件の通知先への送信結果（POST /api/webhooks/test）
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    /// 通知先のホスト（URL のパスにトークンを含むサービスがあるため）
+    pub host: String,
+    pub signed: bool,
+    pub delivered: bool,
+    pub error: Option<String>,
+}
+
+/// ダウンロード終了を POST で通知する（[[webhooks]] と DENCHO_WEBHOOK_URL）
+pub struct Webhooks {
+    targets: Vec<Target>,
+    client: reqwest::Client,
+    /// 送信に失敗した通知の再送キュー
+    outbox: Arc<Outbox>,
+}
+
+impl Webhooks {
+    /// 通知先が 1 件でもあれば作る（不正な URL はエラー）
+    pub fn new(config: &[WebhookConfig], outbox: Arc<Outbox>) -> Result<Option<Self>, String> {
+        let mut targets = config
+            .iter()
+            .enumerate()
+            .map(|(index, webhook)| {
+                Ok(Target {
+                    url: check_url(&format!("webhooks[{}].url", index), &webhook.url)?,
+                    secret: webhook.secret.clone().filter(|secret| !secret.is_empty()),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if let Some(url) = std::env::var(URL_ENV).ok().filter(|url| !url.is_empty()) {
+            targets.push(Target {
+                url: check_url(URL_ENV, &url)?,
+                secret: std::env::var(SECRET_ENV)
+                    .ok()
+                    .filter(|secret| !secret.is_empty()),
+            });
+        }
+        if targets.is_empty() {
+            return Ok(None);
+        }
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| format!("HTTP クライアントの作成に失敗しました: {}", e))?;
+        Ok(Some(Self {
+            targets,
+            client,
+            outbox,
+        }))
+    }
+
+    /// ログに出す通知先（ホストのみ）
+    pub fn hosts(&self) -> Vec<String> {
+        self.targets
+            .iter()
+            .map(|target| host(&target.url))
+            .collect()
+    }
+
+    /// バックグラウンドで全通知先に送信する
+    ///
+    /// 一時的なエラー（接続エラー・5xx・429）で失敗した通知は再送キュー（data/outbox.json）に登録し、
+    /// 再送キューの間隔で送り直す。ダウンロードの結果には影響しない。
+    pub fn notify(&self, payload: Payload) {
+        // 再送時と同じバイト列（＝同じ署名）になるよう、キューに積む値から本文を作る
+        let payload = serde_json::to_value(&payload).unwrap_or_default();
+        let body = serde_json::to_vec(&payload).unwrap_or_default();
+        let delivery_id = uuid::Uuid::new_v4().to_string();
+        for target in &self.targets {
+            let request = self.request(target, &body, &delivery_id);
+            let host = host(&target.url);
+            let destination = target.destination();
+            let status = payload["status"].as_str().unwrap_or_default().to_string();
+            let (payload, delivery_id) = (payload.clone(), delivery_id.clone());
+            let outbox = self.outbox.clone();
+            tokio::spawn(crate::request_id::inherit(async move {
+                match send(request).await {
+                    Ok(()) => log_to_file(
+                        Level::Debug,
+                        &format!("Webhook 通知を送信しました: {} ({})", host, status),
+                    ),
+                    Err((e, true)) => {
+                        let queued = outbox.enqueue(
+                            OutboxKind::Webhook,
+                            &destination,
+                            payload,
+                            &delivery_id,
+                            &e,
+                        );
+                        match queued {
+                            Ok(id) => log_to_file(
+                                Level::Warn,
+                                &format!(
+                                    "Webhook 通知に失敗しました: {} ({})、再送キューに登録しました: {}",
+                                    host, e, id
+                                ),
+                            ),
+                            Err(queue_err) => log_to_file(
+                                Level::Error,
+                                &format!(
+                                    "Webhook 通知に失敗しました: {} ({})、再送キューに登録できません: {}",
+                                    host, e, queue_err
+                                ),
+                            ),
+                        }
+                    }
+                    Err((e, false)) => log_to_file(
+                        Level::Warn,
+                        &format!("Webhook 通知に失敗しました: {} ({})", host, e),
+                    ),
+                }
+            }));
+        }
+    }
+
+    /// 再送キューの通知を送り直す（最初と同じ X-Dencho-Delivery を付ける）
+    pub async fn redeliver(&self, entry: &OutboxEntry) -> Result<(), String> {
+        let target = self
+            .targets
+            .iter()
+            .find(|target| target.destination() == entry.destination)
+            .ok_or_else(|| format!("通知先が設定にありません: {}", entry.destination))?;
+        let body = serde_json::to_vec(&entry.payload)
+            .map_err(|e| format!("再送データが不正です: {}", e))?;
+        send(self.request(target, &body, &entry.idempotency_key))
+            .await
+            .map_err(|(e, _)| e)
+    }
+
+    /// 全通知先に 1 回ずつ送信し、結果を返す（再送しない）
+    pub async fn send_test(&self, payload: Payload) -> Vec<Delivery> {
+        let body = serde_json::to_vec(&payload).unwrap_or_default();
+        let delivery_id = uuid::Uuid::new_v4().to_string();
+        let mut deliveries = Vec::new();
+        for target in &self.targets {
+            let result = send(self.request(target, &body, &delivery_id)).await;
+            let host = host(&target.url);
+            match &result {
+                Ok(()) => log_to_file(
+                    Level::Info,
+                    &format!("Webhook のテスト通知を送信しました: {}", host),
+                ),
+                Err((e, _)) => log_to_file(
+                    Level::Warn,
+                    &format!("Webhook のテスト通知に失敗しました: {} ({})", host, e),
+                ),
+            }
+            deliveries.push(Delivery {
+                host,
+                signed: target.secret.is_some(),
+                delivered: result.is_ok(),
+                error: result.err().map(|(e, _)| e),
+            });
+        }
+        deliveries
+    }
+
+    fn request(&self, target: &Target, body: &[u8], delivery_id: &str) -> reqwest::RequestBuilder {
+        let mut request = self
+            .client
+            .post(&target.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DELIVERY_HEADER, delivery_id)
+            .body(body.to_vec());
+        if let Some(secret) = &target.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, body));
+        }
+        request
+    }
+}
+
+/// 送信する（エラー時は理由と、再送して変わりうるか）
+async fn send(request: reqwest::RequestBuilder) -> Result<(), (String, bool)> {
+    match request.send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => {
+            let status = response.status();
+            let retryable = status.is_server_error() || status.as_u16() == 429;
+            Err((format!("HTTP {}", status), retryable))
+        }
+        Err(e) => Err((e.without_url().to_string(), true)),
+    }
+}
+
+/// `sha256=<HMAC-SHA256(secret, 本文) の 16 進>`（GitHub の Webhook と同じ形式）
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC は任意長の鍵を受け付ける");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// name はエラーメッセージに出す設定項目名
+pub fn check_url(name: &str, url: &str) -> Result<String, String> {
+    let parsed =
+        reqwest::Url::parse(url).map_err(|e| format!("{} が URL として不正です: {}", name, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!(
+            "{} には http:// または https:// の URL を指定してください",
+            name
+        ));
+    }
+    Ok(url.to_string())
+}
+
+/// ログに出す通知先（URL のパスにトークンを含むサービスがあるのでホストのみ）
+pub fn host(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::outbox::RetryPolicy;
+    use crate::test_util::TempDir;
+    use axum::http::{HeaderMap, StatusCode};
+    use std::sync::Mutex;
+
+    /// 受け取ったヘッダーを記録し、status を返す受信側
+    async fn receiver(status: StatusCode) -> (String, Arc<Mutex<Vec<HeaderMap>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let app = axum::Router::new().route(
+            "/hook/secret-token",
+            axum::routing::post(move |headers: HeaderMap| {
+                log.lock().unwrap().push(headers);
+                async move { status }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/hook/secret-token",
+            listener.local_addr().unwrap()
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, received)
+    }
+
+    fn outbox(dir: &TempDir) -> Arc<Outbox> {
+        let policy = RetryPolicy {
+            base_delay: chrono::Duration::seconds(60),
+            max_delay: chrono::Duration::seconds(3600),
+            max_age: chrono::Duration::hours(72),
+            discard_after: chrono::Duration::hours(168),
+        };
+        let clock = TestClock::new("2024-01-31T00:00:00Z".parse().unwrap());
+        Arc::new(Outbox::load(dir.path().join("outbox.json"), policy, clock).unwrap())
+    }
+
+    fn payload() -> Payload {
+        Payload {
+            event: "download.finished",
+            status: "success".to_string(),
+            message: "done".to_string(),
+            provider: Some("supabase".to_string()),
+            filename: None,
+            files: Vec::new(),
+            duration_ms: Some(1),
+            job_id: None,
+            text: "done".to_string(),
+        }
+    }
+
+    #[test]
+    fn signature_is_hex_hmac_sha256() {
+        // RFC 4231 のテストケース 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn destination_does_not_expose_the_url_path() {
+        let target = Target {
+            url: "https://hooks.slack.com/services/T000/B000/XXXX".to_string(),
+            secret: None,
+        };
+        let destination = target.destination();
+        assert!(destination.starts_with("hooks.slack.com#"));
+        assert!(!destination.contains("XXXX"));
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_queued_and_redelivered_with_the_same_id() {
+        let dir = TempDir::new();
+        let outbox = outbox(&dir);
+        let (url, received) = receiver(StatusCode::SERVICE_UNAVAILABLE).await;
+        let config = [WebhookConfig {
+            url: url.clone(),
+            secret: Some("s3cret".to_string()),
+        }];
+        let webhooks = Webhooks::new(&config, outbox.clone()).unwrap().unwrap();
+
+        webhooks.notify(payload());
+        let entry = loop {
+            if let Some(entry) = outbox.list().pop() {
+                break entry;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(entry.kind, OutboxKind::Webhook);
+        assert!(!entry.destination.contains("secret-token"));
+        assert!(!serde_json::to_string(&entry).unwrap().contains("s3cret"));
+
+        let first = received.lock().unwrap()[0].clone();
+        assert_eq!(first[DELIVERY_HEADER], entry.idempotency_key.as_str());
+        assert!(first[SIGNATURE_HEADER]
+            .to_str()
+            .unwrap()
+            .starts_with("sha256="));
+
+        // 受信側が復旧していなければ再送も失敗し、同じ ID で送られる
+        assert!(webhooks.redeliver(&entry).await.is_err());
+        let retried = received.lock().unwrap()[1].clone();
+        assert_eq!(retried[DELIVERY_HEADER], entry.idempotency_key.as_str());
+        assert_eq!(retried[SIGNATURE_HEADER], first[SIGNATURE_HEADER]);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_queued() {
+        let dir = TempDir::new();
+        let outbox = outbox(&dir);
+        let (url, received) = receiver(StatusCode::NOT_FOUND).await;
+        let config = [WebhookConfig { url, secret: None }];
+        let webhooks = Webhooks::new(&config, outbox.clone()).unwrap().unwrap();
+
+        webhooks.notify(payload());
+        while received.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(outbox.list().is_empty());
+    }
+
+    #[test]
+    fn non_http_urls_are_rejected() {
+        let dir = TempDir::new();
+        let config = [WebhookConfig {
+            url: "ftp://example.com/hook".to_string(),
+            secret: None,
+        }];
+        assert!(Webhooks::new(&config, outbox(&dir)).is_err());
+    }
+}
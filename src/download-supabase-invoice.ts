@@ -1,4 +1,4 @@
-import { chromium } from '@playwright/test';
+import { chromium, firefox, webkit, errors, type Page } from '@playwright/test';
 import path from 'path';
 import fs from 'fs';
 import { fileURLToPath } from 'url';
@@ -7,13 +7,96 @@ const __filename = fileURLToPath(import.meta.url);
 const __dirname = path.dirname(__filename);
 
 const AUTH_STATE_PATH = path.join(process.cwd(), '.auth', 'supabase-state.json');
-const DOWNLOAD_DIR = path.join(process.cwd(), 'downloads', 'invoice');
+// 保存先（Rustサーバーがリクエストの outputDir を検証して作成済みのフォルダを渡す）
+const DOWNLOAD_DIR = process.env.DENCHO_OUTPUT_DIR || path.join(process.cwd(), 'downloads', 'invoice');
 const LOG_DIR = path.join(process.cwd(), 'logs');
 const LOG_FILE = path.join(LOG_DIR, 'supabase-download.log');
 
-// GitHub認証情報を環境変数から取得
-const GITHUB_USERNAME = process.env.GITHUB_USERNAME || '';
-const GITHUB_PASSWORD = process.env.GITHUB_PASSWORD || '';
+// ログインが完了しなかった場合の終了コード（Rustサーバーが LoginFailed として扱う）
+const LOGIN_FAILED_EXIT_CODE = 2;
+
+// タイムアウトやネットワークの一時的なエラーで失敗した場合の終了コード（Rustサーバーが再試行する）
+const TRANSIENT_EXIT_CODE = 3;
+
+class LoginError extends Error {}
+
+function isTransientError(error: unknown): boolean {
+  if (error instanceof errors.TimeoutError) {
+    return true;
+  }
+  const message = error instanceof Error ? error.message : String(error);
+  return /net::ERR_|ECONNRESET|ETIMEDOUT|EAI_AGAIN/.test(message);
+}
+
+// ブラウザのプロキシ（Rustサーバーが DENCHO_PROXY で渡す。ブラウザは HTTPS_PROXY 等を読まないため）
+function proxyOptions(): { server: string; bypass?: string; username?: string; password?: string } | undefined {
+  const value = process.env.DENCHO_PROXY;
+  if (!value) {
+    return undefined;
+  }
+  const url = new URL(value);
+  return {
+    server: `${url.protocol}//${url.host}`,
+    bypass: process.env.NO_PROXY || undefined,
+    username: url.username ? decodeURIComponent(url.username) : undefined,
+    password: url.password ? decodeURIComponent(url.password) : undefined,
+  };
+}
+
+// 認証後に組織ページへ戻るのを待つ（戻らなければログイン失敗）
+async function waitForOrganizations(page: Page) {
+  try {
+    // waitForFunction は現在のURLもチェックするため、既に到達済みでも正常に完了する
+    await page.waitForFunction(
+      () => window.location.pathname.includes('/organizations'),
+      { timeout: 300000 }
+    );
+  } catch (error) {
+    throw new LoginError(`ログインが完了しませんでした: ${error instanceof Error ? error.message : error}`);
+  }
+}
+
+// GitHub認証情報を標準入力の 1 行目（JSON）から取得
+// 環境変数はブラウザの子プロセスにも継承されるため、Rustサーバーは標準入力で渡す
+// （dencho.toml の download.legacy_env = true の場合のみ環境変数 GITHUB_USERNAME / GITHUB_PASSWORD）
+async function readCredentials(): Promise<{ username: string; password: string }> {
+  let username = process.env.GITHUB_USERNAME || '';
+  let password = process.env.GITHUB_PASSWORD || '';
+
+  // ターミナルから直接実行した場合は入力を待たない
+  if (!process.stdin.isTTY) {
+    const chunks: Buffer[] = [];
+    for await (const chunk of process.stdin) {
+      chunks.push(chunk as Buffer);
+    }
+    const line = Buffer.concat(chunks).toString('utf8').split('\n')[0].trim();
+    if (line) {
+      const input = JSON.parse(line) as { githubUsername?: string; githubPassword?: string };
+      username = input.githubUsername || username;
+      password = input.githubPassword || password;
+    }
+  }
+
+  return { username, password };
+}
+
+const { username: GITHUB_USERNAME, password: GITHUB_PASSWORD } = await readCredentials();
+
+// 使用するブラウザ（Rustサーバーが chromium / firefox / webkit のいずれかを渡す）
+const BROWSER_TYPES = { chromium, firefox, webkit };
+const BROWSER_NAME = (process.env.PLAYWRIGHT_BROWSER || 'chromium') as keyof typeof BROWSER_TYPES;
+
+// ブラウザのロケール（Rustサーバーが許可リストで検証済みの値を渡す）
+const LOCALE = process.env.DENCHO_LOCALE || 'ja-JP';
+
+function buildAcceptLanguage(locale: string): string {
+  const lang = locale.split('-')[0];
+  const tags = [locale, `${lang};q=0.9`];
+  if (lang !== 'en') {
+    tags.push('en-US;q=0.8', 'en;q=0.7');
+  }
+  return tags.join(',');
+}
 
 // ログ関数
 function log(message: string) {
@@ -43,7 +126,7 @@ function logError(message: string, error?: unknown) {
   fs.appendFileSync(LOG_FILE, logMessage + '\n');
 }
 
-async function downloadSupabaseInvoices() {
+async function downloadSupabaseInvoices(): Promise<string[]> {
   // ダウンロードディレクトリ作成
   if (!fs.existsSync(DOWNLOAD_DIR)) {
     fs.mkdirSync(DOWNLOAD_DIR, { recursive: true });
@@ -56,17 +139,29 @@ async function downloadSupabaseInvoices() {
   const headless = process.env.HEADLESS === 'true';
   log(`ブラウザモード: ${headless ? 'headless' : 'headed'}`);
 
-  const browser = await chromium.launch({
-    headless: headless
+  const browserType = BROWSER_TYPES[BROWSER_NAME];
+  if (!browserType) {
+    throw new Error(`未対応のブラウザです: ${BROWSER_NAME}`);
+  }
+  log(`ブラウザ: ${BROWSER_NAME}`);
+
+  const proxy = proxyOptions();
+  if (proxy) {
+    log(`プロキシ: ${proxy.server}`);
+  }
+  const browser = await browserType.launch({
+    headless: headless,
+    proxy
   });
 
+  log(`ロケール: ${LOCALE}`);
   const context = hasAuth
-    ? await browser.newContext({ storageState: AUTH_STATE_PATH })
-    : await browser.newContext();
+    ? await browser.newContext({ storageState: AUTH_STATE_PATH, locale: LOCALE })
+    : await browser.newContext({ locale: LOCALE });
 
   // ダウンロード設定
   await context.setExtraHTTPHeaders({
-    'Accept-Language': 'ja-JP,ja;q=0.9,en-US;q=0.8,en;q=0.7'
+    'Accept-Language': buildAcceptLanguage(LOCALE)
   });
 
   const page = await context.newPage();
@@ -128,11 +223,7 @@ async function downloadSupabaseInvoices() {
 
         // 組織ページに戻るまで待機
         log('認証完了を待機中...');
-        // waitForFunction は現在のURLもチェックするため、既に到達済みでも正常に完了する
-        await page.waitForFunction(
-          () => window.location.pathname.includes('/organizations'),
-          { timeout: 300000 }
-        );
+        await waitForOrganizations(page);
         log('組織ページに戻りました');
       }
       // passkey/2FA画面に遷移した場合、またはその他
@@ -141,14 +232,7 @@ async function downloadSupabaseInvoices() {
         log('organizationsページへのリダイレクトを待機中...');
 
         // organizationsページに到達するまで待機（最大5分）
-        // waitForFunction は現在のURLもチェックするため、既に到達済みでも正常に完了する
-        await page.waitForFunction(
-          () => {
-            const url = window.location.pathname;
-            return url.includes('/organizations');
-          },
-          { timeout: 300000 }
-        );
+        await waitForOrganizations(page);
         log('組織ページに到達しました');
       }
 
@@ -200,6 +284,7 @@ async function downloadSupabaseInvoices() {
 
     await download.saveAs(filepath);
     log(`✓ ダウンロード完了: ${filepath}`);
+    return [filepath];
 
   } catch (error) {
     logError('エラーが発生しました:', error);
@@ -211,11 +296,16 @@ async function downloadSupabaseInvoices() {
 
 // 実行
 downloadSupabaseInvoices()
-  .then(() => {
+  .then((files) => {
     log('処理が完了しました');
+    // 最後の行に保存したファイルを JSON で出力する（Rustサーバーがレスポンスの files にする）
+    console.log(JSON.stringify({ files }));
     process.exit(0);
   })
   .catch((error) => {
     logError('処理に失敗しました:', error);
-    process.exit(1);
+    if (error instanceof LoginError) {
+      process.exit(LOGIN_FAILED_EXIT_CODE);
+    }
+    process.exit(isTransientError(error) ? TRANSIENT_EXIT_CODE : 1);
   });